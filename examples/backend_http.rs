@@ -4,30 +4,10 @@
 //! Start blink-store: cargo run -- serve --tcp 127.0.0.1:8765
 //! Run this:          cargo run --example backend_http -- --store 127.0.0.1:8765 --port 8080
 
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use blink_store::BlinkClient;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
-/// One TCP connection per request to Blink Store.
-async fn store_request(addr: &str, cmd: &str, key: &str, value: &[u8]) -> std::io::Result<String> {
-    let mut stream = TcpStream::connect(addr).await?;
-    let (reader, mut writer) = stream.split();
-
-    let req = if value.is_empty() {
-        format!("{} {}\n", cmd, key)
-    } else {
-        let v = String::from_utf8_lossy(value);
-        format!("{} {} {}\n", cmd, key, v)
-    };
-    writer.write_all(req.as_bytes()).await?;
-    writer.flush().await?;
-
-    let mut line = String::new();
-    let mut buf_reader = tokio::io::BufReader::new(reader);
-    buf_reader.read_line(&mut line).await?;
-    Ok(line)
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let args: Vec<String> = std::env::args().collect();
@@ -44,22 +24,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .and_then(|s| s.parse().ok())
         .unwrap_or(8080);
 
+    // Pooled connections to Blink Store, shared by all HTTP requests.
+    let store = BlinkClient::connect(store_addr.clone()).await?;
+
     let listen = format!("0.0.0.0:{}", port);
     let listener = TcpListener::bind(&listen).await?;
     eprintln!("HTTP backend on {} (store at {})", listen, store_addr);
 
     loop {
         let (stream, _) = listener.accept().await?;
-        let store_addr = store_addr.clone();
+        let store = store.clone();
         tokio::spawn(async move {
-            let _ = handle_http(stream, &store_addr).await;
+            let _ = handle_http(stream, &store).await;
         });
     }
 }
 
 async fn handle_http(
     mut stream: TcpStream,
-    store_addr: &str,
+    store: &BlinkClient,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let (reader, mut writer) = stream.split();
     let mut buf_reader = tokio::io::BufReader::new(reader);
@@ -80,19 +63,10 @@ async fn handle_http(
     }
 
     if method == "GET" {
-        let resp = store_request(store_addr, "GET", key, &[]).await?;
-        let resp = resp.trim_end();
-        if resp == "NOT_FOUND" {
-            send_response(&mut writer, 404, b"Not Found").await?;
-            return Ok(());
-        }
-        if let Some(stripped) = resp.strip_prefix("VALUE ") {
-            match BASE64.decode(stripped.trim().as_bytes()) {
-                Ok(v) => send_response(&mut writer, 200, &v).await?,
-                Err(_) => send_response(&mut writer, 502, b"Bad Gateway").await?,
-            }
-        } else {
-            send_response(&mut writer, 502, b"Bad Gateway").await?;
+        match store.get(key).await {
+            Ok(Some(v)) => send_response(&mut writer, 200, &v).await?,
+            Ok(None) => send_response(&mut writer, 404, b"Not Found").await?,
+            Err(_) => send_response(&mut writer, 502, b"Bad Gateway").await?,
         }
     } else if method == "POST" {
         let mut content_length: Option<usize> = None;
@@ -124,11 +98,10 @@ async fn handle_http(
                 body.extend_from_slice(&buf[..n]);
             }
         }
-        let resp = store_request(store_addr, "SET", key, &body).await?;
-        if resp.trim_end().starts_with("OK") {
-            send_response(&mut writer, 204, b"").await?;
-        } else {
-            send_response(&mut writer, 502, b"Bad Gateway").await?;
+        let value = String::from_utf8_lossy(&body);
+        match store.set(key, value.as_bytes()).await {
+            Ok(()) => send_response(&mut writer, 204, b"").await?,
+            Err(_) => send_response(&mut writer, 502, b"Bad Gateway").await?,
         }
     } else {
         send_response(&mut writer, 405, b"Method Not Allowed").await?;
@@ -0,0 +1,334 @@
+//! Access control lists: per-user command and key-pattern permissions.
+//!
+//! ACL files are line-based, one user per line (`#` starts a comment):
+//!
+//! ```text
+//! user default on nopass +@all ~*
+//! user team-a on #<sha256-hex> +get +set +delete ~a:*
+//! user monitor on #<sha256-hex> +usage +info
+//! ```
+//!
+//! - `on` / `off` enables or disables the user.
+//! - `nopass` accepts any password; `#<hex>` is the SHA-256 of the password,
+//!   unsalted, so pick passwords that are long and random.
+//! - `+<command>` allows a command; `+@all` allows every command.
+//! - `~<pattern>` allows keys matching a glob pattern; `allkeys` is `~*`.
+
+use crate::error::BlinkError;
+use crate::glob::glob_match;
+use crate::protocol::Command;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+/// User that connections start as when the ACL defines it with `nopass`.
+pub const DEFAULT_USER: &str = "default";
+
+/// One ACL user and its permissions.
+#[derive(Debug, Clone)]
+pub struct AclUser {
+    name: String,
+    enabled: bool,
+    password_sha256: Option<[u8; 32]>,
+    all_commands: bool,
+    commands: Vec<String>,
+    key_patterns: Vec<String>,
+}
+
+impl AclUser {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// True if the user has no password (`nopass`).
+    pub fn is_passwordless(&self) -> bool {
+        self.password_sha256.is_none()
+    }
+
+    /// Compares digests in constant time, so response times do not reveal
+    /// how much of a guess matched.
+    pub fn verify_password(&self, password: &str) -> bool {
+        match &self.password_sha256 {
+            None => true,
+            Some(expected) => Sha256::digest(password.as_bytes()).ct_eq(expected).into(),
+        }
+    }
+
+    pub fn can_run(&self, cmd: Command) -> bool {
+        self.all_commands || self.commands.iter().any(|c| c == cmd.name())
+    }
+
+    pub fn can_access(&self, key: &str) -> bool {
+        self.key_patterns.iter().any(|p| glob_match(p, key))
+    }
+
+    /// Checks that the user may run `cmd`, and touch `key` when it is non-empty.
+    pub fn check(&self, cmd: Command, key: &str) -> Result<(), BlinkError> {
+        if !self.can_run(cmd) {
+            return Err(BlinkError::NoPerm(format!(
+                "user '{}' cannot run '{}'",
+                self.name,
+                cmd.name()
+            )));
+        }
+        if !key.is_empty() && !self.can_access(key) {
+            return Err(BlinkError::NoPerm(format!(
+                "user '{}' cannot access key '{}'",
+                self.name, key
+            )));
+        }
+        Ok(())
+    }
+
+    /// Renders the user back into ACL file syntax (as shown by `ACL LIST`).
+    pub fn describe(&self) -> String {
+        let mut out = format!(
+            "user {} {}",
+            self.name,
+            if self.enabled { "on" } else { "off" }
+        );
+        match &self.password_sha256 {
+            None => out.push_str(" nopass"),
+            Some(hash) => {
+                out.push_str(" #");
+                for b in hash {
+                    out.push_str(&format!("{:02x}", b));
+                }
+            }
+        }
+        if self.all_commands {
+            out.push_str(" +@all");
+        }
+        for c in &self.commands {
+            out.push_str(" +");
+            out.push_str(c);
+        }
+        for p in &self.key_patterns {
+            out.push_str(" ~");
+            out.push_str(p);
+        }
+        out
+    }
+}
+
+/// Parses one `user ...` rule line.
+fn parse_user(line: &str) -> Result<AclUser, BlinkError> {
+    let mut tokens = line.split_whitespace();
+    match tokens.next() {
+        Some(t) if t.eq_ignore_ascii_case("user") => {}
+        _ => return Err(BlinkError::Config(format!("expected 'user': {}", line))),
+    }
+    let name = tokens
+        .next()
+        .ok_or_else(|| BlinkError::Config(format!("missing user name: {}", line)))?;
+
+    let mut user = AclUser {
+        name: name.to_owned(),
+        enabled: false,
+        password_sha256: None,
+        all_commands: false,
+        commands: Vec::new(),
+        key_patterns: Vec::new(),
+    };
+    let mut has_password_rule = false;
+
+    for token in tokens {
+        if token.eq_ignore_ascii_case("on") {
+            user.enabled = true;
+        } else if token.eq_ignore_ascii_case("off") {
+            user.enabled = false;
+        } else if token.eq_ignore_ascii_case("nopass") {
+            user.password_sha256 = None;
+            has_password_rule = true;
+        } else if token.eq_ignore_ascii_case("allkeys") {
+            user.key_patterns.push("*".to_owned());
+        } else if let Some(hex) = token.strip_prefix('#') {
+            user.password_sha256 = Some(parse_sha256_hex(hex)?);
+            has_password_rule = true;
+        } else if let Some(cmd) = token.strip_prefix('+') {
+            if cmd.eq_ignore_ascii_case("@all") {
+                user.all_commands = true;
+            } else {
+                user.commands.push(cmd.to_ascii_lowercase());
+            }
+        } else if let Some(pattern) = token.strip_prefix('~') {
+            user.key_patterns.push(pattern.to_owned());
+        } else {
+            return Err(BlinkError::Config(format!(
+                "unknown ACL rule '{}' for user '{}'",
+                token, name
+            )));
+        }
+    }
+
+    if !has_password_rule {
+        return Err(BlinkError::Config(format!(
+            "user '{}' needs 'nopass' or '#<sha256>'",
+            name
+        )));
+    }
+    Ok(user)
+}
+
+fn parse_sha256_hex(hex: &str) -> Result<[u8; 32], BlinkError> {
+    let invalid = || BlinkError::Config(format!("invalid SHA-256 hex: {}", hex));
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(out)
+}
+
+/// Parses a whole ACL file into users keyed by name.
+pub fn parse_rules(text: &str) -> Result<HashMap<String, Arc<AclUser>>, BlinkError> {
+    let mut users = HashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let user = parse_user(line)?;
+        users.insert(user.name.clone(), Arc::new(user));
+    }
+    Ok(users)
+}
+
+/// Shared ACL state; reloadable at runtime from its source file.
+pub struct Acl {
+    path: Option<PathBuf>,
+    users: RwLock<HashMap<String, Arc<AclUser>>>,
+}
+
+impl Acl {
+    /// Builds an ACL from rule text (no backing file, so `reload` is a no-op).
+    pub fn from_rules(text: &str) -> Result<Self, BlinkError> {
+        Ok(Self {
+            path: None,
+            users: RwLock::new(parse_rules(text)?),
+        })
+    }
+
+    /// Loads an ACL file; `reload` re-reads the same path.
+    pub fn load(path: &Path) -> Result<Self, BlinkError> {
+        let acl = Self {
+            path: Some(path.to_owned()),
+            users: RwLock::new(HashMap::new()),
+        };
+        acl.reload()?;
+        Ok(acl)
+    }
+
+    /// Re-reads the backing file and atomically swaps in the new users.
+    /// On error the previous users stay in effect. Returns the user count.
+    pub fn reload(&self) -> Result<usize, BlinkError> {
+        let Some(path) = &self.path else {
+            return Ok(self.read().len());
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| BlinkError::Config(format!("read ACL file {:?}: {}", path, e)))?;
+        let users = parse_rules(&text)?;
+        let count = users.len();
+        *self.users.write().unwrap_or_else(|e| e.into_inner()) = users;
+        info!(action = "acl_reload", path = ?path, users = count);
+        Ok(count)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, Arc<AclUser>>> {
+        self.users.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Looks up an enabled user by name.
+    pub fn user(&self, name: &str) -> Option<Arc<AclUser>> {
+        self.read().get(name).filter(|u| u.enabled).cloned()
+    }
+
+    /// The user new connections start as: `default`, if enabled and passwordless.
+    pub fn default_user(&self) -> Option<Arc<AclUser>> {
+        self.user(DEFAULT_USER).filter(|u| u.is_passwordless())
+    }
+
+    pub fn authenticate(&self, name: &str, password: &str) -> Result<Arc<AclUser>, BlinkError> {
+        match self.user(name) {
+            Some(user) if user.verify_password(password) => Ok(user),
+            _ => Err(BlinkError::AuthFailed),
+        }
+    }
+
+    /// All users in ACL file syntax, sorted by name.
+    pub fn list(&self) -> Vec<String> {
+        let users = self.read();
+        let mut names: Vec<&String> = users.keys().collect();
+        names.sort();
+        names.into_iter().map(|n| users[n].describe()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sha256("secret")
+    const SECRET_HASH: &str = "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+
+    fn acl() -> Acl {
+        Acl::from_rules(&format!(
+            "# shared store\n\
+             user default on nopass +@all ~*\n\
+             user team-a on #{h} +get +set +delete ~a:*\n\
+             user monitor on #{h} +usage +info\n\
+             user retired off nopass +@all ~*\n",
+            h = SECRET_HASH
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn authenticate_checks_password_and_state() {
+        let acl = acl();
+        assert!(acl.authenticate("team-a", "secret").is_ok());
+        assert!(acl.authenticate("team-a", "wrong").is_err());
+        assert!(acl.authenticate("retired", "").is_err());
+        assert!(acl.authenticate("nobody", "").is_err());
+        assert_eq!(acl.default_user().unwrap().name(), "default");
+    }
+
+    #[test]
+    fn command_and_key_permissions() {
+        let acl = acl();
+        let a = acl.user("team-a").unwrap();
+        assert!(a.check(Command::Get, "a:1").is_ok());
+        assert!(a.check(Command::Get, "b:1").is_err());
+        assert!(a.check(Command::Usage, "").is_err());
+
+        let monitor = acl.user("monitor").unwrap();
+        assert!(monitor.check(Command::Usage, "").is_ok());
+        assert!(monitor.check(Command::Info, "").is_ok());
+        assert!(monitor.check(Command::Get, "a:1").is_err());
+    }
+
+    #[test]
+    fn describe_round_trips() {
+        let acl = acl();
+        let reparsed = parse_rules(&acl.list().join("\n")).unwrap();
+        assert_eq!(reparsed.len(), 4);
+        assert!(reparsed["team-a"].verify_password("secret"));
+        assert!(reparsed["team-a"].can_access("a:x"));
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        assert!(parse_rules("user x on +get").is_err());
+        assert!(parse_rules("user x on nopass bogus").is_err());
+        assert!(parse_rules("user x on #abc").is_err());
+    }
+}
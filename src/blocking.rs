@@ -0,0 +1,269 @@
+//! Blocking client for callers without an async runtime.
+//!
+//! One connection over `std::net::TcpStream` or (on Unix) `UnixStream`,
+//! speaking the protocol through the same `encode_request` /
+//! `parse_response` functions the server and the async client use.
+//!
+//! ```no_run
+//! # fn demo() -> Result<(), blink_store::BlinkError> {
+//! let mut client = blink_store::blocking::BlockingClient::connect_tcp("127.0.0.1:8765")?;
+//! client.set("user", b"alice")?;
+//! assert_eq!(client.get("user")?.as_deref(), Some(&b"alice"[..]));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{setex_args, unexpected, value_text};
+use crate::engine::Expiry;
+use crate::error::BlinkError;
+use crate::protocol::{encode_request, parse_response, Command, Response};
+use bytes::Bytes;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> std::io::Result<Stream> {
+        match self {
+            Stream::Tcp(s) => s.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.try_clone().map(Stream::Unix),
+        }
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+            #[cfg(unix)]
+            Stream::Unix(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+fn io_err(context: &str) -> impl Fn(std::io::Error) -> BlinkError + '_ {
+    move |e| {
+        if matches!(
+            e.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ) {
+            BlinkError::Timeout(context.to_owned())
+        } else {
+            BlinkError::Internal(format!("{}: {}", context, e))
+        }
+    }
+}
+
+/// Single-connection blocking client.
+pub struct BlockingClient {
+    reader: BufReader<Stream>,
+    writer: Stream,
+    line: String,
+}
+
+impl BlockingClient {
+    pub fn connect_tcp(addr: &str) -> Result<Self, BlinkError> {
+        let stream = TcpStream::connect(addr).map_err(io_err("connect"))?;
+        let _ = stream.set_nodelay(true);
+        Self::from_stream(Stream::Tcp(stream))
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix(path: &std::path::Path) -> Result<Self, BlinkError> {
+        let stream = std::os::unix::net::UnixStream::connect(path).map_err(io_err("connect"))?;
+        Self::from_stream(Stream::Unix(stream))
+    }
+
+    fn from_stream(stream: Stream) -> Result<Self, BlinkError> {
+        let reader = stream.try_clone().map_err(io_err("clone stream"))?;
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer: stream,
+            line: String::new(),
+        })
+    }
+
+    /// Read/write timeout for each request; `None` blocks indefinitely.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), BlinkError> {
+        self.writer.set_timeout(timeout).map_err(io_err("set timeout"))
+    }
+
+    fn read_response(&mut self) -> Result<Response, BlinkError> {
+        self.line.clear();
+        let n = self
+            .reader
+            .read_line(&mut self.line)
+            .map_err(io_err("read"))?;
+        if n == 0 {
+            return Err(BlinkError::Internal("connection closed by server".into()));
+        }
+        parse_response(&self.line)
+    }
+
+    /// Sends one request and returns its raw reply.
+    pub fn request(&mut self, cmd: Command, key: &str, value: &str) -> Result<Response, BlinkError> {
+        let line = encode_request(cmd, key, value)?;
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(io_err("write"))?;
+        self.read_response()
+    }
+
+    /// Sends every request in one write and reads the replies in order.
+    pub fn pipeline(&mut self, requests: &[(Command, &str, &str)]) -> Result<Vec<Response>, BlinkError> {
+        let mut lines = String::new();
+        for (cmd, key, value) in requests {
+            lines.push_str(&encode_request(*cmd, key, value)?);
+        }
+        self.writer
+            .write_all(lines.as_bytes())
+            .map_err(io_err("write"))?;
+        (0..requests.len()).map(|_| self.read_response()).collect()
+    }
+
+    pub fn authenticate(&mut self, user: &str, password: &str) -> Result<(), BlinkError> {
+        match self.request(Command::Auth, user, password)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn select(&mut self, db: &str) -> Result<(), BlinkError> {
+        match self.request(Command::Select, "", db)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Stale values are returned like fresh ones.
+    pub fn get(&mut self, key: &str) -> Result<Option<Bytes>, BlinkError> {
+        match self.request(Command::Get, key, "")? {
+            Response::Value(v) | Response::Stale(v) => Ok(Some(v)),
+            Response::NotFound => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), BlinkError> {
+        match self.request(Command::Set, key, value_text(value)?)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn set_with_expiry(&mut self, key: &str, value: &[u8], expiry: Expiry) -> Result<(), BlinkError> {
+        match self.request(Command::SetEx, key, &setex_args(value, expiry)?)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Returns false if the key did not exist.
+    pub fn delete(&mut self, key: &str) -> Result<bool, BlinkError> {
+        match self.request(Command::Delete, key, "")? {
+            Response::Ok => Ok(true),
+            Response::NotFound => Ok(false),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn usage(&mut self) -> Result<u64, BlinkError> {
+        match self.request(Command::Usage, "", "")? {
+            Response::Usage(n) => Ok(n),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn ping(&mut self) -> Result<(), BlinkError> {
+        match self.request(Command::Ping, "", "")? {
+            Response::Pong => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+impl Drop for BlockingClient {
+    fn drop(&mut self) {
+        let _ = self.writer.write_all(b"QUIT\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::MemoryEngine;
+    use crate::server::{serve_tcp, ServerState};
+    use std::sync::Arc;
+
+    /// Runs a server on a background runtime; the test thread stays blocking.
+    fn start_server() -> (tokio::runtime::Runtime, String) {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let listener = rt
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(ServerState::new(Arc::new(MemoryEngine::new(1024).unwrap())));
+        rt.spawn(serve_tcp(listener, state));
+        (rt, addr)
+    }
+
+    #[test]
+    fn blocking_round_trip() {
+        let (_rt, addr) = start_server();
+        let mut client = BlockingClient::connect_tcp(&addr).unwrap();
+        client.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.ping().unwrap();
+        client.set("k", b"v 1").unwrap();
+        assert_eq!(&client.get("k").unwrap().unwrap()[..], b"v 1");
+        assert_eq!(client.usage().unwrap(), 4);
+        assert!(client.delete("k").unwrap());
+        assert_eq!(client.get("k").unwrap(), None);
+        assert!(matches!(client.select("9"), Err(BlinkError::Server(_))));
+        let replies = client
+            .pipeline(&[(Command::Set, "a", "1"), (Command::Get, "a", "")])
+            .unwrap();
+        assert_eq!(
+            replies,
+            vec![Response::Ok, Response::Value(Bytes::from_static(b"1"))]
+        );
+    }
+}
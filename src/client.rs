@@ -0,0 +1,1551 @@
+//! Async client for the Blink Store text protocol.
+//!
+//! `BlinkClient` keeps a small pool of TCP connections, health-checks
+//! connections that sat idle, reconnects with exponential backoff, applies
+//! connect/request timeouts, and can pipeline several requests in one
+//! round trip.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), blink_store::BlinkError> {
+//! let client = blink_store::BlinkClient::connect("127.0.0.1:8765").await?;
+//! client.set("user", b"alice").await?;
+//! assert_eq!(client.get("user").await?.as_deref(), Some(&b"alice"[..]));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::engine::{End, Expiry, RateLimitStatus, Versioned};
+use crate::error::BlinkError;
+use crate::protocol::{encode_request, parse_response, Command, Response};
+use crate::pubsub::Message;
+use crate::stream::{PendingEntry, StreamEntry, StreamId};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, Semaphore};
+use tracing::debug;
+
+/// Connection and retry settings for `BlinkClient`.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Server address, e.g. `127.0.0.1:8765`.
+    pub addr: String,
+    /// Maximum number of open connections.
+    pub pool_size: usize,
+    pub connect_timeout: Duration,
+    /// Time allowed for one request (or pipeline) to get its replies.
+    pub request_timeout: Duration,
+    /// Retries when no connection could be had, with exponential backoff.
+    /// A request is never sent twice: once it was written, a failure or
+    /// timeout is returned, since the server may have applied it.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for every further attempt.
+    pub retry_backoff: Duration,
+    /// Connections idle longer than this are checked with `PING` before reuse.
+    pub health_check_after: Duration,
+    /// Optional `AUTH` credentials sent on every new connection.
+    pub auth: Option<(String, String)>,
+    /// Optional database `SELECT`ed on every new connection.
+    pub database: Option<String>,
+}
+
+impl ClientConfig {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            pool_size: 8,
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(5),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(50),
+            health_check_after: Duration::from_secs(30),
+            auth: None,
+            database: None,
+        }
+    }
+}
+
+struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    last_used: Instant,
+}
+
+impl Connection {
+    async fn open(config: &ClientConfig) -> Result<Self, BlinkError> {
+        let stream = tokio::time::timeout(config.connect_timeout, TcpStream::connect(&config.addr))
+            .await
+            .map_err(|_| BlinkError::Timeout(format!("connect to {}", config.addr)))?
+            .map_err(|e| BlinkError::Internal(format!("connect {}: {}", config.addr, e)))?;
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        let mut conn = Connection {
+            reader: BufReader::new(reader),
+            writer,
+            last_used: Instant::now(),
+        };
+        if let Some((user, password)) = &config.auth {
+            conn.expect_ok(&encode_request(Command::Auth, user, password)?, config)
+                .await?;
+        }
+        if let Some(db) = &config.database {
+            conn.expect_ok(&encode_request(Command::Select, "", db)?, config)
+                .await?;
+        }
+        Ok(conn)
+    }
+
+    async fn expect_ok(&mut self, line: &str, config: &ClientConfig) -> Result<(), BlinkError> {
+        match self.round_trip(line, 1, config.request_timeout).await?.pop() {
+            Some(Response::Ok) => Ok(()),
+            Some(Response::Error(msg)) => Err(BlinkError::Server(msg)),
+            other => Err(BlinkError::Protocol(format!("unexpected reply {:?}", other))),
+        }
+    }
+
+    async fn write(&mut self, lines: &str) -> Result<(), BlinkError> {
+        self.writer
+            .write_all(lines.as_bytes())
+            .await
+            .map_err(|e| BlinkError::Internal(format!("write: {}", e)))
+    }
+
+    async fn read_reply(&mut self) -> Result<Response, BlinkError> {
+        let mut line = String::new();
+        let n = self
+            .reader
+            .read_line(&mut line)
+            .await
+            .map_err(|e| BlinkError::Internal(format!("read: {}", e)))?;
+        if n == 0 {
+            return Err(BlinkError::Internal("connection closed by server".into()));
+        }
+        parse_response(&line)
+    }
+
+    /// False if the server closed the connection, or sent something
+    /// unasked, while it sat in the pool.
+    fn is_open(&self) -> bool {
+        if !self.reader.buffer().is_empty() {
+            return false;
+        }
+        let mut probe = [0u8; 1];
+        matches!(
+            self.reader.get_ref().try_read(&mut probe),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock
+        )
+    }
+
+    /// Writes `lines` in one go and reads `count` reply lines.
+    async fn round_trip(
+        &mut self,
+        lines: &str,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Response>, BlinkError> {
+        let exchange = async {
+            self.write(lines).await?;
+            let mut replies = Vec::with_capacity(count);
+            for _ in 0..count {
+                replies.push(self.read_reply().await?);
+            }
+            Ok(replies)
+        };
+        let replies = tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| BlinkError::Timeout("waiting for reply".into()))??;
+        self.last_used = Instant::now();
+        Ok(replies)
+    }
+}
+
+/// Values are sent as the rest of a text line, so they must be UTF-8.
+pub(crate) fn value_text(value: &[u8]) -> Result<&str, BlinkError> {
+    std::str::from_utf8(value).map_err(|_| BlinkError::Protocol("value must be UTF-8".into()))
+}
+
+/// Hash field names, set members and extra keys are single tokens on the
+/// request line.
+fn field_text(field: &str) -> Result<&str, BlinkError> {
+    if field.is_empty() || field.contains(char::is_whitespace) {
+        return Err(BlinkError::Protocol(
+            "field must be non-empty and contain no whitespace".into(),
+        ));
+    }
+    Ok(field)
+}
+
+fn score_text(score: f64) -> Result<String, BlinkError> {
+    if score.is_nan() {
+        return Err(BlinkError::NotANumber);
+    }
+    Ok(score.to_string())
+}
+
+fn parse_score(text: &[u8]) -> Result<f64, BlinkError> {
+    std::str::from_utf8(text)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| BlinkError::Protocol("invalid score".into()))
+}
+
+fn utf8(bytes: &[u8], what: &str) -> Result<String, BlinkError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| BlinkError::Protocol(format!("{} is not UTF-8", what)))
+}
+
+/// Decodes a `WITHSCORES` reply of alternating members and scores.
+fn scored_members(values: &[Bytes]) -> Result<Vec<(String, f64)>, BlinkError> {
+    if !values.len().is_multiple_of(2) {
+        return Err(BlinkError::Protocol("unpaired member and score".into()));
+    }
+    values
+        .chunks(2)
+        .map(|pair| Ok((utf8(&pair[0], "member")?, parse_score(&pair[1])?)))
+        .collect()
+}
+
+fn stream_id(text: &[u8]) -> Result<StreamId, BlinkError> {
+    std::str::from_utf8(text)
+        .map_err(|_| BlinkError::Protocol("stream ID is not UTF-8".into()))?
+        .parse()
+}
+
+/// Decodes stream entries: per entry its ID, its number of fields, then
+/// each field and value.
+fn stream_entries(values: &[Bytes]) -> Result<Vec<StreamEntry>, BlinkError> {
+    let truncated = || BlinkError::Protocol("truncated stream entry".into());
+    let mut entries = Vec::new();
+    let mut rest = values;
+    while let [id, n, tail @ ..] = rest {
+        let len = std::str::from_utf8(n)
+            .ok()
+            .and_then(|n| n.parse::<usize>().ok())
+            .and_then(|n| n.checked_mul(2))
+            .filter(|len| *len <= tail.len())
+            .ok_or_else(truncated)?;
+        let fields = tail[..len]
+            .chunks(2)
+            .map(|pair| Ok((utf8(&pair[0], "field")?, pair[1].clone())))
+            .collect::<Result<_, BlinkError>>()?;
+        entries.push(StreamEntry {
+            id: stream_id(id)?,
+            fields,
+        });
+        rest = &tail[len..];
+    }
+    if !rest.is_empty() {
+        return Err(truncated());
+    }
+    Ok(entries)
+}
+
+/// `<soft_ms> <hard_ms>`, 0 meaning no expiry.
+fn expiry_args(expiry: Expiry) -> String {
+    let ms = |d: Option<Duration>| d.map_or(0, |d| d.as_millis().max(1) as u64);
+    format!("{} {}", ms(expiry.soft), ms(expiry.hard))
+}
+
+/// SETEX arguments: `<soft_ms> <hard_ms> <value>`, 0 meaning no expiry.
+pub(crate) fn setex_args(value: &[u8], expiry: Expiry) -> Result<String, BlinkError> {
+    let mut args = expiry_args(expiry);
+    if !value.is_empty() {
+        args.push(' ');
+        args.push_str(value_text(value)?);
+    }
+    Ok(args)
+}
+
+pub(crate) fn unexpected(reply: Response) -> BlinkError {
+    match reply {
+        Response::Error(msg) => BlinkError::Server(msg),
+        other => BlinkError::Protocol(format!("unexpected reply {:?}", other)),
+    }
+}
+
+struct Pool {
+    config: ClientConfig,
+    idle: Mutex<Vec<Connection>>,
+    permits: Semaphore,
+}
+
+/// Pooled async client. Cheap to clone; clones share the pool.
+#[derive(Clone)]
+pub struct BlinkClient {
+    pool: Arc<Pool>,
+}
+
+impl BlinkClient {
+    /// Connects with default settings, failing fast if the server is unreachable.
+    pub async fn connect(addr: impl Into<String>) -> Result<Self, BlinkError> {
+        Self::connect_with(ClientConfig::new(addr)).await
+    }
+
+    pub async fn connect_with(config: ClientConfig) -> Result<Self, BlinkError> {
+        let first = Connection::open(&config).await?;
+        let pool_size = config.pool_size.max(1);
+        Ok(Self {
+            pool: Arc::new(Pool {
+                config,
+                idle: Mutex::new(vec![first]),
+                permits: Semaphore::new(pool_size),
+            }),
+        })
+    }
+
+    /// Takes an idle connection (health-checking stale ones) or opens a new one.
+    async fn checkout(&self) -> Result<Connection, BlinkError> {
+        let config = &self.pool.config;
+        loop {
+            let conn = self.pool.idle.lock().await.pop();
+            let Some(mut conn) = conn else {
+                return Connection::open(config).await;
+            };
+            if !conn.is_open() {
+                debug!(action = "client_drop_closed_connection");
+                continue;
+            }
+            if conn.last_used.elapsed() < config.health_check_after {
+                return Ok(conn);
+            }
+            match conn.round_trip("PING\n", 1, config.request_timeout).await {
+                Ok(replies) if replies == [Response::Pong] => return Ok(conn),
+                _ => debug!(action = "client_drop_unhealthy_connection"),
+            }
+        }
+    }
+
+    /// Sends pre-encoded request `lines` and returns `count` replies within
+    /// `timeout`. Getting a connection is retried with exponential backoff;
+    /// the exchange itself is not, as the server may have applied it.
+    async fn execute(&self, lines: &str, count: usize, timeout: Duration) -> Result<Vec<Response>, BlinkError> {
+        let config = &self.pool.config;
+        let _permit = self
+            .pool
+            .permits
+            .acquire()
+            .await
+            .map_err(|e| BlinkError::Internal(e.to_string()))?;
+        let mut attempt = 0;
+        loop {
+            let err = match self.checkout().await {
+                Ok(mut conn) => {
+                    let replies = conn.round_trip(lines, count, timeout).await?;
+                    self.pool.idle.lock().await.push(conn);
+                    return Ok(replies);
+                }
+                Err(e) => e,
+            };
+            // Bad input or a server-side refusal will not succeed on retry.
+            if matches!(err, BlinkError::Protocol(_) | BlinkError::Server(_)) {
+                return Err(err);
+            }
+            if attempt >= config.max_retries {
+                return Err(err);
+            }
+            let delay = config.retry_backoff * 2u32.saturating_pow(attempt);
+            debug!(error = %err, attempt, delay_ms = delay.as_millis() as u64, action = "client_retry");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Sends one request and returns its raw reply.
+    pub async fn request(&self, cmd: Command, key: &str, value: &str) -> Result<Response, BlinkError> {
+        self.execute(&encode_request(cmd, key, value)?, 1, self.pool.config.request_timeout)
+            .await?
+            .pop()
+            .ok_or_else(|| BlinkError::Protocol("missing reply".into()))
+    }
+
+    /// Stale values are returned like fresh ones; use `request` with
+    /// `Command::Get` to see the `STALE` marker.
+    pub async fn get(&self, key: &str) -> Result<Option<Bytes>, BlinkError> {
+        match self.request(Command::Get, key, "").await? {
+            Response::Value(v) | Response::Stale(v) => Ok(Some(v)),
+            Response::NotFound => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn set(&self, key: &str, value: &[u8]) -> Result<(), BlinkError> {
+        match self.request(Command::Set, key, value_text(value)?).await? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Sets a value that turns stale after `expiry.soft` and is removed
+    /// after `expiry.hard`.
+    pub async fn set_with_expiry(&self, key: &str, value: &[u8], expiry: Expiry) -> Result<(), BlinkError> {
+        match self.request(Command::SetEx, key, &setex_args(value, expiry)?).await? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Returns false if the key did not exist.
+    pub async fn delete(&self, key: &str) -> Result<bool, BlinkError> {
+        match self.request(Command::Delete, key, "").await? {
+            Response::Ok => Ok(true),
+            Response::NotFound => Ok(false),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// How many of `keys` exist, without transferring their values.
+    pub async fn exists(&self, keys: &[&str]) -> Result<usize, BlinkError> {
+        match keys.split_first() {
+            Some((key, rest)) => self.count(Command::Exists, key, rest).await,
+            None => Ok(0),
+        }
+    }
+
+    /// Marks `keys` as recently used for eviction; returns how many exist.
+    pub async fn touch(&self, keys: &[&str]) -> Result<usize, BlinkError> {
+        match keys.split_first() {
+            Some((key, rest)) => self.count(Command::Touch, key, rest).await,
+            None => Ok(0),
+        }
+    }
+
+    /// `string`, `hash`, `list`, `set`, `zset`, `hll`, `bloom`, `stream`,
+    /// `ratelimit` or `lock`; `None` if the key is missing.
+    pub async fn key_type(&self, key: &str) -> Result<Option<String>, BlinkError> {
+        match self.request(Command::Type, key, "").await? {
+            Response::Value(kind) if &kind[..] == b"none" => Ok(None),
+            Response::Value(kind) => utf8(&kind, "type").map(Some),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Moves a key, with its expiry, to `to`, replacing any value there.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), BlinkError> {
+        match self.request(Command::Rename, from, field_text(to)?).await? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Like `rename`, but returns false and changes nothing if `to` exists.
+    pub async fn renamenx(&self, from: &str, to: &str) -> Result<bool, BlinkError> {
+        match self.request(Command::RenameNx, from, field_text(to)?).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Number of keys in the selected database.
+    pub async fn dbsize(&self) -> Result<usize, BlinkError> {
+        match self.request(Command::DbSize, "", "").await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Stores `value` and returns the value it replaced, atomically.
+    pub async fn getset(&self, key: &str, value: &[u8]) -> Result<Option<Bytes>, BlinkError> {
+        match self.request(Command::GetSet, key, value_text(value)?).await? {
+            Response::Value(v) => Ok(Some(v)),
+            Response::NotFound => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Removes the key and returns its value, atomically; of several
+    /// concurrent callers only one gets it. Like every request, it is never
+    /// resent once written, so the server removes the value at most once;
+    /// but if the reply is lost, the value is lost with it, so one-time
+    /// tokens should be reissued rather than re-read after an error.
+    pub async fn getdel(&self, key: &str) -> Result<Option<Bytes>, BlinkError> {
+        match self.request(Command::GetDel, key, "").await? {
+            Response::Value(v) => Ok(Some(v)),
+            Response::NotFound => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Reads the value and replaces its expiry (`Expiry::default()` makes
+    /// it persistent), atomically.
+    pub async fn getex(&self, key: &str, expiry: Expiry) -> Result<Option<Bytes>, BlinkError> {
+        match self.request(Command::GetEx, key, &expiry_args(expiry)).await? {
+            Response::Value(v) => Ok(Some(v)),
+            Response::NotFound => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Appends to a string value, creating it if needed; returns the new
+    /// length.
+    pub async fn append(&self, key: &str, suffix: &[u8]) -> Result<usize, BlinkError> {
+        match self.request(Command::Append, key, value_text(suffix)?).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Overwrites a string value from byte `offset`, zero-padding it as
+    /// needed; returns the new length.
+    pub async fn setrange(&self, key: &str, offset: usize, patch: &[u8]) -> Result<usize, BlinkError> {
+        let args = format!("{} {}", offset, value_text(patch)?);
+        match self.request(Command::SetRange, key, args.trim_end()).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Bytes `start..=end` of a string value; negative offsets count from
+    /// the end. Empty if the key is missing.
+    pub async fn getrange(&self, key: &str, start: i64, end: i64) -> Result<Bytes, BlinkError> {
+        match self.request(Command::GetRange, key, &format!("{} {}", start, end)).await? {
+            Response::Value(v) => Ok(v),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn strlen(&self, key: &str) -> Result<usize, BlinkError> {
+        match self.request(Command::StrLen, key, "").await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Sets one field of a hash; returns true if the field is new.
+    pub async fn hset(&self, key: &str, field: &str, value: &[u8]) -> Result<bool, BlinkError> {
+        let args = format!("{} {}", field_text(field)?, value_text(value)?);
+        match self.request(Command::HSet, key, args.trim_end()).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn hget(&self, key: &str, field: &str) -> Result<Option<Bytes>, BlinkError> {
+        match self.request(Command::HGet, key, field_text(field)?).await? {
+            Response::Value(v) => Ok(Some(v)),
+            Response::NotFound => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Returns how many of `fields` existed.
+    pub async fn hdel(&self, key: &str, fields: &[&str]) -> Result<usize, BlinkError> {
+        self.count(Command::HDel, key, fields).await
+    }
+
+    /// Every field of a hash, sorted by name; empty if the key is missing.
+    pub async fn hgetall(&self, key: &str) -> Result<Vec<(String, Bytes)>, BlinkError> {
+        match self.request(Command::HGetAll, key, "").await? {
+            Response::Values(values) if values.len().is_multiple_of(2) => values
+                .chunks(2)
+                .map(|pair| Ok((utf8(&pair[0], "field")?, pair[1].clone())))
+                .collect(),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Pushes onto the end of a list; returns the new length.
+    pub async fn push(&self, key: &str, end: End, value: &[u8]) -> Result<usize, BlinkError> {
+        let cmd = match end {
+            End::Left => Command::LPush,
+            End::Right => Command::RPush,
+        };
+        match self.request(cmd, key, value_text(value)?).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn pop(&self, key: &str, end: End) -> Result<Option<Bytes>, BlinkError> {
+        let cmd = match end {
+            End::Left => Command::LPop,
+            End::Right => Command::RPop,
+        };
+        match self.request(cmd, key, "").await? {
+            Response::Value(v) => Ok(Some(v)),
+            Response::NotFound => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Items `start..=stop` of a list; negative indexes count from the end.
+    pub async fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>, BlinkError> {
+        match self.request(Command::LRange, key, &format!("{} {}", start, stop)).await? {
+            Response::Values(items) => Ok(items),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Pops the head of a list, waiting up to `timeout` (indefinitely if
+    /// `None`) for a push. The connection is held for the whole wait.
+    pub async fn blpop(&self, key: &str, timeout: Option<Duration>) -> Result<Option<Bytes>, BlinkError> {
+        let ms = timeout.map_or(0, |t| t.as_millis().max(1) as u64);
+        let request_timeout = match timeout {
+            Some(t) => self.pool.config.request_timeout + t,
+            None => Duration::MAX,
+        };
+        let reply = self
+            .execute(&encode_request(Command::BLPop, key, &ms.to_string())?, 1, request_timeout)
+            .await?
+            .pop();
+        match reply {
+            Some(Response::Value(v)) => Ok(Some(v)),
+            Some(Response::NotFound) => Ok(None),
+            Some(other) => Err(unexpected(other)),
+            None => Err(BlinkError::Protocol("missing reply".into())),
+        }
+    }
+
+    /// Adds members to a set; returns how many were new.
+    pub async fn sadd(&self, key: &str, members: &[&str]) -> Result<usize, BlinkError> {
+        self.count(Command::SAdd, key, members).await
+    }
+
+    /// Removes members from a set; returns how many existed.
+    pub async fn srem(&self, key: &str, members: &[&str]) -> Result<usize, BlinkError> {
+        self.count(Command::SRem, key, members).await
+    }
+
+    /// Every member of a set, sorted; empty if the key is missing.
+    pub async fn smembers(&self, key: &str) -> Result<Vec<String>, BlinkError> {
+        match self.request(Command::SMembers, key, "").await? {
+            Response::Values(values) => values.iter().map(|m| utf8(m, "member")).collect(),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn sismember(&self, key: &str, member: &str) -> Result<bool, BlinkError> {
+        match self.request(Command::SIsMember, key, field_text(member)?).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Sets the score of each `(score, member)` in a sorted set; returns how
+    /// many members are new.
+    pub async fn zadd(&self, key: &str, entries: &[(f64, &str)]) -> Result<usize, BlinkError> {
+        let mut args = Vec::with_capacity(entries.len());
+        for (score, member) in entries {
+            args.push(format!("{} {}", score_text(*score)?, field_text(member)?));
+        }
+        match self.request(Command::ZAdd, key, &args.join(" ")).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Adds `delta` to a member's score (0 if new); returns the new score.
+    pub async fn zincrby(&self, key: &str, delta: f64, member: &str) -> Result<f64, BlinkError> {
+        let args = format!("{} {}", score_text(delta)?, field_text(member)?);
+        match self.request(Command::ZIncrBy, key, &args).await? {
+            Response::Value(score) => parse_score(&score),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Removes members from a sorted set; returns how many existed.
+    pub async fn zrem(&self, key: &str, members: &[&str]) -> Result<usize, BlinkError> {
+        self.count(Command::ZRem, key, members).await
+    }
+
+    /// Members ranked `start..=stop` by ascending score, with their scores;
+    /// negative ranks count from the highest.
+    pub async fn zrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(String, f64)>, BlinkError> {
+        let args = format!("{} {} WITHSCORES", start, stop);
+        match self.request(Command::ZRange, key, &args).await? {
+            Response::Values(values) => scored_members(&values),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Members with a score between `min` and `max`, lowest first.
+    pub async fn zrangebyscore(
+        &self,
+        key: &str,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> Result<Vec<(String, f64)>, BlinkError> {
+        let bound = |b: Bound<f64>, unbounded: &str| match b {
+            Bound::Included(s) => score_text(s),
+            Bound::Excluded(s) => score_text(s).map(|s| format!("({}", s)),
+            Bound::Unbounded => Ok(unbounded.to_owned()),
+        };
+        let args = format!("{} {} WITHSCORES", bound(min, "-inf")?, bound(max, "+inf")?);
+        match self.request(Command::ZRangeByScore, key, &args).await? {
+            Response::Values(values) => scored_members(&values),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Sends `cmd key name...` and expects an `INTEGER` count back.
+    async fn count(&self, cmd: Command, key: &str, members: &[&str]) -> Result<usize, BlinkError> {
+        for member in members {
+            field_text(member)?;
+        }
+        match self.request(cmd, key, &members.join(" ")).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Counts `cost` requests against `max` per `window` for `key`; see
+    /// `MemoryEngine::rate_limit`.
+    pub async fn rate_limit(
+        &self,
+        key: &str,
+        max: u32,
+        window: Duration,
+        cost: u32,
+    ) -> Result<RateLimitStatus, BlinkError> {
+        let args = format!("{} {} {}", max, window.as_millis(), cost);
+        match self.request(Command::RateLimit, key, &args).await? {
+            Response::Info(fields) => {
+                let field = |name: &str| {
+                    fields
+                        .iter()
+                        .find(|(k, _)| k == name)
+                        .and_then(|(_, v)| v.parse::<u64>().ok())
+                        .ok_or_else(|| BlinkError::Protocol(format!("RATELIMIT reply lacks {}", name)))
+                };
+                Ok(RateLimitStatus {
+                    allowed: field("allowed")? == 1,
+                    remaining: field("remaining")?,
+                    retry_after: Duration::from_millis(field("retry_after_ms")?),
+                    reset_after: Duration::from_millis(field("reset_after_ms")?),
+                })
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Takes the lock at `key` for `ttl`; returns its fencing token, or
+    /// `None` if another owner holds it. Without `owner`, the owner to pass
+    /// to `unlock` and `extend_lock` is the token in decimal.
+    pub async fn lock(&self, key: &str, ttl: Duration, owner: Option<&str>) -> Result<Option<u64>, BlinkError> {
+        let mut args = ttl.as_millis().max(1).to_string();
+        if let Some(owner) = owner {
+            args.push(' ');
+            args.push_str(field_text(owner)?);
+        }
+        self.lock_reply(Command::Lock, key, &args).await
+    }
+
+    /// Renews a lock held by `owner`; returns its token, or `None` if it
+    /// was lost.
+    pub async fn extend_lock(&self, key: &str, owner: &str, ttl: Duration) -> Result<Option<u64>, BlinkError> {
+        let args = format!("{} {}", ttl.as_millis().max(1), field_text(owner)?);
+        self.lock_reply(Command::Extend, key, &args).await
+    }
+
+    /// Releases a lock held by `owner`; returns false if it was not held.
+    pub async fn unlock(&self, key: &str, owner: &str) -> Result<bool, BlinkError> {
+        match self.request(Command::Unlock, key, field_text(owner)?).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Adds elements to a HyperLogLog; returns true if its estimate may
+    /// have changed.
+    pub async fn pfadd(&self, key: &str, elements: &[&str]) -> Result<bool, BlinkError> {
+        Ok(self.count(Command::PfAdd, key, elements).await? == 1)
+    }
+
+    /// Estimated number of distinct elements across the HyperLogLogs at
+    /// `keys`.
+    pub async fn pfcount(&self, keys: &[&str]) -> Result<u64, BlinkError> {
+        match keys.split_first() {
+            Some((key, rest)) => Ok(self.count(Command::PfCount, key, rest).await? as u64),
+            None => Ok(0),
+        }
+    }
+
+    /// Merges the HyperLogLogs at `sources` into the one at `dest`.
+    pub async fn pfmerge(&self, dest: &str, sources: &[&str]) -> Result<(), BlinkError> {
+        for source in sources {
+            field_text(source)?;
+        }
+        match self.request(Command::PfMerge, dest, &sources.join(" ")).await? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Creates a Bloom filter sized for `capacity` items at `error_rate`
+    /// false positives; returns false if `key` exists.
+    pub async fn bf_reserve(&self, key: &str, error_rate: f64, capacity: u64) -> Result<bool, BlinkError> {
+        let args = format!("{} {}", error_rate, capacity);
+        match self.request(Command::BfReserve, key, &args).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Adds an item to a Bloom filter; returns true if it was definitely
+    /// not there before.
+    pub async fn bf_add(&self, key: &str, item: &[u8]) -> Result<bool, BlinkError> {
+        match self.request(Command::BfAdd, key, value_text(item)?).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Whether an item may have been added to a Bloom filter; false is
+    /// certain.
+    pub async fn bf_exists(&self, key: &str, item: &[u8]) -> Result<bool, BlinkError> {
+        match self.request(Command::BfExists, key, value_text(item)?).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Appends an entry to a stream and returns its ID; without `id`, the
+    /// server makes one from its clock. With `max_len`, the oldest entries
+    /// are trimmed so at most that many remain.
+    pub async fn xadd(
+        &self,
+        key: &str,
+        id: Option<StreamId>,
+        fields: &[(&str, &str)],
+        max_len: Option<usize>,
+    ) -> Result<StreamId, BlinkError> {
+        let mut args = max_len.map_or_else(String::new, |n| format!("MAXLEN {} ", n));
+        args.push_str(&id.map_or_else(|| "*".to_owned(), |id| id.to_string()));
+        for (field, value) in fields {
+            args.push_str(&format!(" {} {}", field_text(field)?, field_text(value)?));
+        }
+        match self.request(Command::XAdd, key, &args).await? {
+            Response::Value(id) => stream_id(&id),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn xlen(&self, key: &str) -> Result<usize, BlinkError> {
+        match self.request(Command::XLen, key, "").await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Entries with IDs from `start` to `end` inclusive, oldest first.
+    pub async fn xrange(
+        &self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        let args = format!("{} {}", start, end);
+        self.stream_read(Command::XRange, key, args, count, None).await
+    }
+
+    /// Removes the oldest entries until at most `max_len` remain; returns
+    /// how many were removed.
+    pub async fn xtrim(&self, key: &str, max_len: usize) -> Result<usize, BlinkError> {
+        match self.request(Command::XTrim, key, &format!("MAXLEN {}", max_len)).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Entries with IDs after `after`, oldest first.
+    pub async fn xread(&self, key: &str, after: StreamId, count: Option<usize>) -> Result<Vec<StreamEntry>, BlinkError> {
+        self.stream_read(Command::XRead, key, after.to_string(), count, None)
+            .await
+    }
+
+    /// Like `xread`, but waits up to `timeout` (indefinitely if `None`)
+    /// for an entry; without `after`, for one added from now on. Returns no
+    /// entries on timeout. The connection is held for the whole wait.
+    pub async fn xread_blocking(
+        &self,
+        key: &str,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        let after = after.map_or_else(|| "$".to_owned(), |id| id.to_string());
+        self.stream_read(Command::XRead, key, after, count, Some(timeout))
+            .await
+    }
+
+    /// Creates a consumer group that hands out entries after `start`, or,
+    /// without it, entries added from now on. Creates the stream if needed.
+    pub async fn xgroup_create(&self, key: &str, group: &str, start: Option<StreamId>) -> Result<(), BlinkError> {
+        let start = start.map_or_else(|| "$".to_owned(), |id| id.to_string());
+        let args = format!("CREATE {} {}", field_text(group)?, start);
+        match self.request(Command::XGroup, key, &args).await? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Removes a consumer group; returns false if it did not exist.
+    pub async fn xgroup_destroy(&self, key: &str, group: &str) -> Result<bool, BlinkError> {
+        let args = format!("DESTROY {}", field_text(group)?);
+        match self.request(Command::XGroup, key, &args).await? {
+            Response::Integer(n) => Ok(n == 1),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Hands entries to `consumer` of `group`: without `after`, entries no
+    /// consumer of the group has had yet; with it, the consumer's own
+    /// unacknowledged entries with IDs after `after`.
+    pub async fn xreadgroup(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        let after = after.map_or_else(|| ">".to_owned(), |id| id.to_string());
+        let args = format!("{} {} {}", field_text(group)?, field_text(consumer)?, after);
+        self.stream_read(Command::XReadGroup, key, args, count, None).await
+    }
+
+    /// Like `xreadgroup` for new entries, but waits up to `timeout`
+    /// (indefinitely if `None`) for one. The connection is held for the
+    /// whole wait.
+    pub async fn xreadgroup_blocking(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        count: Option<usize>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        let args = format!("{} {} >", field_text(group)?, field_text(consumer)?);
+        self.stream_read(Command::XReadGroup, key, args, count, Some(timeout))
+            .await
+    }
+
+    /// Acknowledges entries handed out by `group`; returns how many were
+    /// pending.
+    pub async fn xack(&self, key: &str, group: &str, ids: &[StreamId]) -> Result<usize, BlinkError> {
+        let mut args = field_text(group)?.to_owned();
+        for id in ids {
+            args.push_str(&format!(" {}", id));
+        }
+        match self.request(Command::XAck, key, &args).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Entries handed out by `group` and not yet acknowledged, oldest ID
+    /// first.
+    pub async fn xpending(&self, key: &str, group: &str) -> Result<Vec<PendingEntry>, BlinkError> {
+        let values = match self.request(Command::XPending, key, field_text(group)?).await? {
+            Response::Values(values) if values.len().is_multiple_of(4) => values,
+            other => return Err(unexpected(other)),
+        };
+        let number = |text: &[u8]| {
+            std::str::from_utf8(text)
+                .ok()
+                .and_then(|n| n.parse::<u64>().ok())
+                .ok_or_else(|| BlinkError::Protocol("invalid pending entry".into()))
+        };
+        values
+            .chunks(4)
+            .map(|entry| {
+                Ok(PendingEntry {
+                    id: stream_id(&entry[0])?,
+                    consumer: utf8(&entry[1], "consumer")?,
+                    idle: Duration::from_millis(number(&entry[2])?),
+                    deliveries: number(&entry[3])?,
+                })
+            })
+            .collect()
+    }
+
+    /// Sends a stream read and decodes its entries. With `block`, the
+    /// server waits up to the given timeout (indefinitely if `None`), and
+    /// so does the request.
+    async fn stream_read(
+        &self,
+        cmd: Command,
+        key: &str,
+        mut args: String,
+        count: Option<usize>,
+        block: Option<Option<Duration>>,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        if let Some(count) = count {
+            args.push_str(&format!(" COUNT {}", count.max(1)));
+        }
+        let request_timeout = match block {
+            None => self.pool.config.request_timeout,
+            Some(Some(t)) => {
+                args.push_str(&format!(" BLOCK {}", t.as_millis().max(1)));
+                self.pool.config.request_timeout + t
+            }
+            Some(None) => {
+                args.push_str(" BLOCK 0");
+                Duration::MAX
+            }
+        };
+        let reply = self
+            .execute(&encode_request(cmd, key, &args)?, 1, request_timeout)
+            .await?
+            .pop();
+        match reply {
+            Some(Response::Values(values)) => stream_entries(&values),
+            Some(other) => Err(unexpected(other)),
+            None => Err(BlinkError::Protocol("missing reply".into())),
+        }
+    }
+
+    async fn lock_reply(&self, cmd: Command, key: &str, args: &str) -> Result<Option<u64>, BlinkError> {
+        match self.request(cmd, key, args).await? {
+            Response::Integer(0) => Ok(None),
+            Response::Integer(token) => Ok(Some(token as u64)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn usage(&self) -> Result<u64, BlinkError> {
+        match self.request(Command::Usage, "", "").await? {
+            Response::Usage(n) => Ok(n),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// `INFO` fields of the selected database and server, in reply order.
+    pub async fn info(&self) -> Result<Vec<(String, String)>, BlinkError> {
+        match self.request(Command::Info, "", "").await? {
+            Response::Info(fields) => Ok(fields),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn ping(&self) -> Result<(), BlinkError> {
+        match self.request(Command::Ping, "", "").await? {
+            Response::Pong => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Long-poll read: returns once the version of `key` differs from
+    /// `version` (0 for a missing key), or its unchanged state after
+    /// `timeout`. A zero timeout reads the current version without waiting.
+    pub async fn wait_for_change(
+        &self,
+        key: &str,
+        version: u64,
+        timeout: Duration,
+    ) -> Result<Versioned, BlinkError> {
+        let args = format!("{} {}", version, timeout.as_millis());
+        let request_timeout = self.pool.config.request_timeout + timeout;
+        let reply = self
+            .execute(&encode_request(Command::Wait, key, &args)?, 1, request_timeout)
+            .await?
+            .pop();
+        match reply {
+            Some(Response::Versioned { version, value }) => Ok(Versioned { version, value }),
+            Some(other) => Err(unexpected(other)),
+            None => Err(BlinkError::Protocol("missing reply".into())),
+        }
+    }
+
+    /// Returns how many subscribers received the message.
+    pub async fn publish(&self, channel: &str, message: &[u8]) -> Result<usize, BlinkError> {
+        match self.request(Command::Publish, channel, value_text(message)?).await? {
+            Response::Integer(n) => Ok(n as usize),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Opens a dedicated connection, outside the pool, subscribed to `channels`.
+    pub async fn subscribe(&self, channels: &[&str]) -> Result<Subscriber, BlinkError> {
+        let config = &self.pool.config;
+        let mut subscriber = Subscriber {
+            conn: Connection::open(config).await?,
+            request_timeout: config.request_timeout,
+            pending: VecDeque::new(),
+        };
+        subscriber.subscribe(channels).await?;
+        Ok(subscriber)
+    }
+
+    /// Runs `requests` as one MULTI/EXEC transaction on a single connection
+    /// and returns their replies. Either all of them are applied or, if any
+    /// fails, none are and the error is returned. WATCH needs a connection
+    /// of its own, so use `BlockingClient` for optimistic transactions.
+    pub async fn transaction(&self, requests: &[(Command, &str, &str)]) -> Result<Vec<Response>, BlinkError> {
+        let mut pipeline = self.pipeline().request(Command::Multi, "", "");
+        for (cmd, key, value) in requests {
+            pipeline = pipeline.request(*cmd, key, value);
+        }
+        let mut replies = pipeline.request(Command::Exec, "", "").execute().await?;
+        match replies.pop() {
+            Some(Response::Exec(replies)) => Ok(replies),
+            Some(Response::Aborted) => Err(BlinkError::Server("transaction aborted".into())),
+            Some(other) => Err(unexpected(other)),
+            None => Err(BlinkError::Protocol("missing reply".into())),
+        }
+    }
+
+    /// Starts a batch of requests sent in a single round trip.
+    pub fn pipeline(&self) -> Pipeline<'_> {
+        Pipeline {
+            client: self,
+            lines: String::new(),
+            count: 0,
+            error: None,
+        }
+    }
+}
+
+/// A connection in push mode, from `BlinkClient::subscribe`.
+pub struct Subscriber {
+    conn: Connection,
+    request_timeout: Duration,
+    /// Messages that arrived while waiting for a (P)(UN)SUBSCRIBE reply.
+    pending: VecDeque<Message>,
+}
+
+impl Subscriber {
+    async fn command(&mut self, cmd: Command, names: &[&str]) -> Result<(), BlinkError> {
+        let line = encode_request(cmd, "", &names.join(" "))?;
+        let exchange = async {
+            self.conn.write(&line).await?;
+            loop {
+                match self.conn.read_reply().await? {
+                    Response::Ok => return Ok(()),
+                    Response::Message {
+                        channel,
+                        pattern,
+                        payload,
+                    } => self.pending.push_back(Message {
+                        channel,
+                        pattern,
+                        payload,
+                    }),
+                    other => return Err(unexpected(other)),
+                }
+            }
+        };
+        tokio::time::timeout(self.request_timeout, exchange)
+            .await
+            .map_err(|_| BlinkError::Timeout("waiting for reply".into()))?
+    }
+
+    pub async fn subscribe(&mut self, channels: &[&str]) -> Result<(), BlinkError> {
+        self.command(Command::Subscribe, channels).await
+    }
+
+    /// Subscribes to channels matching glob patterns.
+    pub async fn psubscribe(&mut self, patterns: &[&str]) -> Result<(), BlinkError> {
+        self.command(Command::PSubscribe, patterns).await
+    }
+
+    /// Drops the given channels or patterns, or all of them if empty.
+    pub async fn unsubscribe(&mut self, names: &[&str]) -> Result<(), BlinkError> {
+        self.command(Command::Unsubscribe, names).await
+    }
+
+    /// Waits for the next message. Fails with `BlinkError::Server` if the
+    /// server dropped this subscriber for reading too slowly.
+    pub async fn next_message(&mut self) -> Result<Message, BlinkError> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(message);
+        }
+        match self.conn.read_reply().await? {
+            Response::Message {
+                channel,
+                pattern,
+                payload,
+            } => Ok(Message {
+                channel,
+                pattern,
+                payload,
+            }),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+/// Requests queued for one round trip; replies come back in order.
+pub struct Pipeline<'a> {
+    client: &'a BlinkClient,
+    lines: String,
+    count: usize,
+    /// First validation error; reported by `execute`.
+    error: Option<BlinkError>,
+}
+
+impl Pipeline<'_> {
+    fn push(&mut self, line: Result<String, BlinkError>) {
+        match line {
+            Ok(line) => {
+                self.lines.push_str(&line);
+                self.count += 1;
+            }
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+    }
+
+    /// Queues any request; its reply is returned as-is by `execute`.
+    pub fn request(mut self, cmd: Command, key: &str, value: &str) -> Self {
+        self.push(encode_request(cmd, key, value));
+        self
+    }
+
+    pub fn get(self, key: &str) -> Self {
+        self.request(Command::Get, key, "")
+    }
+
+    pub fn set(mut self, key: &str, value: &[u8]) -> Self {
+        let line = value_text(value).and_then(|v| encode_request(Command::Set, key, v));
+        self.push(line);
+        self
+    }
+
+    pub fn delete(self, key: &str) -> Self {
+        self.request(Command::Delete, key, "")
+    }
+
+    pub fn usage(self) -> Self {
+        self.request(Command::Usage, "", "")
+    }
+
+    /// Sends every queued request and returns the raw replies in order.
+    pub async fn execute(self) -> Result<Vec<Response>, BlinkError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.count == 0 {
+            return Ok(Vec::new());
+        }
+        self.client
+            .execute(&self.lines, self.count, self.client.pool.config.request_timeout)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{BlinkStorage, MemoryEngine};
+    use crate::server::{serve_tcp, ServerState};
+    use tokio::net::TcpListener;
+
+    async fn start_server() -> (String, Arc<ServerState>) {
+        start_server_with_limit(1024).await
+    }
+
+    async fn start_server_with_limit(limit_bytes: u64) -> (String, Arc<ServerState>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(ServerState::new(Arc::new(MemoryEngine::new(limit_bytes).unwrap())));
+        tokio::spawn(serve_tcp(listener, state.clone()));
+        (addr, state)
+    }
+
+    #[tokio::test]
+    async fn typed_commands() {
+        let (addr, _) = start_server().await;
+        let client = BlinkClient::connect(addr).await.unwrap();
+        client.ping().await.unwrap();
+        assert_eq!(client.get("k").await.unwrap(), None);
+        client.set("k", b"hello world").await.unwrap();
+        assert_eq!(&client.get("k").await.unwrap().unwrap()[..], b"hello world");
+        assert_eq!(client.usage().await.unwrap(), 12);
+        assert!(client
+            .info()
+            .await
+            .unwrap()
+            .contains(&("used_bytes".to_owned(), "12".to_owned())));
+        assert!(client.delete("k").await.unwrap());
+        assert!(!client.delete("k").await.unwrap());
+        assert!(client.set("bad key", b"v").await.is_err());
+        assert!(client.set("k", b"line\nbreak").await.is_err());
+
+        assert!(client.hset("h", "name", b"ada lovelace").await.unwrap());
+        assert!(client.hset("h", "empty", b"").await.unwrap());
+        assert_eq!(&client.hget("h", "name").await.unwrap().unwrap()[..], b"ada lovelace");
+        assert_eq!(
+            client.hgetall("h").await.unwrap(),
+            vec![
+                ("empty".to_owned(), Bytes::new()),
+                ("name".to_owned(), Bytes::from_static(b"ada lovelace")),
+            ]
+        );
+        assert_eq!(client.hdel("h", &["name", "nope"]).await.unwrap(), 1);
+
+        assert_eq!(client.push("q", End::Right, b"second").await.unwrap(), 1);
+        assert_eq!(client.push("q", End::Left, b"first").await.unwrap(), 2);
+        assert_eq!(client.lrange("q", 0, -1).await.unwrap(), ["first", "second"]);
+        assert_eq!(&client.pop("q", End::Right).await.unwrap().unwrap()[..], b"second");
+        assert_eq!(&client.blpop("q", None).await.unwrap().unwrap()[..], b"first");
+        assert_eq!(client.blpop("q", Some(Duration::from_millis(50))).await.unwrap(), None);
+        assert!(matches!(client.get("h").await, Err(BlinkError::Server(msg)) if msg.starts_with("WRONGTYPE")));
+
+        client.set("old-name", b"v").await.unwrap();
+        client.rename("old-name", "new-name").await.unwrap();
+        assert!(!client.renamenx("new-name", "h").await.unwrap());
+        assert_eq!(client.exists(&["old-name", "new-name", "h"]).await.unwrap(), 2);
+        assert_eq!(client.touch(&["new-name"]).await.unwrap(), 1);
+        assert_eq!(client.key_type("h").await.unwrap().as_deref(), Some("hash"));
+        assert_eq!(client.key_type("old-name").await.unwrap(), None);
+        assert!(client.dbsize().await.unwrap() >= 2);
+
+        let first = client.rate_limit("rl", 2, Duration::from_secs(60), 1).await.unwrap();
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        let denied = client.rate_limit("rl", 2, Duration::from_secs(60), 2).await.unwrap();
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, Duration::from_secs(30));
+
+        let ttl = Duration::from_secs(30);
+        let token = client.lock("job", ttl, None).await.unwrap().unwrap();
+        assert_eq!(client.lock("job", ttl, Some("other")).await.unwrap(), None);
+        let owner = token.to_string();
+        assert_eq!(client.extend_lock("job", &owner, ttl).await.unwrap(), Some(token));
+        assert!(client.unlock("job", &owner).await.unwrap());
+        assert!(client.lock("job", ttl, Some("other")).await.unwrap().unwrap() > token);
+
+        assert_eq!(client.getset("token", b"t1").await.unwrap(), None);
+        assert_eq!(&client.getex("token", Expiry::default()).await.unwrap().unwrap()[..], b"t1");
+        assert_eq!(&client.getdel("token").await.unwrap().unwrap()[..], b"t1");
+        assert_eq!(client.getdel("token").await.unwrap(), None);
+
+        assert_eq!(client.append("log", b"started").await.unwrap(), 7);
+        assert_eq!(client.append("log", b"-fine").await.unwrap(), 12);
+        assert_eq!(client.setrange("log", 8, b"FINE").await.unwrap(), 12);
+        assert_eq!(&client.getrange("log", -4, -1).await.unwrap()[..], b"FINE");
+        assert_eq!(client.strlen("log").await.unwrap(), 12);
+
+        assert_eq!(client.sadd("s", &["b", "a", "b"]).await.unwrap(), 2);
+        assert!(client.sismember("s", "a").await.unwrap());
+        assert_eq!(client.smembers("s").await.unwrap(), ["a", "b"]);
+        assert_eq!(client.srem("s", &["a"]).await.unwrap(), 1);
+
+        assert_eq!(client.zadd("z", &[(2.5, "b"), (-1.0, "a")]).await.unwrap(), 2);
+        assert_eq!(client.zincrby("z", 10.0, "a").await.unwrap(), 9.0);
+        assert_eq!(
+            client.zrange("z", 0, -1).await.unwrap(),
+            [("b".to_owned(), 2.5), ("a".to_owned(), 9.0)]
+        );
+        assert_eq!(
+            client
+                .zrangebyscore("z", Bound::Excluded(2.5), Bound::Unbounded)
+                .await
+                .unwrap(),
+            [("a".to_owned(), 9.0)]
+        );
+        assert_eq!(client.zrem("z", &["a", "b"]).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn sketch_commands() {
+        let (addr, _) = start_server_with_limit(64 * 1024).await;
+        let client = BlinkClient::connect(addr).await.unwrap();
+        assert!(client.pfadd("visitors:mon", &["ada", "alan", "grace"]).await.unwrap());
+        assert!(!client.pfadd("visitors:mon", &["ada"]).await.unwrap());
+        client.pfadd("visitors:tue", &["ada", "edsger"]).await.unwrap();
+        assert_eq!(client.pfcount(&["visitors:mon"]).await.unwrap(), 3);
+        assert_eq!(client.pfcount(&["visitors:mon", "visitors:tue"]).await.unwrap(), 4);
+        client.pfmerge("visitors:week", &["visitors:mon", "visitors:tue"]).await.unwrap();
+        assert_eq!(client.pfcount(&["visitors:week"]).await.unwrap(), 4);
+        assert_eq!(client.key_type("visitors:week").await.unwrap().as_deref(), Some("hll"));
+
+        assert!(client.bf_reserve("seen", 0.001, 1000).await.unwrap());
+        assert!(!client.bf_reserve("seen", 0.001, 1000).await.unwrap());
+        assert!(client.bf_add("seen", b"https://example.com/a b").await.unwrap());
+        assert!(!client.bf_add("seen", b"https://example.com/a b").await.unwrap());
+        assert!(client.bf_exists("seen", b"https://example.com/a b").await.unwrap());
+        assert!(!client.bf_exists("unseen", b"x").await.unwrap());
+        assert!(client.bf_reserve("bad", 2.0, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_commands() {
+        let (addr, _) = start_server_with_limit(64 * 1024).await;
+        let client = BlinkClient::connect(addr).await.unwrap();
+        let first = client.xadd("orders", None, &[("sku", "A-1"), ("qty", "2")], None).await.unwrap();
+        let second = client.xadd("orders", None, &[("sku", "B-7")], Some(10)).await.unwrap();
+        assert!(second > first);
+        assert_eq!(client.xlen("orders").await.unwrap(), 2);
+        let all = client.xrange("orders", StreamId::MIN, StreamId::MAX, None).await.unwrap();
+        assert_eq!(all[0].id, first);
+        assert_eq!(all[0].fields[1], ("qty".to_owned(), Bytes::from_static(b"2")));
+        assert_eq!(client.xread("orders", first, Some(5)).await.unwrap()[0].id, second);
+
+        client.xgroup_create("orders", "billing", Some(StreamId::MIN)).await.unwrap();
+        assert!(client.xgroup_create("orders", "billing", None).await.is_err());
+        let handed = client.xreadgroup("orders", "billing", "b1", None, Some(1)).await.unwrap();
+        assert_eq!(handed[0].id, first);
+        let pending = client.xpending("orders", "billing").await.unwrap();
+        assert_eq!((pending[0].id, pending[0].consumer.as_str()), (first, "b1"));
+        assert_eq!(
+            client.xreadgroup("orders", "billing", "b1", Some(StreamId::MIN), None).await.unwrap(),
+            handed
+        );
+        assert_eq!(client.xack("orders", "billing", &[first]).await.unwrap(), 1);
+
+        let waiting = {
+            let client = client.clone();
+            tokio::spawn(async move {
+                client
+                    .xreadgroup_blocking("orders", "billing", "b2", None, Some(Duration::from_secs(5)))
+                    .await
+            })
+        };
+        // The next new entry for the group is `second`, already there.
+        assert_eq!(waiting.await.unwrap().unwrap()[0].id, second);
+        let timed_out = client
+            .xread_blocking("orders", None, None, Some(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        assert!(timed_out.is_empty());
+        assert_eq!(client.xtrim("orders", 0).await.unwrap(), 2);
+        assert!(client.xgroup_destroy("orders", "billing").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pipeline_returns_replies_in_order() {
+        let (addr, _) = start_server().await;
+        let client = BlinkClient::connect(addr).await.unwrap();
+        let replies = client
+            .pipeline()
+            .set("a", b"1")
+            .get("a")
+            .delete("a")
+            .get("a")
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(
+            replies,
+            vec![
+                Response::Ok,
+                Response::Value(Bytes::from_static(b"1")),
+                Response::Ok,
+                Response::NotFound,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_applies_all_or_nothing() {
+        let (addr, state) = start_server().await;
+        let client = BlinkClient::connect(addr).await.unwrap();
+        let replies = client
+            .transaction(&[(Command::Set, "a", "1"), (Command::Get, "a", "")])
+            .await
+            .unwrap();
+        assert_eq!(replies, vec![Response::Ok, Response::Value(Bytes::from_static(b"1"))]);
+
+        let failed = client
+            .transaction(&[(Command::Delete, "a", ""), (Command::SetEx, "a", "x")])
+            .await;
+        assert!(matches!(failed, Err(BlinkError::Server(_))));
+        assert!(state.store().get("a").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_server_closes_connection() {
+        let (addr, state) = start_server().await;
+        state.set_idle_timeout(Duration::from_secs(1));
+        let client = BlinkClient::connect(addr).await.unwrap();
+        client.set("k", b"v").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1300)).await;
+        assert_eq!(&client.get("k").await.unwrap().unwrap()[..], b"v");
+    }
+
+    #[tokio::test]
+    async fn timed_out_writes_are_not_sent_again() {
+        // Reads requests and never answers, as if the reply were lost.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut lines = BufReader::new(stream).lines();
+                    while let Ok(Some(_)) = lines.next_line().await {
+                        counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+        let mut config = ClientConfig::new(addr);
+        config.request_timeout = Duration::from_millis(100);
+        let client = BlinkClient::connect_with(config).await.unwrap();
+        assert!(matches!(client.append("log", b"once").await, Err(BlinkError::Timeout(_))));
+        assert_eq!(received.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_the_pool() {
+        let (addr, _) = start_server().await;
+        let client = BlinkClient::connect(addr).await.unwrap();
+        let tasks: Vec<_> = (0..20)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let key = format!("k{}", i);
+                    client.set(&key, b"v").await.unwrap();
+                    client.get(&key).await.unwrap()
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert!(task.await.unwrap().is_some());
+        }
+        assert!(client.pool.idle.lock().await.len() <= client.pool.config.pool_size);
+    }
+
+    #[tokio::test]
+    async fn publish_reaches_subscriber_connection() {
+        let (addr, state) = start_server().await;
+        state.set_idle_timeout(Duration::from_millis(200));
+        let client = BlinkClient::connect(addr).await.unwrap();
+        let mut sub = client.subscribe(&["invalidate"]).await.unwrap();
+        sub.psubscribe(&["user:*"]).await.unwrap();
+
+        // Subscribers are exempt from the idle timeout.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(client.publish("invalidate", b"k1").await.unwrap(), 1);
+        assert_eq!(client.publish("user:7", b"").await.unwrap(), 1);
+        assert_eq!(client.publish("nobody", b"x").await.unwrap(), 0);
+
+        let m = sub.next_message().await.unwrap();
+        assert_eq!((m.channel.as_str(), &m.payload[..]), ("invalidate", &b"k1"[..]));
+        let m = sub.next_message().await.unwrap();
+        assert_eq!(m.pattern.as_deref(), Some("user:*"));
+
+        sub.unsubscribe(&[]).await.unwrap();
+        assert_eq!(client.publish("invalidate", b"k2").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn wait_for_change_outlasts_request_timeout() {
+        let (addr, state) = start_server().await;
+        let mut config = ClientConfig::new(addr);
+        config.request_timeout = Duration::from_millis(100);
+        let client = BlinkClient::connect_with(config).await.unwrap();
+        assert_eq!(client.wait_for_change("k", 0, Duration::ZERO).await.unwrap().version, 0);
+
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            state.store().set("k", Bytes::from_static(b"new")).unwrap();
+        });
+        let changed = client
+            .wait_for_change("k", 0, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(changed.value.as_deref(), Some(&b"new"[..]));
+        assert_ne!(changed.version, 0);
+        writer.await.unwrap();
+    }
+}
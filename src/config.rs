@@ -0,0 +1,389 @@
+//! Configuration file, layered settings, and `CONFIG REWRITE` support.
+//!
+//! Settings come from, in order of precedence: command-line flags, `BLINK_*`
+//! environment variables, the TOML config file, then built-in defaults.
+//! Runtime parameter names match the file's keys, so a rewritten file can be
+//! loaded again on the next start.
+
+use crate::engine::{EvictionPolicy, DEFAULT_EVICTION_SAMPLES};
+use crate::error::BlinkError;
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_MEMORY_LIMIT: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_RETENTION_MINUTES: u64 = 60;
+pub const DEFAULT_LOG_LEVEL: &str = "blink_store=info,info";
+pub const DEFAULT_LEASE_TIMEOUT_MS: u64 = 5_000;
+
+/// Parameters exposed through `CONFIG GET` / `CONFIG SET`.
+pub const PARAMS: &[&str] = &[
+    "memory_limit",
+    "eviction_policy",
+    "eviction_samples",
+    "log_level",
+    "idle_timeout_secs",
+    "lease_timeout_ms",
+];
+
+/// One source of settings; every field is optional so layers can be merged.
+/// This is also the schema of the TOML config file.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    /// TCP listen address (e.g. `0.0.0.0:8765`).
+    pub tcp: Option<String>,
+    /// Unix socket path.
+    pub unix: Option<PathBuf>,
+    pub memory_limit: Option<u64>,
+    pub eviction_policy: Option<String>,
+    pub eviction_samples: Option<usize>,
+    pub idle_timeout_secs: Option<u64>,
+    pub lease_timeout_ms: Option<u64>,
+    /// Number of numbered databases.
+    pub databases: Option<usize>,
+    /// Extra named databases as `NAME` or `NAME=BYTES`.
+    pub db: Option<Vec<String>>,
+    /// Soft quotas as `PREFIX=BYTES`.
+    pub quota: Option<Vec<String>>,
+    pub acl_file: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    pub log_level: Option<String>,
+    pub retention_minutes: Option<u64>,
+}
+
+impl ConfigLayer {
+    /// Parses TOML config file text.
+    pub fn parse(text: &str) -> Result<Self, BlinkError> {
+        toml::from_str(text).map_err(|e| BlinkError::Config(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, BlinkError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| BlinkError::Config(format!("read {:?}: {}", path, e)))?;
+        toml::from_str(&text).map_err(|e| BlinkError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Fills every unset field from `lower`, which has lower precedence.
+    pub fn or(self, lower: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            tcp: self.tcp.or(lower.tcp),
+            unix: self.unix.or(lower.unix),
+            memory_limit: self.memory_limit.or(lower.memory_limit),
+            eviction_policy: self.eviction_policy.or(lower.eviction_policy),
+            eviction_samples: self.eviction_samples.or(lower.eviction_samples),
+            idle_timeout_secs: self.idle_timeout_secs.or(lower.idle_timeout_secs),
+            lease_timeout_ms: self.lease_timeout_ms.or(lower.lease_timeout_ms),
+            databases: self.databases.or(lower.databases),
+            db: self.db.or(lower.db),
+            quota: self.quota.or(lower.quota),
+            acl_file: self.acl_file.or(lower.acl_file),
+            log_dir: self.log_dir.or(lower.log_dir),
+            log_level: self.log_level.or(lower.log_level),
+            retention_minutes: self.retention_minutes.or(lower.retention_minutes),
+        }
+    }
+}
+
+/// A named database from `--db NAME[=BYTES]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseSpec {
+    pub name: String,
+    /// Memory limit; `None` means the global `memory_limit`.
+    pub memory_limit: Option<u64>,
+}
+
+/// A soft quota from `--quota PREFIX=BYTES`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaSpec {
+    pub prefix: String,
+    pub limit_bytes: u64,
+}
+
+/// Effective, validated settings with defaults applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub tcp: Option<String>,
+    pub unix: Option<PathBuf>,
+    pub memory_limit: u64,
+    pub eviction_policy: EvictionPolicy,
+    pub eviction_samples: usize,
+    pub idle_timeout_secs: u64,
+    pub lease_timeout_ms: u64,
+    pub databases: usize,
+    pub named_dbs: Vec<DatabaseSpec>,
+    pub quotas: Vec<QuotaSpec>,
+    pub acl_file: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    pub log_level: String,
+    pub retention_minutes: u64,
+}
+
+fn parse_database_spec(spec: &str) -> Result<DatabaseSpec, BlinkError> {
+    let (name, limit) = match spec.split_once('=') {
+        Some((name, bytes)) => (
+            name,
+            Some(bytes.parse::<u64>().map_err(|_| {
+                BlinkError::Config(format!("invalid memory limit in db '{}'", spec))
+            })?),
+        ),
+        None => (spec, None),
+    };
+    if name.is_empty() {
+        return Err(BlinkError::Config(format!("empty database name in '{}'", spec)));
+    }
+    Ok(DatabaseSpec {
+        name: name.to_owned(),
+        memory_limit: limit,
+    })
+}
+
+fn parse_quota_spec(spec: &str) -> Result<QuotaSpec, BlinkError> {
+    let (prefix, bytes) = spec
+        .split_once('=')
+        .ok_or_else(|| BlinkError::Config(format!("quota expects PREFIX=BYTES, got '{}'", spec)))?;
+    let limit_bytes = bytes
+        .parse::<u64>()
+        .map_err(|_| BlinkError::Config(format!("invalid byte budget in quota '{}'", spec)))?;
+    if prefix.is_empty() {
+        return Err(BlinkError::Config(format!("empty quota prefix in '{}'", spec)));
+    }
+    Ok(QuotaSpec {
+        prefix: prefix.to_owned(),
+        limit_bytes,
+    })
+}
+
+impl Settings {
+    /// Applies defaults to a merged layer and validates every value.
+    pub fn resolve(layer: ConfigLayer) -> Result<Self, BlinkError> {
+        let eviction_policy = match &layer.eviction_policy {
+            Some(p) => p.parse()?,
+            None => EvictionPolicy::AllKeysLru,
+        };
+        let eviction_samples = layer.eviction_samples.unwrap_or(DEFAULT_EVICTION_SAMPLES);
+        if eviction_samples == 0 {
+            return Err(BlinkError::Config("eviction_samples must be at least 1".into()));
+        }
+        let lease_timeout_ms = layer.lease_timeout_ms.unwrap_or(DEFAULT_LEASE_TIMEOUT_MS);
+        if lease_timeout_ms == 0 {
+            return Err(BlinkError::Config("lease_timeout_ms must be at least 1".into()));
+        }
+        let databases = layer.databases.unwrap_or(1);
+        if databases == 0 {
+            return Err(BlinkError::Config("databases must be at least 1".into()));
+        }
+        let log_level = layer.log_level.unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_owned());
+        tracing_subscriber::EnvFilter::try_new(&log_level)
+            .map_err(|e| BlinkError::Config(format!("log_level '{}': {}", log_level, e)))?;
+
+        Ok(Settings {
+            tcp: layer.tcp,
+            unix: layer.unix,
+            memory_limit: layer.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT),
+            eviction_policy,
+            eviction_samples,
+            idle_timeout_secs: layer.idle_timeout_secs.unwrap_or(0),
+            lease_timeout_ms,
+            databases,
+            named_dbs: layer
+                .db
+                .unwrap_or_default()
+                .iter()
+                .map(|s| parse_database_spec(s))
+                .collect::<Result<_, _>>()?,
+            quotas: layer
+                .quota
+                .unwrap_or_default()
+                .iter()
+                .map(|s| parse_quota_spec(s))
+                .collect::<Result<_, _>>()?,
+            acl_file: layer.acl_file,
+            log_dir: layer.log_dir,
+            log_level,
+            retention_minutes: layer.retention_minutes.unwrap_or(DEFAULT_RETENTION_MINUTES),
+        })
+    }
+}
+
+fn toml_array(items: impl Iterator<Item = String>) -> String {
+    let items: Vec<String> = items.map(|i| toml_value(&i)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Renders the effective settings as a loadable TOML config file.
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(tcp) = &self.tcp {
+            writeln!(f, "tcp = {}", toml_value(tcp))?;
+        }
+        if let Some(unix) = &self.unix {
+            writeln!(f, "unix = {}", toml_value(&unix.display().to_string()))?;
+        }
+        writeln!(f, "memory_limit = {}", self.memory_limit)?;
+        writeln!(f, "eviction_policy = {}", toml_value(self.eviction_policy.as_str()))?;
+        writeln!(f, "eviction_samples = {}", self.eviction_samples)?;
+        writeln!(f, "idle_timeout_secs = {}", self.idle_timeout_secs)?;
+        writeln!(f, "lease_timeout_ms = {}", self.lease_timeout_ms)?;
+        writeln!(f, "databases = {}", self.databases)?;
+        writeln!(
+            f,
+            "db = {}",
+            toml_array(self.named_dbs.iter().map(|d| match d.memory_limit {
+                Some(limit) => format!("{}={}", d.name, limit),
+                None => d.name.clone(),
+            }))
+        )?;
+        writeln!(
+            f,
+            "quota = {}",
+            toml_array(
+                self.quotas
+                    .iter()
+                    .map(|q| format!("{}={}", q.prefix, q.limit_bytes))
+            )
+        )?;
+        if let Some(acl) = &self.acl_file {
+            writeln!(f, "acl_file = {}", toml_value(&acl.display().to_string()))?;
+        }
+        if let Some(dir) = &self.log_dir {
+            writeln!(f, "log_dir = {}", toml_value(&dir.display().to_string()))?;
+        }
+        writeln!(f, "log_level = {}", toml_value(&self.log_level))?;
+        writeln!(f, "retention_minutes = {}", self.retention_minutes)
+    }
+}
+
+/// Renders a value as a TOML literal: integers as-is, everything else quoted.
+fn toml_value(value: &str) -> String {
+    if value.parse::<u64>().is_ok() {
+        value.to_owned()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Returns the key of a top-level `key = value` line, if it is one.
+fn line_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once('=')?;
+    let key = key.trim();
+    (!key.is_empty() && !key.starts_with('#')).then_some(key)
+}
+
+/// Updates `values` in config file text: existing top-level keys are
+/// replaced in place, missing ones are inserted before the first table.
+/// Comments and all other lines are kept.
+pub fn rewrite_text(text: &str, values: &[(String, String)]) -> String {
+    let mut lines: Vec<String> = Vec::with_capacity(text.lines().count() + values.len());
+    let mut written = vec![false; values.len()];
+    let mut first_table: Option<usize> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') && first_table.is_none() {
+            first_table = Some(lines.len());
+        }
+        if first_table.is_none() {
+            if let Some(i) = line_key(trimmed).and_then(|k| values.iter().position(|(n, _)| n == k)) {
+                let (name, value) = &values[i];
+                lines.push(format!("{} = {}", name, toml_value(value)));
+                written[i] = true;
+                continue;
+            }
+        }
+        lines.push(line.to_owned());
+    }
+
+    let missing: Vec<String> = values
+        .iter()
+        .zip(&written)
+        .filter(|(_, done)| !**done)
+        .map(|((name, value), _)| format!("{} = {}", name, toml_value(value)))
+        .collect();
+    let at = first_table.unwrap_or(lines.len());
+    lines.splice(at..at, missing);
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// Writes `values` back into the config file at `path` (creating it if needed).
+pub fn rewrite(path: &Path, values: &[(String, String)]) -> Result<(), BlinkError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(BlinkError::Config(format!("read {:?}: {}", path, e))),
+    };
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, rewrite_text(&text, values))
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .map_err(|e| BlinkError::Config(format!("write {:?}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_replaces_and_inserts_keys() {
+        let text = "# blink-store\nmemory_limit = 100\ntcp = \"0.0.0.0:8765\"\n\n[acl]\nmemory_limit = 5\n";
+        let out = rewrite_text(
+            text,
+            &[
+                ("memory_limit".into(), "2048".into()),
+                ("eviction_policy".into(), "noeviction".into()),
+            ],
+        );
+        assert_eq!(
+            out,
+            "# blink-store\nmemory_limit = 2048\ntcp = \"0.0.0.0:8765\"\n\neviction_policy = \"noeviction\"\n[acl]\nmemory_limit = 5\n"
+        );
+    }
+
+    #[test]
+    fn layers_follow_precedence() {
+        let file = ConfigLayer::parse(
+            "tcp = \"0.0.0.0:8765\"\nmemory_limit = 100\nretention_minutes = 5\nquota = [\"a:=10\"]\n",
+        )
+        .unwrap();
+        let env = ConfigLayer {
+            memory_limit: Some(200),
+            ..Default::default()
+        };
+        let cli = ConfigLayer {
+            retention_minutes: Some(1),
+            ..Default::default()
+        };
+        let settings = Settings::resolve(cli.or(env).or(file)).unwrap();
+        assert_eq!(settings.tcp.as_deref(), Some("0.0.0.0:8765"));
+        assert_eq!(settings.memory_limit, 200);
+        assert_eq!(settings.retention_minutes, 1);
+        assert_eq!(settings.eviction_samples, DEFAULT_EVICTION_SAMPLES);
+        assert_eq!(
+            settings.quotas,
+            vec![QuotaSpec {
+                prefix: "a:".into(),
+                limit_bytes: 10
+            }]
+        );
+    }
+
+    #[test]
+    fn rendered_settings_load_back() {
+        let layer = ConfigLayer::parse(
+            "unix = \"/tmp/b.sock\"\ndb = [\"staging=1024\", \"load\"]\neviction_policy = \"noeviction\"\n",
+        )
+        .unwrap();
+        let settings = Settings::resolve(layer).unwrap();
+        let reparsed = Settings::resolve(ConfigLayer::parse(&settings.to_string()).unwrap()).unwrap();
+        assert_eq!(settings, reparsed);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(ConfigLayer::parse("memory_limt = 5").is_err());
+        assert!(Settings::resolve(ConfigLayer::parse("eviction_policy = \"lfu\"").unwrap()).is_err());
+        assert!(Settings::resolve(ConfigLayer::parse("quota = [\"nolimit\"]").unwrap()).is_err());
+    }
+}
@@ -5,74 +5,2538 @@
 //! we sample a handful of entries and evict the one with the lowest counter.
 
 use crate::error::BlinkError;
-use bytes::Bytes;
+use crate::singleflight::SingleFlight;
+use crate::sketch::{BloomFilter, HyperLogLog};
+use crate::stream::{PendingEntry, Stream, StreamEntry, StreamId};
+use bytes::{Bytes, BytesMut};
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
-use tracing::trace;
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::time::Instant;
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::{debug, trace};
 
 /// Abstraction layer for storage backends.
 pub trait BlinkStorage: Send + Sync {
     fn get(&self, key: &str) -> Result<Option<Bytes>, BlinkError>;
     fn set(&self, key: &str, value: Bytes) -> Result<(), BlinkError>;
     fn delete(&self, key: &str) -> Result<bool, BlinkError>;
+    /// Stores `value` and returns the value it replaced, in one step.
+    fn getset(&self, key: &str, value: Bytes) -> Result<Option<Bytes>, BlinkError>;
+    /// Removes the key and returns its value, in one step; of several
+    /// concurrent callers only one gets the value.
+    fn getdel(&self, key: &str) -> Result<Option<Bytes>, BlinkError>;
+    /// Reads the value and replaces its expiry (`Expiry::default()` makes
+    /// it persistent), in one step.
+    fn getex(&self, key: &str, expiry: Expiry) -> Result<Option<Bytes>, BlinkError>;
     fn current_usage_bytes(&self) -> Result<u64, BlinkError>;
 }
 
-fn entry_size(key: &str, value: &[u8]) -> u64 {
-    (key.len() + value.len()) as u64
+fn entry_size(key: &str, value: &Value) -> u64 {
+    key.len() as u64 + value.size()
 }
 
-const EVICTION_SAMPLES: usize = 5;
+fn field_size(field: &str, value: &[u8]) -> u64 {
+    (field.len() + value.len()) as u64
+}
+
+fn member_size(member: &str) -> u64 {
+    (member.len() + std::mem::size_of::<f64>()) as u64
+}
+
+fn removal_stripe(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % REMOVAL_STRIPES
+}
+
+fn lock_size(owner_len: usize) -> u64 {
+    (owner_len + std::mem::size_of::<u64>()) as u64
+}
+
+/// Entries sampled per eviction round unless reconfigured.
+pub const DEFAULT_EVICTION_SAMPLES: usize = 5;
+
+/// Entries looked at per sample when sampling one quota group, so a rare
+/// prefix cannot make an eviction round walk the whole keyspace.
+const EVICTION_SCAN_FACTOR: usize = 16;
+
+/// What `set` does when the memory limit would be exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used of a few sampled entries (default).
+    AllKeysLru,
+    /// Never evict; writes that do not fit fail with `AtCapacity`.
+    NoEviction,
+}
+
+impl EvictionPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::NoEviction => "noeviction",
+        }
+    }
+}
+
+impl std::str::FromStr for EvictionPolicy {
+    type Err = BlinkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("allkeys-lru") {
+            Ok(EvictionPolicy::AllKeysLru)
+        } else if s.eq_ignore_ascii_case("noeviction") {
+            Ok(EvictionPolicy::NoEviction)
+        } else {
+            Err(BlinkError::Config(format!(
+                "unknown eviction policy '{}' (expected allkeys-lru or noeviction)",
+                s
+            )))
+        }
+    }
+}
+
+/// A byte budget for every key starting with `prefix` (one tenant).
+struct QuotaGroup {
+    prefix: String,
+    limit_bytes: u64,
+    usage: AtomicU64,
+}
+
+/// Point-in-time usage of one quota group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub prefix: String,
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+}
+
+/// What a key holds.
+#[derive(Clone)]
+enum Value {
+    Str(Bytes),
+    /// Field → value; each field is counted as its name plus its value.
+    Hash(HashMap<String, Bytes>),
+    List(VecDeque<Bytes>),
+    Set(HashSet<String>),
+    /// Each member is counted as its name plus 8 bytes of score.
+    SortedSet(SortedSet),
+    /// `rate_limit` state: when the bucket will be full again. Counted as
+    /// 8 bytes.
+    RateLimit(Instant),
+    /// A `lock` held by `owner`; counted as the owner plus 8 bytes.
+    Lock { owner: String, token: u64 },
+    /// Counted as its fixed register array.
+    HyperLogLog(Box<HyperLogLog>),
+    /// Counted as its bit array, fixed when it is created.
+    Bloom(Box<BloomFilter>),
+    /// Counted as described on `Stream`.
+    Stream(Stream),
+}
+
+impl Value {
+    /// Bytes counted against the memory limit, excluding the key.
+    fn size(&self) -> u64 {
+        match self {
+            Value::Str(bytes) => bytes.len() as u64,
+            Value::Hash(fields) => fields.iter().map(|(f, v)| field_size(f, v)).sum(),
+            Value::List(items) => items.iter().map(|v| v.len() as u64).sum(),
+            Value::Set(members) => members.iter().map(|m| m.len() as u64).sum(),
+            Value::SortedSet(zset) => zset.scores.keys().map(|m| member_size(m)).sum(),
+            Value::RateLimit(_) => std::mem::size_of::<u64>() as u64,
+            Value::Lock { owner, .. } => lock_size(owner.len()),
+            Value::HyperLogLog(_) => HyperLogLog::SIZE as u64,
+            Value::Bloom(filter) => filter.size() as u64,
+            Value::Stream(stream) => stream.size(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::RateLimit(_) => "ratelimit",
+            Value::Lock { .. } => "lock",
+            Value::HyperLogLog(_) => "hll",
+            Value::Bloom(_) => "bloom",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// A collection with nothing left in it; such keys are removed.
+    fn is_empty_collection(&self) -> bool {
+        match self {
+            Value::Str(_)
+            | Value::RateLimit(_)
+            | Value::Lock { .. }
+            | Value::HyperLogLog(_)
+            | Value::Bloom(_)
+            | Value::Stream(_) => false,
+            Value::Hash(fields) => fields.is_empty(),
+            Value::List(items) => items.is_empty(),
+            Value::Set(members) => members.is_empty(),
+            Value::SortedSet(zset) => zset.scores.is_empty(),
+        }
+    }
+}
+
+/// A score ordered with `f64::total_cmp`; scores are never NaN.
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members with scores, ordered by score and then by member name.
+#[derive(Clone, Default)]
+struct SortedSet {
+    scores: HashMap<String, f64>,
+    by_score: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    /// Sets `member`'s score; returns its previous score. `-0.0` is stored
+    /// as `0.0`, which `total_cmp` would otherwise order below it.
+    fn insert(&mut self, member: &str, score: f64) -> Option<f64> {
+        let score = if score == 0.0 { 0.0 } else { score };
+        let old = self.scores.insert(member.to_owned(), score);
+        if let Some(old) = old {
+            self.by_score.remove(&(Score(old), member.to_owned()));
+        }
+        self.by_score.insert((Score(score), member.to_owned()));
+        old
+    }
+
+    fn remove(&mut self, member: &str) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.by_score.remove(&(Score(score), member.to_owned()));
+        Some(score)
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (String, f64)> + '_ {
+        self.by_score.iter().map(|(s, m)| (m.clone(), s.0))
+    }
+
+    /// Members with a score within `min..max`, lowest first.
+    fn range_by_score(&self, min: Bound<f64>, max: Bound<f64>) -> Vec<(String, f64)> {
+        let from = match min {
+            Bound::Included(s) | Bound::Excluded(s) => s,
+            Bound::Unbounded => f64::NEG_INFINITY,
+        };
+        self.by_score
+            .range((Score(from), String::new())..)
+            .skip_while(|(s, _)| matches!(min, Bound::Excluded(m) if s.0 <= m))
+            .take_while(|(s, _)| match max {
+                Bound::Included(m) => s.0 <= m,
+                Bound::Excluded(m) => s.0 < m,
+                Bound::Unbounded => true,
+            })
+            .map(|(s, m)| (m.clone(), s.0))
+            .collect()
+    }
+}
+
+/// Which end of a list to push to or pop from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+/// Takes over the buffer of `bytes` if nothing else shares it, otherwise
+/// copies it.
+fn into_mut(bytes: Bytes) -> BytesMut {
+    bytes
+        .try_into_mut()
+        .unwrap_or_else(|shared| BytesMut::from(&shared[..]))
+}
+
+/// Resolves `LRANGE`-style indexes (negative counts from the end, `stop`
+/// inclusive) into a `start..end` range of a list of `len` items.
+fn list_range(len: usize, start: i64, stop: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let resolve = |i: i64| if i < 0 { (len + i).max(0) } else { i };
+    let start = resolve(start);
+    let end = (resolve(stop) + 1).min(len);
+    if start >= end {
+        return 0..0;
+    }
+    start as usize..end as usize
+}
+
+/// Stored value plus the metadata eviction and expiry need.
+#[derive(Clone)]
+struct Record {
+    value: Value,
+    /// Access counter; the lowest of a sample is evicted first.
+    counter: u64,
+    /// From this point reads report the value as stale.
+    stale_at: Option<Instant>,
+    /// From this point the entry is gone.
+    expires_at: Option<Instant>,
+    /// Unique per write; see `Versioned`.
+    version: u64,
+    /// Bytes the entry is accounted for (`entry_size`), kept up to date by
+    /// every change so writes to large collections need not re-measure.
+    size: u64,
+}
+
+impl Record {
+    fn is_stale(&self, now: Instant) -> bool {
+        self.stale_at.is_some_and(|t| t <= now)
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+
+    /// Live locks and rate limiters are only removed when they expire:
+    /// evicting a lock would let a second owner take it while the first
+    /// still works, and evicting a limiter would reset its budget.
+    fn is_evictable(&self, now: Instant) -> bool {
+        self.is_expired(now) || !matches!(self.value, Value::Lock { .. } | Value::RateLimit(_))
+    }
+}
+
+/// Outcome of `MemoryEngine::rate_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub allowed: bool,
+    /// Further requests of cost 1 that would be allowed right now.
+    pub remaining: u64,
+    /// How long until this request would be allowed; zero if it was.
+    pub retry_after: Duration,
+    /// How long until the full limit is available again.
+    pub reset_after: Duration,
+}
+
+/// Soft and hard time-to-live for `MemoryEngine::set_with_expiry`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Expiry {
+    /// After this, reads still return the value but report it as stale so
+    /// one caller can refresh it (stale-while-revalidate).
+    pub soft: Option<Duration>,
+    /// After this, the entry is removed.
+    pub hard: Option<Duration>,
+}
+
+impl Expiry {
+    fn check(&self) -> Result<(), BlinkError> {
+        if let (Some(soft), Some(hard)) = (self.soft, self.hard) {
+            if soft > hard {
+                return Err(BlinkError::Config(
+                    "soft expiry must not be later than hard expiry".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a value read from the engine is past its soft expiry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    Fresh,
+    Stale,
+}
+
+/// A key's value and version, as compared by `MemoryEngine::wait_for_change`.
+/// Every write gives the key a new version; a missing key has version 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned {
+    pub version: u64,
+    pub value: Option<Bytes>,
+}
+
+/// Longest `wait_for_change` honours; longer timeouts are cut to this.
+pub const MAX_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A waiter's registration in `MemoryEngine::watchers`. Dropping it (the
+/// wait finished, timed out or was cancelled) removes the key's entry once
+/// no other waiter uses it, so abandoned waits leave nothing behind.
+struct KeyWatch<'a> {
+    engine: &'a MemoryEngine,
+    key: &'a str,
+    changed: Option<watch::Receiver<()>>,
+}
+
+impl<'a> KeyWatch<'a> {
+    fn register(engine: &'a MemoryEngine, key: &'a str) -> Self {
+        let changed = engine
+            .watchers
+            .entry(key.to_owned())
+            .or_insert_with(|| watch::channel(()).0)
+            .subscribe();
+        Self {
+            engine,
+            key,
+            changed: Some(changed),
+        }
+    }
+
+    /// Resolves when the key is written or removed after registration.
+    async fn changed(&mut self) -> bool {
+        match &mut self.changed {
+            Some(changed) => changed.changed().await.is_ok(),
+            None => false,
+        }
+    }
+
+    /// Like `changed`, but gives up at `deadline` (never if `None`) and
+    /// returns false.
+    async fn changed_until(&mut self, deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, self.changed())
+                .await
+                .unwrap_or(false),
+            None => self.changed().await,
+        }
+    }
+}
+
+impl Drop for KeyWatch<'_> {
+    fn drop(&mut self) {
+        // Drop our receiver first so the count below no longer includes it.
+        self.changed = None;
+        self.engine
+            .watchers
+            .remove_if(self.key, |_, tx| tx.receiver_count() == 0);
+    }
+}
+
+/// Changes made inside `MemoryEngine::transaction`, kept to undo them if it
+/// fails and to announce them only once it commits.
+#[derive(Default)]
+struct TxnLog {
+    /// Every touched key's record from before the transaction.
+    undo: HashMap<String, Option<Record>>,
+    events: Vec<(KeyEventKind, String)>,
+    /// Keys whose `wait_for_change` callers are woken on commit.
+    changed: Vec<String>,
+}
+
+thread_local! {
+    /// Address of the engine whose transaction this thread is running.
+    static TXN_ENGINE: Cell<usize> = const { Cell::new(0) };
+    /// The transaction gate stripe this thread reads through, once picked.
+    static GATE_STRIPE: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// Stripes of the transaction gate. Threads spread over them, so plain
+/// operations on different threads do not contend on one lock.
+const GATE_STRIPES: usize = 32;
+
+static NEXT_GATE_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+/// Stripes of `MemoryEngine::removals`. Removing a key counts as a change
+/// to every missing key in its stripe.
+const REMOVAL_STRIPES: usize = 1024;
+
+/// One stripe of the transaction gate, on its own cache line.
+#[repr(align(128))]
+#[derive(Default)]
+struct GateStripe(RwLock<()>);
+
+/// Marks the current thread as running an engine's transaction until dropped.
+struct TxnOwner {
+    previous: usize,
+}
+
+impl TxnOwner {
+    fn enter(engine: &MemoryEngine) -> Self {
+        let previous = TXN_ENGINE.with(|e| e.replace(engine as *const MemoryEngine as usize));
+        Self { previous }
+    }
+}
+
+impl Drop for TxnOwner {
+    fn drop(&mut self) {
+        TXN_ENGINE.with(|e| e.set(self.previous));
+    }
+}
+
+/// Keyspace events buffered per subscriber before it starts missing some.
+pub const EVENT_BUFFER: usize = 1024;
+
+/// What happened to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Set,
+    Delete,
+    /// Removed to make room under the memory limit.
+    Evict,
+    /// Removed after its hard expiry.
+    Expire,
+}
+
+impl KeyEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyEventKind::Set => "set",
+            KeyEventKind::Delete => "delete",
+            KeyEventKind::Evict => "evict",
+            KeyEventKind::Expire => "expire",
+        }
+    }
+}
+
+impl std::str::FromStr for KeyEventKind {
+    type Err = BlinkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "set" => Ok(KeyEventKind::Set),
+            "delete" => Ok(KeyEventKind::Delete),
+            "evict" => Ok(KeyEventKind::Evict),
+            "expire" => Ok(KeyEventKind::Expire),
+            _ => Err(BlinkError::Protocol(format!("unknown key event '{}'", s))),
+        }
+    }
+}
+
+/// A keyspace notification from `MemoryEngine::subscribe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub kind: KeyEventKind,
+    pub key: String,
+}
+
+/// Leases kept before expired ones (never filled by a `set`) are swept.
+const LEASE_SWEEP_THRESHOLD: usize = 1024;
+
+/// The right to recompute a missing key, held by one caller until it sets
+/// the key or the lease expires. Dropping `released` wakes the waiters.
+struct Lease {
+    expires: Instant,
+    released: watch::Sender<()>,
+}
+
+/// Outcome of `MemoryEngine::get_or_lease`.
+pub enum LeaseLookup {
+    /// The key is present and fresh.
+    Value(Bytes),
+    /// The key is stale and another caller holds the lease to refresh it.
+    Stale(Bytes),
+    /// The key is missing or stale and the caller now holds its lease: it
+    /// should compute the value and `set` it before the lease expires.
+    Granted,
+    /// Another caller holds the lease; wait, then look up again.
+    Held(LeaseWait),
+}
+
+/// Resolves when a held lease is released (the key was set) or expires.
+pub struct LeaseWait {
+    released: watch::Receiver<()>,
+    expires: Instant,
+}
+
+impl LeaseWait {
+    pub async fn wait(mut self) {
+        let _ = tokio::time::timeout_at(self.expires, self.released.changed()).await;
+    }
+}
+
+/// In-memory engine with sampled eviction and memory-cap enforcement.
+pub struct MemoryEngine {
+    store: DashMap<String, Record>,
+    /// Entries with a hard expiry, so `purge_expired` can skip the scan.
+    expiring: AtomicUsize,
+    limit_bytes: AtomicU64,
+    eviction_samples: AtomicUsize,
+    no_eviction: AtomicBool,
+    current_usage: AtomicU64,
+    /// Entries in `store`, including expired ones not yet removed.
+    keys: AtomicUsize,
+    access_counter: AtomicU64,
+    /// Next version handed to a write; 0 is reserved for missing keys.
+    versions: AtomicU64,
+    /// Per stripe of keys, the version drawn when one was last removed or
+    /// replaced, so a watched missing key notices being set and removed.
+    removals: Box<[AtomicU64]>,
+    quotas: RwLock<Vec<Arc<QuotaGroup>>>,
+    loads: SingleFlight,
+    leases: DashMap<String, Lease>,
+    events: broadcast::Sender<KeyEvent>,
+    /// Keys with a `wait_for_change` in progress; woken on every change.
+    watchers: DashMap<String, watch::Sender<()>>,
+    /// A stripe is taken shared by every operation on entries, and all of
+    /// them exclusively by `transaction`, so a transaction sees and leaves
+    /// no partial state.
+    gate: Box<[GateStripe]>,
+    txn: Mutex<TxnLog>,
+}
+
+impl MemoryEngine {
+    pub fn new(limit_bytes: u64) -> Result<Self, BlinkError> {
+        Ok(Self {
+            store: DashMap::new(),
+            expiring: AtomicUsize::new(0),
+            limit_bytes: AtomicU64::new(limit_bytes),
+            eviction_samples: AtomicUsize::new(DEFAULT_EVICTION_SAMPLES),
+            no_eviction: AtomicBool::new(false),
+            current_usage: AtomicU64::new(0),
+            keys: AtomicUsize::new(0),
+            access_counter: AtomicU64::new(0),
+            versions: AtomicU64::new(1),
+            removals: (0..REMOVAL_STRIPES).map(|_| AtomicU64::new(0)).collect(),
+            quotas: RwLock::new(Vec::new()),
+            loads: SingleFlight::new(),
+            leases: DashMap::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            watchers: DashMap::new(),
+            gate: (0..GATE_STRIPES).map(|_| GateStripe::default()).collect(),
+            txn: Mutex::new(TxnLog::default()),
+        })
+    }
+
+    /// Stream of keyspace events (set, delete, evict, expire) from now on.
+    /// A subscriber that falls more than `EVENT_BUFFER` events behind gets a
+    /// `Lagged` error item reporting how many it missed, then continues.
+    pub fn subscribe(&self) -> BroadcastStream<KeyEvent> {
+        BroadcastStream::new(self.events.subscribe())
+    }
+
+    fn emit(&self, kind: KeyEventKind, key: &str) {
+        if self.in_txn() {
+            self.txn_log().events.push((kind, key.to_owned()));
+            return;
+        }
+        // Skip building the event when nobody listens, the common case.
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(KeyEvent {
+                kind,
+                key: key.to_owned(),
+            });
+        }
+    }
+
+    /// Wakes `wait_for_change` callers for `key` after it was written or removed.
+    fn notify_watchers(&self, key: &str) {
+        if self.in_txn() {
+            self.txn_log().changed.push(key.to_owned());
+            return;
+        }
+        if let Some(tx) = self.watchers.get(key) {
+            tx.send_replace(());
+        }
+    }
+
+    /// Announces that `key` was written: wakes its watchers, emits `set`
+    /// and releases any `get_or_lease` lease on it, so waiters look again.
+    fn written(&self, key: &str) {
+        self.leases.remove(key);
+        self.notify_watchers(key);
+        self.emit(KeyEventKind::Set, key);
+    }
+
+    /// Shared side of this thread's transaction gate stripe, taken by public
+    /// operations on entries. A thread running this engine's transaction
+    /// already holds every stripe exclusively, so nothing is taken there.
+    fn gate(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        if self.in_txn() {
+            return None;
+        }
+        let index = GATE_STRIPE.with(|stripe| {
+            if stripe.get() == usize::MAX {
+                stripe.set(NEXT_GATE_STRIPE.fetch_add(1, Ordering::Relaxed) % GATE_STRIPES);
+            }
+            stripe.get()
+        });
+        let stripe = &self.gate[index];
+        Some(stripe.0.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn in_txn(&self) -> bool {
+        TXN_ENGINE.with(|e| e.get() == self as *const Self as usize)
+    }
+
+    fn txn_log(&self) -> MutexGuard<'_, TxnLog> {
+        self.txn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Inside a transaction, remembers `key`'s record from before its first
+    /// change so a failed transaction can put it back.
+    fn log_undo(&self, key: &str, previous: Option<&Record>) {
+        if self.in_txn() {
+            self.txn_log()
+                .undo
+                .entry(key.to_owned())
+                .or_insert_with(|| previous.cloned());
+        }
+    }
+
+    /// Runs `f` atomically: no other operation on this engine runs in the
+    /// meantime, and if `f` fails, every entry it wrote or removed (including
+    /// keys evicted to make room) is restored. Returns `None` without running
+    /// `f` if the `watch_version` of any `watched` key has changed. Keyspace events
+    /// and `wait_for_change` wakeups are sent only on commit. `f` must not
+    /// start another transaction on this engine.
+    pub fn transaction<T, E>(
+        &self,
+        watched: &[(String, u64)],
+        f: impl FnOnce() -> Result<T, E>,
+    ) -> Option<Result<T, E>> {
+        // Always taken in the same order, so two transactions cannot deadlock.
+        let _exclusive: Vec<_> = self
+            .gate
+            .iter()
+            .map(|stripe| stripe.0.write().unwrap_or_else(|e| e.into_inner()))
+            .collect();
+        if watched
+            .iter()
+            .any(|(key, version)| self.current_watch_version(key) != *version)
+        {
+            return None;
+        }
+        *self.txn_log() = TxnLog::default();
+        let result = {
+            let _owner = TxnOwner::enter(self);
+            f()
+        };
+        let log = std::mem::take(&mut *self.txn_log());
+        match &result {
+            Ok(_) => {
+                for key in &log.changed {
+                    self.notify_watchers(key);
+                }
+                for (kind, key) in &log.events {
+                    self.emit(*kind, key);
+                }
+            }
+            Err(_) => {
+                for (key, record) in log.undo {
+                    self.restore(&key, record);
+                }
+            }
+        }
+        Some(result)
+    }
+
+    /// Puts back a record saved by `log_undo`, or removes the key if it did
+    /// not exist.
+    fn restore(&self, key: &str, record: Option<Record>) {
+        if let Some((_, current)) = self.store.remove(key) {
+            self.release(key, &current);
+        }
+        if let Some(record) = record {
+            self.charge(key, &record);
+            self.store.insert(key.to_owned(), record);
+        }
+    }
+
+    fn current_version(&self, key: &str) -> u64 {
+        let now = Instant::now();
+        self.store
+            .get(key)
+            .filter(|r| !r.is_expired(now))
+            .map_or(0, |r| r.version)
+    }
+
+    /// Version of `key` (0 if missing), without counting as an access.
+    pub fn version(&self, key: &str) -> u64 {
+        let _gate = self.gate();
+        self.current_version(key)
+    }
+
+    /// Like `version`, but a missing key gets the version of the last
+    /// removal among keys sharing its stripe, so setting and then removing
+    /// it still counts as a change. Used to `WATCH` keys for `transaction`.
+    pub fn watch_version(&self, key: &str) -> u64 {
+        let _gate = self.gate();
+        self.current_watch_version(key)
+    }
+
+    fn current_watch_version(&self, key: &str) -> u64 {
+        match self.current_version(key) {
+            0 => self.removals[removal_stripe(key)].load(Ordering::Acquire),
+            version => version,
+        }
+    }
+
+    /// Reads `key` together with its version.
+    pub fn get_versioned(&self, key: &str) -> Result<Versioned, BlinkError> {
+        let _gate = self.gate();
+        Ok(match self.read_str(key)? {
+            Some((value, _, version)) => Versioned {
+                version,
+                value: Some(value),
+            },
+            None => Versioned {
+                version: 0,
+                value: None,
+            },
+        })
+    }
+
+    /// Long-poll read: returns as soon as the version of `key` differs from
+    /// `version`, waiting up to `timeout` (at most `MAX_WAIT`) for a write,
+    /// delete, eviction or expiry. On timeout the unchanged state is returned.
+    pub async fn wait_for_change(
+        &self,
+        key: &str,
+        version: u64,
+        timeout: Duration,
+    ) -> Result<Versioned, BlinkError> {
+        let deadline = Instant::now() + timeout.min(MAX_WAIT);
+        // Registered before the first read so a change in between is not missed.
+        let mut watch = KeyWatch::register(self, key);
+        loop {
+            let current = self.get_versioned(key)?;
+            if current.version != version {
+                return Ok(current);
+            }
+            match tokio::time::timeout_at(deadline, watch.changed()).await {
+                Ok(true) => {}
+                _ => return Ok(current),
+            }
+        }
+    }
+
+    /// Number of keys with a `wait_for_change` in progress.
+    pub fn watched_keys(&self) -> usize {
+        self.watchers.len()
+    }
+
+    /// Read-through lookup: returns the value of `key`, or runs `load` on a
+    /// miss and stores its result. Concurrent misses on the same key share
+    /// one `load`, so a hot key that was evicted is recomputed only once. A
+    /// loaded value that cannot be stored (`AtCapacity`) is still returned.
+    pub async fn get_or_load<F, Fut>(&self, key: &str, load: F) -> Result<Bytes, BlinkError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Bytes, BlinkError>>,
+    {
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        self.loads
+            .run(key, || async {
+                // A load for this key may have finished since our lookup.
+                if let Some(value) = self.get(key)? {
+                    return Ok(value);
+                }
+                let value = load().await?;
+                if let Err(e) = self.set(key, value.clone()) {
+                    debug!(key = %key, error = %e, action = "load_store_failed");
+                }
+                Ok(value)
+            })
+            .await
+    }
+
+    /// Lease-based lookup for callers that recompute values themselves
+    /// (`GETLOCK`): the first caller to miss, or to find the value stale, is
+    /// granted a lease for `ttl`. While it is held, later callers get the
+    /// stale value, or are told to wait until the key is set or the lease
+    /// expires.
+    pub fn get_or_lease(&self, key: &str, ttl: Duration) -> Result<LeaseLookup, BlinkError> {
+        // Before the lease entry: `set` takes the gate, then the lease.
+        let _gate = self.gate();
+        let now = Instant::now();
+        if self.leases.len() > LEASE_SWEEP_THRESHOLD {
+            self.leases.retain(|_, lease| lease.expires > now);
+        }
+        // Holding the lease entry while checking the store: `set` inserts
+        // before it releases the lease, so a lease is never granted for a
+        // key that was just written.
+        let entry = self.leases.entry(key.to_owned());
+        let stale = match self.read_str(key)? {
+            Some((value, Freshness::Fresh, _)) => return Ok(LeaseLookup::Value(value)),
+            Some((value, Freshness::Stale, _)) => Some(value),
+            None => None,
+        };
+        let lease = Lease {
+            expires: now + ttl,
+            released: watch::channel(()).0,
+        };
+        match entry {
+            Entry::Occupied(held) if held.get().expires > now => Ok(match stale {
+                Some(value) => LeaseLookup::Stale(value),
+                None => LeaseLookup::Held(LeaseWait {
+                    released: held.get().released.subscribe(),
+                    expires: held.get().expires,
+                }),
+            }),
+            Entry::Occupied(mut expired) => {
+                expired.insert(lease);
+                Ok(LeaseLookup::Granted)
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(lease);
+                Ok(LeaseLookup::Granted)
+            }
+        }
+    }
+
+    /// Reads `key` and reports whether it is past its soft expiry. Entries
+    /// past their hard expiry are removed and read as missing.
+    pub fn get_with_freshness(&self, key: &str) -> Result<Option<(Bytes, Freshness)>, BlinkError> {
+        let _gate = self.gate();
+        Ok(self
+            .read_str(key)?
+            .map(|(value, freshness, _)| (value, freshness)))
+    }
+
+    /// Reads a string value with its freshness and version.
+    fn read_str(&self, key: &str) -> Result<Option<(Bytes, Freshness, u64)>, BlinkError> {
+        self.read(key, |record, freshness| match &record.value {
+            Value::Str(bytes) => Ok((bytes.clone(), freshness, record.version)),
+            _ => Err(BlinkError::WrongType),
+        })
+        .transpose()
+    }
+
+    /// Looks up `key`, counting it as an access, and passes its record to
+    /// `f`. Entries past their hard expiry are removed and read as missing.
+    fn read<T>(&self, key: &str, f: impl FnOnce(&Record, Freshness) -> T) -> Option<T> {
+        let now = Instant::now();
+        {
+            let mut record = self.store.get_mut(key)?;
+            if !record.is_expired(now) {
+                record.counter = self.next_counter();
+                let freshness = if record.is_stale(now) {
+                    Freshness::Stale
+                } else {
+                    Freshness::Fresh
+                };
+                return Some(f(&record, freshness));
+            }
+        }
+        if self.remove_entry_if(key, |r| r.is_expired(now)).is_some() {
+            self.emit(KeyEventKind::Expire, key);
+        }
+        None
+    }
+
+    /// Like `set`, but the value turns stale after `expiry.soft` and is
+    /// removed after `expiry.hard`. A plain `set` clears any expiry.
+    pub fn set_with_expiry(&self, key: &str, value: Bytes, expiry: Expiry) -> Result<(), BlinkError> {
+        self.replace(key, value, expiry, false).map(|_| ())
+    }
+
+    /// Stores `value` at `key` and returns the record it replaced. With
+    /// `strings_only`, a live value of another kind is left alone and
+    /// `WrongType` returned instead.
+    fn replace(
+        &self,
+        key: &str,
+        value: Bytes,
+        expiry: Expiry,
+        strings_only: bool,
+    ) -> Result<Option<Record>, BlinkError> {
+        expiry.check()?;
+        let _gate = self.gate();
+        let now = Instant::now();
+        let value = Value::Str(value);
+        let need = entry_size(key, &value);
+        let old_size = self.store.get(key).map(|r| r.size).unwrap_or(0);
+        self.make_room(need, old_size, key)?;
+
+        let record = Record {
+            value,
+            counter: self.next_counter(),
+            stale_at: expiry.soft.map(|d| now + d),
+            expires_at: expiry.hard.map(|d| now + d),
+            version: self.versions.fetch_add(1, Ordering::Relaxed),
+            size: need,
+        };
+        let previous = match self.store.entry(key.to_owned()) {
+            Entry::Occupied(mut occupied) => {
+                let old = occupied.get();
+                if strings_only && !old.is_expired(now) && !matches!(old.value, Value::Str(_)) {
+                    return Err(BlinkError::WrongType);
+                }
+                self.charge(key, &record);
+                Some(occupied.insert(record))
+            }
+            Entry::Vacant(vacant) => {
+                self.charge(key, &record);
+                vacant.insert(record);
+                None
+            }
+        };
+        self.log_undo(key, previous.as_ref());
+        if let Some(previous) = &previous {
+            self.release(key, previous);
+        }
+        self.written(key);
+        Ok(previous)
+    }
+
+    /// Appends `suffix` to the string at `key`, creating it if needed;
+    /// returns the new length. The value is extended in place unless a
+    /// reader still holds it, and keeps its expiry.
+    pub fn append(&self, key: &str, suffix: &[u8]) -> Result<usize, BlinkError> {
+        let added = suffix.len() as u64;
+        self.update_value(
+            key,
+            "string",
+            || Value::Str(Bytes::new()),
+            |_| (added, 0),
+            |current| {
+                let Value::Str(bytes) = current else {
+                    unreachable!()
+                };
+                let mut buf = into_mut(std::mem::take(bytes));
+                buf.extend_from_slice(suffix);
+                *bytes = buf.freeze();
+                Ok((bytes.len(), added, 0))
+            },
+        )
+    }
+
+    /// Overwrites the string at `key` from byte `offset` with `patch`,
+    /// zero-padding it if it is shorter than `offset`; returns the new
+    /// length. A missing key is treated as empty, but an empty `patch` does
+    /// not create it.
+    pub fn setrange(&self, key: &str, offset: usize, patch: &[u8]) -> Result<usize, BlinkError> {
+        if patch.is_empty() {
+            return self.strlen(key);
+        }
+        let end = offset.saturating_add(patch.len());
+        let grown = |len: usize| end.saturating_sub(len) as u64;
+        self.update_value(
+            key,
+            "string",
+            || Value::Str(Bytes::new()),
+            |current| {
+                let Value::Str(bytes) = current else {
+                    unreachable!()
+                };
+                (grown(bytes.len()), 0)
+            },
+            |current| {
+                let Value::Str(bytes) = current else {
+                    unreachable!()
+                };
+                let added = grown(bytes.len());
+                let mut buf = into_mut(std::mem::take(bytes));
+                if buf.len() < end {
+                    buf.resize(end, 0);
+                }
+                buf[offset..end].copy_from_slice(patch);
+                *bytes = buf.freeze();
+                Ok((bytes.len(), added, 0))
+            },
+        )
+    }
+
+    /// Bytes `start..=end` of the string at `key`; negative offsets count
+    /// from the end (-1 is the last byte). Shares the stored buffer.
+    pub fn getrange(&self, key: &str, start: i64, end: i64) -> Result<Bytes, BlinkError> {
+        let _gate = self.gate();
+        Ok(self
+            .read_str(key)?
+            .map(|(bytes, _, _)| bytes.slice(list_range(bytes.len(), start, end)))
+            .unwrap_or_default())
+    }
+
+    /// Length of the string at `key`; 0 if it is missing.
+    pub fn strlen(&self, key: &str) -> Result<usize, BlinkError> {
+        let _gate = self.gate();
+        Ok(self.read_str(key)?.map_or(0, |(bytes, _, _)| bytes.len()))
+    }
+
+    /// Creates or changes the value at `key` in place, keeping its expiry.
+    /// An existing value must be of `kind`; `empty` makes the new value if
+    /// the key is missing or expired. `cost` gives the bytes a change will
+    /// add and replace, so room is made beforehand; `apply` makes the change
+    /// and returns its result with the bytes actually added and removed.
+    /// `apply` must leave the value untouched when it fails. A change that
+    /// would make the entry larger than the memory limit fails with
+    /// `AtCapacity`.
+    fn update_value<T>(
+        &self,
+        key: &str,
+        kind: &str,
+        empty: impl Fn() -> Value,
+        cost: impl FnOnce(&Value) -> (u64, u64),
+        apply: impl FnOnce(&mut Value) -> Result<(T, u64, u64), BlinkError>,
+    ) -> Result<T, BlinkError> {
+        self.change_value(key, kind, empty, cost, apply, |_| true)
+    }
+
+    /// `update_value`, but unless `write` returns true for `apply`'s result,
+    /// changing an existing value keeps its version and wakes or tells no
+    /// one: for changes that turned out to be no-ops, and for bookkeeping
+    /// done by reads, such as `xreadgroup` recording pending entries.
+    fn change_value<T>(
+        &self,
+        key: &str,
+        kind: &str,
+        empty: impl Fn() -> Value,
+        cost: impl FnOnce(&Value) -> (u64, u64),
+        apply: impl FnOnce(&mut Value) -> Result<(T, u64, u64), BlinkError>,
+        write: impl FnOnce(&T) -> bool,
+    ) -> Result<T, BlinkError> {
+        let _gate = self.gate();
+        let now = Instant::now();
+        let mut fresh = None;
+        let (need, freed, size) = match self.store.get(key) {
+            Some(record) if !record.is_expired(now) => {
+                if record.value.kind() != kind {
+                    return Err(BlinkError::WrongType);
+                }
+                let (need, freed) = cost(&record.value);
+                (need, freed, record.size)
+            }
+            _ => {
+                let value = fresh.insert(empty());
+                let (need, freed) = cost(value);
+                (entry_size(key, value) + need, freed, 0)
+            }
+        };
+        if size.saturating_add(need).saturating_sub(freed) > self.limit_bytes() {
+            return Err(BlinkError::AtCapacity);
+        }
+        self.make_room(need, freed, key)?;
+
+        let counter = self.next_counter();
+        let (out, announce) = match self.store.entry(key.to_owned()) {
+            Entry::Occupied(mut occupied) if !occupied.get().is_expired(now) => {
+                if occupied.get().value.kind() != kind {
+                    return Err(BlinkError::WrongType);
+                }
+                self.log_undo(key, Some(occupied.get()));
+                let record = occupied.get_mut();
+                let (out, added, removed) = apply(&mut record.value)?;
+                let write = write(&out);
+                record.counter = counter;
+                if write {
+                    record.version = self.versions.fetch_add(1, Ordering::Relaxed);
+                }
+                record.size = record.size + added - removed;
+                self.grow(key, added);
+                self.shrink(key, removed);
+                (out, write)
+            }
+            // Missing, or expired and replaced by a new value.
+            entry => {
+                let mut value = fresh.take().unwrap_or_else(&empty);
+                let (out, _, _) = apply(&mut value)?;
+                let record = Record {
+                    size: entry_size(key, &value),
+                    value,
+                    counter,
+                    stale_at: None,
+                    expires_at: None,
+                    version: self.versions.fetch_add(1, Ordering::Relaxed),
+                };
+                self.insert_record(key, entry, record);
+                (out, true)
+            }
+        };
+        if announce {
+            self.written(key);
+        }
+        Ok(out)
+    }
+
+    /// Takes items out of the `kind` collection at `key`. `apply` returns
+    /// its result with the bytes it freed, or `None` if it changed nothing.
+    /// The key is removed along with its last item.
+    fn take_from_collection<T: Default>(
+        &self,
+        key: &str,
+        kind: &str,
+        apply: impl FnOnce(&mut Value) -> Option<(T, u64)>,
+    ) -> Result<T, BlinkError> {
+        let _gate = self.gate();
+        let now = Instant::now();
+        let (out, emptied) = {
+            let Some(mut record) = self.store.get_mut(key) else {
+                return Ok(T::default());
+            };
+            if record.is_expired(now) {
+                return Ok(T::default());
+            }
+            if record.value.kind() != kind {
+                return Err(BlinkError::WrongType);
+            }
+            self.log_undo(key, Some(&record));
+            let Some((out, freed)) = apply(&mut record.value) else {
+                return Ok(T::default());
+            };
+            record.version = self.versions.fetch_add(1, Ordering::Relaxed);
+            record.size -= freed;
+            self.shrink(key, freed);
+            (out, record.value.is_empty_collection())
+        };
+        self.changed_collection(key, emptied);
+        Ok(out)
+    }
+
+    /// Sets `field` of the hash at `key`, creating the hash if needed.
+    /// Returns true if the field is new.
+    pub fn hset(&self, key: &str, field: &str, value: Bytes) -> Result<bool, BlinkError> {
+        let added = field_size(field, &value);
+        self.update_value(
+            key,
+            "hash",
+            || Value::Hash(HashMap::new()),
+            |current| {
+                let Value::Hash(fields) = current else {
+                    unreachable!()
+                };
+                (added, fields.get(field).map_or(0, |v| field_size(field, v)))
+            },
+            |current| {
+                let Value::Hash(fields) = current else {
+                    unreachable!()
+                };
+                let old = fields.insert(field.to_owned(), value);
+                let removed = old.as_ref().map_or(0, |v| field_size(field, v));
+                Ok((old.is_none(), added, removed))
+            },
+        )
+    }
+
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<Bytes>, BlinkError> {
+        let _gate = self.gate();
+        self.read(key, |record, _| match &record.value {
+            Value::Hash(fields) => Ok(fields.get(field).cloned()),
+            _ => Err(BlinkError::WrongType),
+        })
+        .transpose()
+        .map(Option::flatten)
+    }
+
+    /// Every field of the hash at `key`, sorted by field name.
+    pub fn hgetall(&self, key: &str) -> Result<Vec<(String, Bytes)>, BlinkError> {
+        let _gate = self.gate();
+        let fields = self
+            .read(key, |record, _| match &record.value {
+                Value::Hash(fields) => Ok(fields
+                    .iter()
+                    .map(|(f, v)| (f.clone(), v.clone()))
+                    .collect::<Vec<_>>()),
+                _ => Err(BlinkError::WrongType),
+            })
+            .transpose()?;
+        let mut fields = fields.unwrap_or_default();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(fields)
+    }
+
+    /// Removes `fields` from the hash at `key`; returns how many existed.
+    /// The key is removed along with its last field.
+    pub fn hdel(&self, key: &str, fields: &[&str]) -> Result<usize, BlinkError> {
+        self.take_from_collection(key, "hash", |current| {
+            let Value::Hash(hash) = current else {
+                unreachable!()
+            };
+            let (mut removed, mut freed) = (0, 0);
+            for field in fields {
+                if let Some(old) = hash.remove(*field) {
+                    freed += field_size(field, &old);
+                    removed += 1;
+                }
+            }
+            (removed > 0).then_some((removed, freed))
+        })
+    }
+
+    /// Announces a change to a collection, removing the key if the change
+    /// left it empty.
+    fn changed_collection(&self, key: &str, emptied: bool) {
+        if emptied
+            && self
+                .remove_entry_if(key, |r| r.value.is_empty_collection())
+                .is_some()
+        {
+            self.emit(KeyEventKind::Delete, key);
+        } else {
+            self.written(key);
+        }
+    }
+
+    /// Pushes `value` onto one end of the list at `key`, creating it if
+    /// needed; returns the new length.
+    pub fn push(&self, key: &str, end: End, value: Bytes) -> Result<usize, BlinkError> {
+        let added = value.len() as u64;
+        self.update_value(
+            key,
+            "list",
+            || Value::List(VecDeque::new()),
+            |_| (added, 0),
+            |current| {
+                let Value::List(items) = current else {
+                    unreachable!()
+                };
+                match end {
+                    End::Left => items.push_front(value),
+                    End::Right => items.push_back(value),
+                }
+                Ok((items.len(), added, 0))
+            },
+        )
+    }
+
+    /// Removes and returns the item at one end of the list at `key`. The key
+    /// is removed along with its last item.
+    pub fn pop(&self, key: &str, end: End) -> Result<Option<Bytes>, BlinkError> {
+        self.take_from_collection(key, "list", |current| {
+            let Value::List(items) = current else {
+                unreachable!()
+            };
+            let item = match end {
+                End::Left => items.pop_front(),
+                End::Right => items.pop_back(),
+            }?;
+            let freed = item.len() as u64;
+            Some((Some(item), freed))
+        })
+    }
+
+    /// Items `start..=stop` of the list at `key`; negative indexes count
+    /// from the end (-1 is the last item).
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>, BlinkError> {
+        let _gate = self.gate();
+        self.read(key, |record, _| match &record.value {
+            Value::List(items) => Ok(items.range(list_range(items.len(), start, stop)).cloned().collect()),
+            _ => Err(BlinkError::WrongType),
+        })
+        .unwrap_or(Ok(Vec::new()))
+    }
+
+    /// Pops from the head of the list at `key`, waiting up to `timeout`
+    /// (indefinitely if `None`) for an item to be pushed. Concurrent callers
+    /// race for each item; the losers keep waiting. Returns `None` on timeout.
+    pub async fn blpop(&self, key: &str, timeout: Option<Duration>) -> Result<Option<Bytes>, BlinkError> {
+        let deadline = timeout.map(|t| Instant::now() + t.min(MAX_WAIT));
+        // Registered before the first pop so a push in between is not missed.
+        let mut watch = KeyWatch::register(self, key);
+        loop {
+            if let Some(item) = self.pop(key, End::Left)? {
+                return Ok(Some(item));
+            }
+            if !watch.changed_until(deadline).await {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Adds `members` to the set at `key`, creating it if needed; returns
+    /// how many were not already members.
+    pub fn sadd(&self, key: &str, members: &[&str]) -> Result<usize, BlinkError> {
+        self.update_value(
+            key,
+            "set",
+            || Value::Set(HashSet::new()),
+            |current| {
+                let Value::Set(set) = current else {
+                    unreachable!()
+                };
+                let need = members
+                    .iter()
+                    .filter(|m| !set.contains(**m))
+                    .map(|m| m.len() as u64)
+                    .sum();
+                (need, 0)
+            },
+            |current| {
+                let Value::Set(set) = current else {
+                    unreachable!()
+                };
+                let (mut added, mut bytes) = (0, 0);
+                for member in members {
+                    if set.insert((*member).to_owned()) {
+                        added += 1;
+                        bytes += member.len() as u64;
+                    }
+                }
+                Ok((added, bytes, 0))
+            },
+        )
+    }
+
+    /// Removes `members` from the set at `key`; returns how many existed.
+    /// The key is removed along with its last member.
+    pub fn srem(&self, key: &str, members: &[&str]) -> Result<usize, BlinkError> {
+        self.take_from_collection(key, "set", |current| {
+            let Value::Set(set) = current else {
+                unreachable!()
+            };
+            let (mut removed, mut freed) = (0, 0);
+            for member in members {
+                if set.remove(*member) {
+                    removed += 1;
+                    freed += member.len() as u64;
+                }
+            }
+            (removed > 0).then_some((removed, freed))
+        })
+    }
+
+    /// Every member of the set at `key`, sorted.
+    pub fn smembers(&self, key: &str) -> Result<Vec<String>, BlinkError> {
+        let _gate = self.gate();
+        let members = self
+            .read(key, |record, _| match &record.value {
+                Value::Set(set) => Ok(set.iter().cloned().collect::<Vec<_>>()),
+                _ => Err(BlinkError::WrongType),
+            })
+            .transpose()?;
+        let mut members = members.unwrap_or_default();
+        members.sort();
+        Ok(members)
+    }
+
+    pub fn sismember(&self, key: &str, member: &str) -> Result<bool, BlinkError> {
+        let _gate = self.gate();
+        self.read(key, |record, _| match &record.value {
+            Value::Set(set) => Ok(set.contains(member)),
+            _ => Err(BlinkError::WrongType),
+        })
+        .unwrap_or(Ok(false))
+    }
+
+    /// Sets the score of each `(score, member)` in the sorted set at `key`,
+    /// creating it if needed; returns how many members are new.
+    pub fn zadd(&self, key: &str, entries: &[(f64, &str)]) -> Result<usize, BlinkError> {
+        if entries.iter().any(|(score, _)| score.is_nan()) {
+            return Err(BlinkError::NotANumber);
+        }
+        self.update_value(
+            key,
+            "zset",
+            || Value::SortedSet(SortedSet::default()),
+            |current| {
+                let Value::SortedSet(zset) = current else {
+                    unreachable!()
+                };
+                let need = entries
+                    .iter()
+                    .filter(|(_, m)| !zset.scores.contains_key(*m))
+                    .map(|(_, m)| member_size(m))
+                    .sum();
+                (need, 0)
+            },
+            |current| {
+                let Value::SortedSet(zset) = current else {
+                    unreachable!()
+                };
+                let (mut added, mut bytes) = (0, 0);
+                for (score, member) in entries {
+                    if zset.insert(member, *score).is_none() {
+                        added += 1;
+                        bytes += member_size(member);
+                    }
+                }
+                Ok((added, bytes, 0))
+            },
+        )
+    }
+
+    /// Adds `delta` to `member`'s score in the sorted set at `key` (a new
+    /// member starts at 0); returns the new score.
+    pub fn zincrby(&self, key: &str, delta: f64, member: &str) -> Result<f64, BlinkError> {
+        self.update_value(
+            key,
+            "zset",
+            || Value::SortedSet(SortedSet::default()),
+            |current| {
+                let Value::SortedSet(zset) = current else {
+                    unreachable!()
+                };
+                let need = if zset.scores.contains_key(member) {
+                    0
+                } else {
+                    member_size(member)
+                };
+                (need, 0)
+            },
+            |current| {
+                let Value::SortedSet(zset) = current else {
+                    unreachable!()
+                };
+                let old = zset.scores.get(member).copied();
+                let score = old.unwrap_or(0.0) + delta;
+                if score.is_nan() {
+                    return Err(BlinkError::NotANumber);
+                }
+                zset.insert(member, score);
+                let added = if old.is_some() { 0 } else { member_size(member) };
+                Ok((score, added, 0))
+            },
+        )
+    }
+
+    /// Removes `members` from the sorted set at `key`; returns how many
+    /// existed. The key is removed along with its last member.
+    pub fn zrem(&self, key: &str, members: &[&str]) -> Result<usize, BlinkError> {
+        self.take_from_collection(key, "zset", |current| {
+            let Value::SortedSet(zset) = current else {
+                unreachable!()
+            };
+            let (mut removed, mut freed) = (0, 0);
+            for member in members {
+                if zset.remove(member).is_some() {
+                    removed += 1;
+                    freed += member_size(member);
+                }
+            }
+            (removed > 0).then_some((removed, freed))
+        })
+    }
+
+    /// Members ranked `start..=stop` by ascending score, with their scores;
+    /// negative ranks count from the highest (-1).
+    pub fn zrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(String, f64)>, BlinkError> {
+        let _gate = self.gate();
+        self.read(key, |record, _| match &record.value {
+            Value::SortedSet(zset) => {
+                let range = list_range(zset.scores.len(), start, stop);
+                Ok(zset.iter().skip(range.start).take(range.len()).collect())
+            }
+            _ => Err(BlinkError::WrongType),
+        })
+        .unwrap_or(Ok(Vec::new()))
+    }
+
+    /// Members with a score between `min` and `max`, lowest first, with
+    /// their scores.
+    pub fn zrangebyscore(
+        &self,
+        key: &str,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> Result<Vec<(String, f64)>, BlinkError> {
+        let _gate = self.gate();
+        self.read(key, |record, _| match &record.value {
+            Value::SortedSet(zset) => Ok(zset.range_by_score(min, max)),
+            _ => Err(BlinkError::WrongType),
+        })
+        .unwrap_or(Ok(Vec::new()))
+    }
+
+    /// How many of `keys` exist (a key listed twice counts twice). Does not
+    /// count as an access.
+    pub fn exists(&self, keys: &[&str]) -> usize {
+        let _gate = self.gate();
+        let now = Instant::now();
+        keys.iter()
+            .filter(|key| self.store.get(**key).is_some_and(|r| !r.is_expired(now)))
+            .count()
+    }
+
+    /// Marks `keys` as just used, so eviction keeps them longest; returns
+    /// how many exist.
+    pub fn touch(&self, keys: &[&str]) -> usize {
+        let _gate = self.gate();
+        keys.iter().filter(|key| self.read(key, |_, _| ()).is_some()).count()
+    }
+
+    /// Kind of value at `key`: `string`, `hash`, `list`, `set`, `zset`,
+    /// `hll`, `bloom`, `stream`, `ratelimit` or `lock`.
+    pub fn key_type(&self, key: &str) -> Option<&'static str> {
+        let _gate = self.gate();
+        let now = Instant::now();
+        self.store
+            .get(key)
+            .filter(|r| !r.is_expired(now))
+            .map(|r| r.value.kind())
+    }
+
+    /// Number of keys, including expired ones not yet removed.
+    pub fn dbsize(&self) -> usize {
+        self.keys.load(Ordering::Relaxed)
+    }
+
+    /// Moves the value at `from`, with its expiry, to `to`, replacing any
+    /// value there unless `replace` is false, in which case nothing happens
+    /// and false is returned if `to` exists. Fails with `NotFound` if `from`
+    /// does not exist. No other operation sees the keys in between; like
+    /// `transaction`, it waits for every in-flight operation to finish.
+    pub fn rename(&self, from: &str, to: &str, replace: bool) -> Result<bool, BlinkError> {
+        self.atomically(|| self.rename_entry(from, to, replace))
+    }
+
+    /// Runs `f` in a transaction, or directly if one is already running on
+    /// this thread.
+    fn atomically<T>(&self, f: impl FnOnce() -> Result<T, BlinkError>) -> Result<T, BlinkError> {
+        if self.in_txn() {
+            return f();
+        }
+        self.transaction(&[], f)
+            .unwrap_or_else(|| Err(BlinkError::Internal("transaction aborted".into())))
+    }
+
+    /// `rename` inside a transaction, which rolls it back if making room
+    /// for `to` fails.
+    fn rename_entry(&self, from: &str, to: &str, replace: bool) -> Result<bool, BlinkError> {
+        let now = Instant::now();
+        let live = |key: &str| self.store.get(key).is_some_and(|r| !r.is_expired(now));
+        if !live(from) {
+            if self.remove_entry_if(from, |r| r.is_expired(now)).is_some() {
+                self.emit(KeyEventKind::Expire, from);
+            }
+            return Err(BlinkError::NotFound(from.to_owned()));
+        }
+        if !replace && live(to) {
+            return Ok(false);
+        }
+        if from == to {
+            return Ok(true);
+        }
+        let Some((_, mut record)) = self.store.remove(from) else {
+            return Err(BlinkError::NotFound(from.to_owned()));
+        };
+        self.log_undo(from, Some(&record));
+        self.release(from, &record);
+        self.notify_watchers(from);
+        self.emit(KeyEventKind::Delete, from);
+
+        record.size = record.size - from.len() as u64 + to.len() as u64;
+        let old_size = self.store.get(to).map_or(0, |r| r.size);
+        self.make_room(record.size, old_size, to)?;
+        record.version = self.versions.fetch_add(1, Ordering::Relaxed);
+        self.charge(to, &record);
+        let previous = self.store.insert(to.to_owned(), record);
+        self.log_undo(to, previous.as_ref());
+        if let Some(previous) = &previous {
+            self.release(to, previous);
+        }
+        self.written(to);
+        Ok(true)
+    }
+
+    /// Counts `cost` requests against the limit of `max` per `window` for
+    /// `key`, as one atomic step (GCRA: the key stores when its bucket will
+    /// be full again). Denied requests change nothing. The entry expires
+    /// once the bucket is full, so idle limiters free their memory.
+    pub fn rate_limit(
+        &self,
+        key: &str,
+        max: u32,
+        window: Duration,
+        cost: u32,
+    ) -> Result<RateLimitStatus, BlinkError> {
+        if max == 0 || window.is_zero() {
+            return Err(BlinkError::Config("rate limit and window must be positive".into()));
+        }
+        if cost > max {
+            return Err(BlinkError::Config("cost must not exceed the rate limit".into()));
+        }
+        let _gate = self.gate();
+        let now = Instant::now();
+        // Time one unit of cost takes to be paid back.
+        let interval = (window / max).max(Duration::from_nanos(1));
+        let decide = |tat: Instant| {
+            let tat = tat.max(now);
+            let next = tat + interval * cost;
+            let (used, allow_at) = if next <= now + window {
+                (next, None)
+            } else {
+                (tat, Some(next - window))
+            };
+            let status = RateLimitStatus {
+                allowed: allow_at.is_none(),
+                remaining: (window.saturating_sub(used - now).as_nanos() / interval.as_nanos())
+                    as u64,
+                retry_after: allow_at.map_or(Duration::ZERO, |t| t - now),
+                reset_after: used - now,
+            };
+            (status, next)
+        };
+        let need = match self.store.get(key) {
+            Some(record) if !record.is_expired(now) => match record.value {
+                Value::RateLimit(_) => 0,
+                _ => return Err(BlinkError::WrongType),
+            },
+            _ => entry_size(key, &Value::RateLimit(now)),
+        };
+        if cost == 0 {
+            let tat = self.read(key, |record, _| match record.value {
+                Value::RateLimit(tat) => tat,
+                _ => now,
+            });
+            return Ok(decide(tat.unwrap_or(now)).0);
+        }
+        self.make_room(need, 0, key)?;
+
+        let counter = self.next_counter();
+        let status = match self.store.entry(key.to_owned()) {
+            Entry::Occupied(mut occupied) if !occupied.get().is_expired(now) => {
+                let Value::RateLimit(tat) = occupied.get().value else {
+                    return Err(BlinkError::WrongType);
+                };
+                let (status, next) = decide(tat);
+                if !status.allowed {
+                    return Ok(status);
+                }
+                self.log_undo(key, Some(occupied.get()));
+                let record = occupied.get_mut();
+                if record.expires_at.is_none() {
+                    self.expiring.fetch_add(1, Ordering::Relaxed);
+                }
+                record.value = Value::RateLimit(next);
+                record.counter = counter;
+                record.expires_at = Some(next);
+                record.version = self.versions.fetch_add(1, Ordering::Relaxed);
+                status
+            }
+            // Missing, or expired and replaced by a full bucket.
+            entry => {
+                let (status, next) = decide(now);
+                let record = Record {
+                    value: Value::RateLimit(next),
+                    counter,
+                    stale_at: None,
+                    expires_at: Some(next),
+                    version: self.versions.fetch_add(1, Ordering::Relaxed),
+                    size: entry_size(key, &Value::RateLimit(next)),
+                };
+                self.insert_record(key, entry, record);
+                status
+            }
+        };
+        self.written(key);
+        Ok(status)
+    }
+
+    /// Takes the lock at `key` for `ttl` unless another owner holds it, and
+    /// returns its fencing token; `None` if it is held. Tokens come from the
+    /// version counter, so each new acquisition of a key gets a larger one.
+    /// Without `owner`, the owner is the token in decimal. If `owner`
+    /// already holds the lock, its TTL is renewed and its token returned,
+    /// so a retried request is harmless. The lock is released when `ttl`
+    /// elapses.
+    pub fn lock(&self, key: &str, owner: Option<&str>, ttl: Duration) -> Result<Option<u64>, BlinkError> {
+        if ttl.is_zero() {
+            return Err(BlinkError::Config("lock ttl must be positive".into()));
+        }
+        let _gate = self.gate();
+        let now = Instant::now();
+        // A generated owner is a u64 in decimal, at most 20 bytes.
+        let owner_len = owner.map_or(20, str::len);
+        let need = key.len() as u64 + lock_size(owner_len);
+        if self.store.get(key).is_some_and(|r| !r.is_expired(now)) {
+            return self.extend_lock_entry(key, owner, ttl, now);
+        }
+        self.make_room(need, 0, key)?;
+
+        let counter = self.next_counter();
+        let token = match self.store.entry(key.to_owned()) {
+            Entry::Occupied(occupied) if !occupied.get().is_expired(now) => {
+                drop(occupied);
+                return self.extend_lock_entry(key, owner, ttl, now);
+            }
+            // Free, or held by a lock that has expired.
+            entry => {
+                // Taken under the entry's lock so tokens for this key are
+                // handed out in acquisition order.
+                let token = self.versions.fetch_add(1, Ordering::Relaxed);
+                let value = Value::Lock {
+                    owner: owner.map_or_else(|| token.to_string(), str::to_owned),
+                    token,
+                };
+                let record = Record {
+                    size: entry_size(key, &value),
+                    value,
+                    counter,
+                    stale_at: None,
+                    expires_at: Some(now + ttl),
+                    version: token,
+                };
+                self.insert_record(key, entry, record);
+                token
+            }
+        };
+        self.written(key);
+        Ok(Some(token))
+    }
+
+    /// Renews the TTL of the lock at `key` if `owner` holds it; returns its
+    /// token, or `None` if the lock is free or held by someone else.
+    pub fn extend_lock(&self, key: &str, owner: &str, ttl: Duration) -> Result<Option<u64>, BlinkError> {
+        if ttl.is_zero() {
+            return Err(BlinkError::Config("lock ttl must be positive".into()));
+        }
+        let _gate = self.gate();
+        self.extend_lock_entry(key, Some(owner), ttl, Instant::now())
+    }
+
+    fn extend_lock_entry(
+        &self,
+        key: &str,
+        owner: Option<&str>,
+        ttl: Duration,
+        now: Instant,
+    ) -> Result<Option<u64>, BlinkError> {
+        let Some(mut record) = self.store.get_mut(key) else {
+            return Ok(None);
+        };
+        if record.is_expired(now) {
+            return Ok(None);
+        }
+        let Value::Lock { owner: holder, token } = &record.value else {
+            return Err(BlinkError::WrongType);
+        };
+        if owner != Some(holder.as_str()) {
+            return Ok(None);
+        }
+        let token = *token;
+        self.log_undo(key, Some(&record));
+        record.expires_at = Some(now + ttl);
+        Ok(Some(token))
+    }
+
+    /// Releases the lock at `key` if `owner` holds it; returns false if it
+    /// is free, expired or held by someone else.
+    pub fn unlock(&self, key: &str, owner: &str) -> Result<bool, BlinkError> {
+        let _gate = self.gate();
+        let now = Instant::now();
+        let mut wrong_type = false;
+        let removed = self.remove_entry_if(key, |record| match &record.value {
+            _ if record.is_expired(now) => false,
+            Value::Lock { owner: holder, .. } => holder == owner,
+            _ => {
+                wrong_type = true;
+                false
+            }
+        });
+        if wrong_type {
+            return Err(BlinkError::WrongType);
+        }
+        if removed.is_some() {
+            self.emit(KeyEventKind::Delete, key);
+        }
+        Ok(removed.is_some())
+    }
+
+    /// Adds `elements` to the HyperLogLog at `key`, creating it if needed;
+    /// returns true if its estimate may have changed. Every HyperLogLog
+    /// takes `HyperLogLog::SIZE` bytes, however many elements it has seen.
+    pub fn pfadd(&self, key: &str, elements: &[&[u8]]) -> Result<bool, BlinkError> {
+        self.change_value(
+            key,
+            "hll",
+            || Value::HyperLogLog(Box::default()),
+            |_| (0, 0),
+            |current| {
+                let Value::HyperLogLog(hll) = current else {
+                    unreachable!()
+                };
+                let changed = elements.iter().fold(false, |changed, e| hll.add(e) | changed);
+                Ok((changed, 0, 0))
+            },
+            |&changed| changed,
+        )
+    }
+
+    /// Estimated number of distinct elements added to the HyperLogLogs at
+    /// `keys`, counted once even if added to several; missing keys count
+    /// as empty.
+    pub fn pfcount(&self, keys: &[&str]) -> Result<u64, BlinkError> {
+        let _gate = self.gate();
+        if let [key] = keys {
+            return Ok(self.read_hll(key, |hll| hll.count())?.unwrap_or(0));
+        }
+        let mut union = HyperLogLog::default();
+        for key in keys {
+            self.read_hll(key, |hll| union.merge(hll))?;
+        }
+        Ok(union.count())
+    }
+
+    /// Merges the HyperLogLogs at `sources` into the one at `dest`,
+    /// creating it if needed, in one step. Like `transaction`, it waits for
+    /// every in-flight operation to finish.
+    pub fn pfmerge(&self, dest: &str, sources: &[&str]) -> Result<(), BlinkError> {
+        self.atomically(|| {
+            let mut union = HyperLogLog::default();
+            for key in sources {
+                self.read_hll(key, |hll| union.merge(hll))?;
+            }
+            self.update_value(
+                dest,
+                "hll",
+                || Value::HyperLogLog(Box::default()),
+                |_| (0, 0),
+                |current| {
+                    let Value::HyperLogLog(hll) = current else {
+                        unreachable!()
+                    };
+                    hll.merge(&union);
+                    Ok(((), 0, 0))
+                },
+            )
+        })
+    }
+
+    fn read_hll<T>(&self, key: &str, f: impl FnOnce(&HyperLogLog) -> T) -> Result<Option<T>, BlinkError> {
+        self.read(key, |record, _| match &record.value {
+            Value::HyperLogLog(hll) => Ok(f(hll)),
+            _ => Err(BlinkError::WrongType),
+        })
+        .transpose()
+    }
+
+    /// Creates an empty Bloom filter at `key` sized for `capacity` items
+    /// at a false-positive rate of `error_rate`; returns false and changes
+    /// nothing if `key` exists. Filters created by `bf_add` use
+    /// `DEFAULT_BLOOM_CAPACITY` and `DEFAULT_BLOOM_ERROR_RATE`.
+    pub fn bf_reserve(&self, key: &str, capacity: u64, error_rate: f64) -> Result<bool, BlinkError> {
+        let need = (key.len() as u64).saturating_add(BloomFilter::size_for(capacity, error_rate)?);
+        if need > self.limit_bytes() {
+            return Err(BlinkError::AtCapacity);
+        }
+        let _gate = self.gate();
+        let now = Instant::now();
+        if self.store.get(key).is_some_and(|r| !r.is_expired(now)) {
+            return Ok(false);
+        }
+        let filter = BloomFilter::new(capacity, error_rate)?;
+        self.make_room(need, 0, key)?;
+
+        let counter = self.next_counter();
+        match self.store.entry(key.to_owned()) {
+            Entry::Occupied(occupied) if !occupied.get().is_expired(now) => return Ok(false),
+            entry => {
+                let record = Record {
+                    value: Value::Bloom(Box::new(filter)),
+                    counter,
+                    stale_at: None,
+                    expires_at: None,
+                    version: self.versions.fetch_add(1, Ordering::Relaxed),
+                    size: need,
+                };
+                self.insert_record(key, entry, record);
+            }
+        }
+        self.written(key);
+        Ok(true)
+    }
+
+    /// Adds `item` to the Bloom filter at `key`, creating a default-sized
+    /// one if needed; returns true if it was definitely not there before.
+    pub fn bf_add(&self, key: &str, item: &[u8]) -> Result<bool, BlinkError> {
+        self.change_value(
+            key,
+            "bloom",
+            || Value::Bloom(Box::default()),
+            |_| (0, 0),
+            |current| {
+                let Value::Bloom(filter) = current else {
+                    unreachable!()
+                };
+                Ok((filter.add(item), 0, 0))
+            },
+            |&added| added,
+        )
+    }
+
+    /// Whether `item` may have been added to the Bloom filter at `key`:
+    /// false is certain, true is wrong at about the filter's error rate.
+    pub fn bf_exists(&self, key: &str, item: &[u8]) -> Result<bool, BlinkError> {
+        let _gate = self.gate();
+        self.read(key, |record, _| match &record.value {
+            Value::Bloom(filter) => Ok(filter.contains(item)),
+            _ => Err(BlinkError::WrongType),
+        })
+        .transpose()
+        .map(|found| found.unwrap_or(false))
+    }
+
+    /// Appends an entry to the stream at `key`, creating it if needed, and
+    /// returns its ID. Without `id`, one is made from the clock. With
+    /// `max_len`, the oldest entries are then trimmed so at most that many
+    /// remain.
+    pub fn xadd(
+        &self,
+        key: &str,
+        id: Option<StreamId>,
+        fields: Vec<(String, Bytes)>,
+        max_len: Option<usize>,
+    ) -> Result<StreamId, BlinkError> {
+        let added = crate::stream::entry_size(&fields);
+        self.update_value(
+            key,
+            "stream",
+            || Value::Stream(Stream::default()),
+            |current| {
+                let Value::Stream(stream) = current else {
+                    unreachable!()
+                };
+                let trimmed = max_len.map_or(0, |max| (stream.len() + 1).saturating_sub(max));
+                (added, stream.oldest_size(trimmed))
+            },
+            |current| {
+                let Value::Stream(stream) = current else {
+                    unreachable!()
+                };
+                let id = stream.next_id(id)?;
+                let added = stream.add(id, fields);
+                let (_, freed) = max_len.map_or((0, 0), |max| stream.trim(max));
+                Ok((id, added, freed))
+            },
+        )
+    }
+
+    /// Number of entries in the stream at `key`; 0 if it is missing.
+    pub fn xlen(&self, key: &str) -> Result<usize, BlinkError> {
+        let _gate = self.gate();
+        Ok(self.read_stream(key, |stream| stream.len())?.unwrap_or(0))
+    }
+
+    /// Up to `count` entries of the stream at `key` with IDs from `start`
+    /// to `end` inclusive, oldest first.
+    pub fn xrange(
+        &self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: usize,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        let _gate = self.gate();
+        Ok(self
+            .read_stream(key, |stream| {
+                stream.range(Bound::Included(start), Bound::Included(end), count)
+            })?
+            .unwrap_or_default())
+    }
+
+    /// Removes the oldest entries of the stream at `key` until at most
+    /// `max_len` remain; returns how many were removed.
+    pub fn xtrim(&self, key: &str, max_len: usize) -> Result<usize, BlinkError> {
+        self.take_from_collection(key, "stream", |current| {
+            let Value::Stream(stream) = current else {
+                unreachable!()
+            };
+            match stream.trim(max_len) {
+                (0, _) => None,
+                trimmed => Some(trimmed),
+            }
+        })
+    }
+
+    /// Up to `count` entries of the stream at `key` with IDs after `after`,
+    /// oldest first.
+    pub fn xread(&self, key: &str, after: StreamId, count: usize) -> Result<Vec<StreamEntry>, BlinkError> {
+        let _gate = self.gate();
+        Ok(self
+            .read_stream(key, |stream| stream.range(Bound::Excluded(after), Bound::Unbounded, count))?
+            .unwrap_or_default())
+    }
+
+    /// Like `xread`, but waits up to `timeout` (indefinitely if `None`) for
+    /// an entry if there are none after `after`. Without `after`, only
+    /// entries added from now on are returned. Returns no entries on
+    /// timeout.
+    pub async fn xread_blocking(
+        &self,
+        key: &str,
+        after: Option<StreamId>,
+        count: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        let deadline = timeout.map(|t| Instant::now() + t.min(MAX_WAIT));
+        // Registered before the first read so an entry in between is not missed.
+        let mut watch = KeyWatch::register(self, key);
+        let after = match after {
+            Some(after) => after,
+            None => self.stream_last_id(key)?,
+        };
+        loop {
+            let entries = self.xread(key, after, count)?;
+            if !entries.is_empty() || !watch.changed_until(deadline).await {
+                return Ok(entries);
+            }
+        }
+    }
+
+    /// Creates consumer group `group` of the stream at `key`, creating the
+    /// stream if needed. The group hands out entries after `start`, or,
+    /// without it, entries added from now on. Returns false and changes
+    /// nothing if the group exists.
+    pub fn xgroup_create(&self, key: &str, group: &str, start: Option<StreamId>) -> Result<bool, BlinkError> {
+        {
+            let _gate = self.gate();
+            if self.read_stream(key, |stream| stream.has_group(group))? == Some(true) {
+                return Ok(false);
+            }
+        }
+        let added = crate::stream::group_size(group);
+        self.change_value(
+            key,
+            "stream",
+            || Value::Stream(Stream::default()),
+            |_| (added, 0),
+            |current| {
+                let Value::Stream(stream) = current else {
+                    unreachable!()
+                };
+                let start = start.unwrap_or(stream.last_id());
+                Ok(stream
+                    .create_group(group, start)
+                    .map_or((false, 0, 0), |added| (true, added, 0)))
+            },
+            |&created| created,
+        )
+    }
+
+    /// Removes consumer group `group` of the stream at `key` with its
+    /// pending entries; returns false if there was no such group.
+    pub fn xgroup_destroy(&self, key: &str, group: &str) -> Result<bool, BlinkError> {
+        self.take_from_collection(key, "stream", |current| {
+            let Value::Stream(stream) = current else {
+                unreachable!()
+            };
+            stream.destroy_group(group).map(|freed| (true, freed))
+        })
+    }
+
+    /// Hands up to `count` entries of the stream at `key` to `consumer` of
+    /// `group`. Without `after`, these are entries no consumer of the group
+    /// has been given yet; they stay pending until `xack`. With `after`,
+    /// they are the consumer's own pending entries with IDs after it, for
+    /// recovering after a crash. Fails with `NoGroup` if the group (or the
+    /// stream) does not exist. This is a read: the stream's version stays
+    /// the same and no `set` event or wakeup is sent.
+    pub fn xreadgroup(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: usize,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        {
+            let _gate = self.gate();
+            let ready = self.read_stream(key, |stream| stream.can_deliver(group, consumer, after))?;
+            match ready.transpose()? {
+                None => return Err(BlinkError::NoGroup(group.to_owned())),
+                Some(false) => return Ok(Vec::new()),
+                Some(true) => {}
+            }
+        }
+        let now = Instant::now();
+        self.change_value(
+            key,
+            "stream",
+            || Value::Stream(Stream::default()),
+            |current| {
+                let Value::Stream(stream) = current else {
+                    unreachable!()
+                };
+                let need = match after {
+                    None => stream.deliver_cost(group, consumer, count),
+                    Some(_) => 0,
+                };
+                (need, 0)
+            },
+            |current| {
+                let Value::Stream(stream) = current else {
+                    unreachable!()
+                };
+                let (entries, added) = stream.deliver(group, consumer, after, count, now)?;
+                Ok((entries, added, 0))
+            },
+            |_| false,
+        )
+    }
+
+    /// Like `xreadgroup` for new entries, but waits up to `timeout`
+    /// (indefinitely if `None`) for one to be added. Returns no entries on
+    /// timeout.
+    pub async fn xreadgroup_blocking(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<StreamEntry>, BlinkError> {
+        let deadline = timeout.map(|t| Instant::now() + t.min(MAX_WAIT));
+        let mut watch = KeyWatch::register(self, key);
+        loop {
+            let entries = self.xreadgroup(key, group, consumer, None, count)?;
+            if !entries.is_empty() || !watch.changed_until(deadline).await {
+                return Ok(entries);
+            }
+        }
+    }
+
+    /// Acknowledges entries handed out by `group`, removing them from its
+    /// pending list; returns how many were pending.
+    pub fn xack(&self, key: &str, group: &str, ids: &[StreamId]) -> Result<usize, BlinkError> {
+        self.take_from_collection(key, "stream", |current| {
+            let Value::Stream(stream) = current else {
+                unreachable!()
+            };
+            match stream.ack(group, ids) {
+                (0, _) => None,
+                acked => Some(acked),
+            }
+        })
+    }
+
+    /// Entries handed out by `group` and not yet acknowledged, oldest ID
+    /// first.
+    pub fn xpending(&self, key: &str, group: &str) -> Result<Vec<PendingEntry>, BlinkError> {
+        let _gate = self.gate();
+        let now = Instant::now();
+        self.read_stream(key, |stream| stream.pending(group, now))?
+            .unwrap_or_else(|| Err(BlinkError::NoGroup(group.to_owned())))
+    }
+
+    fn read_stream<T>(&self, key: &str, f: impl FnOnce(&Stream) -> T) -> Result<Option<T>, BlinkError> {
+        self.read(key, |record, _| match &record.value {
+            Value::Stream(stream) => Ok(f(stream)),
+            _ => Err(BlinkError::WrongType),
+        })
+        .transpose()
+    }
+
+    /// ID of the newest entry ever added to the stream at `key`; `MIN` if
+    /// it is missing.
+    fn stream_last_id(&self, key: &str) -> Result<StreamId, BlinkError> {
+        let _gate = self.gate();
+        Ok(self
+            .read_stream(key, |stream| stream.last_id())?
+            .unwrap_or(StreamId::MIN))
+    }
+
+    /// Makes room for an entry that needs `need` bytes and replaces `freed`
+    /// bytes, evicting unless the policy is `noeviction`. Never evicts `key`.
+    /// Fails with `AtCapacity` if nothing more can be evicted.
+    fn make_room(&self, need: u64, freed: u64, key: &str) -> Result<(), BlinkError> {
+        let fits = || self.current_usage.load(Ordering::Acquire) + need <= self.limit_bytes() + freed;
+        if fits() {
+            return Ok(());
+        }
+        if self.eviction_policy() == EvictionPolicy::NoEviction {
+            return Err(BlinkError::AtCapacity);
+        }
+        self.evict_until_room(need.saturating_sub(freed), key);
+        if fits() {
+            Ok(())
+        } else {
+            Err(BlinkError::AtCapacity)
+        }
+    }
+
+    /// Removes every entry past its hard expiry; returns how many. Reads
+    /// already drop expired entries, so this only reclaims memory held by
+    /// keys nobody reads. The server runs it periodically.
+    pub fn purge_expired(&self) -> usize {
+        if self.expiring.load(Ordering::Relaxed) == 0 {
+            return 0;
+        }
+        let _gate = self.gate();
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .store
+            .iter()
+            .filter(|r| r.is_expired(now))
+            .map(|r| r.key().clone())
+            .collect();
+        let mut purged = 0;
+        for key in &expired {
+            if self.remove_entry_if(key, |r| r.is_expired(now)).is_some() {
+                self.emit(KeyEventKind::Expire, key);
+                purged += 1;
+            }
+        }
+        purged
+    }
+
+    fn next_counter(&self) -> u64 {
+        self.access_counter.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn limit_bytes(&self) -> u64 {
+        self.limit_bytes.load(Ordering::Acquire)
+    }
+
+    /// Changes the memory limit at runtime. Lowering it evicts immediately
+    /// (unless the policy is `noeviction`).
+    pub fn set_limit_bytes(&self, limit_bytes: u64) {
+        self.limit_bytes.store(limit_bytes, Ordering::Release);
+        if self.eviction_policy() != EvictionPolicy::NoEviction {
+            let _gate = self.gate();
+            self.evict_until_room(0, "");
+        }
+    }
+
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        if self.no_eviction.load(Ordering::Acquire) {
+            EvictionPolicy::NoEviction
+        } else {
+            EvictionPolicy::AllKeysLru
+        }
+    }
+
+    pub fn set_eviction_policy(&self, policy: EvictionPolicy) {
+        self.no_eviction
+            .store(policy == EvictionPolicy::NoEviction, Ordering::Release);
+    }
+
+    pub fn eviction_samples(&self) -> usize {
+        self.eviction_samples.load(Ordering::Acquire)
+    }
+
+    pub fn set_eviction_samples(&self, samples: usize) -> Result<(), BlinkError> {
+        if samples == 0 {
+            return Err(BlinkError::Config("eviction samples must be at least 1".into()));
+        }
+        self.eviction_samples.store(samples, Ordering::Release);
+        Ok(())
+    }
+
+    /// Sets (or replaces) the byte budget for keys starting with `prefix`.
+    /// A key belongs to the group with the longest matching prefix. A group
+    /// may exceed its budget while the engine has room, but once the global
+    /// limit is hit, over-budget groups are evicted from first.
+    pub fn set_quota(&self, prefix: &str, limit_bytes: u64) -> Result<(), BlinkError> {
+        if prefix.is_empty() {
+            return Err(BlinkError::Config("quota prefix must not be empty".into()));
+        }
+        {
+            let mut quotas = self.quotas.write().unwrap_or_else(|e| e.into_inner());
+            quotas.retain(|g| g.prefix != prefix);
+            quotas.push(Arc::new(QuotaGroup {
+                prefix: prefix.to_owned(),
+                limit_bytes,
+                usage: AtomicU64::new(0),
+            }));
+        }
+        self.recompute_quota_usage();
+        Ok(())
+    }
+
+    /// Removes the quota for `prefix`; returns false if there was none.
+    pub fn remove_quota(&self, prefix: &str) -> bool {
+        let removed = {
+            let mut quotas = self.quotas.write().unwrap_or_else(|e| e.into_inner());
+            let before = quotas.len();
+            quotas.retain(|g| g.prefix != prefix);
+            quotas.len() != before
+        };
+        if removed {
+            self.recompute_quota_usage();
+        }
+        removed
+    }
+
+    /// Usage of every quota group, sorted by prefix.
+    pub fn quota_usage(&self) -> Vec<QuotaUsage> {
+        let quotas = self.quotas.read().unwrap_or_else(|e| e.into_inner());
+        let mut out: Vec<QuotaUsage> = quotas
+            .iter()
+            .map(|g| QuotaUsage {
+                prefix: g.prefix.clone(),
+                used_bytes: g.usage.load(Ordering::Acquire),
+                limit_bytes: g.limit_bytes,
+            })
+            .collect();
+        out.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        out
+    }
 
-/// In-memory engine with sampled eviction and memory-cap enforcement.
-pub struct MemoryEngine {
-    store: DashMap<String, (Bytes, u64)>,
-    limit_bytes: u64,
-    current_usage: AtomicU64,
-    access_counter: AtomicU64,
-}
+    /// The quota group `key` belongs to (longest matching prefix), if any.
+    fn quota_group(&self, key: &str) -> Option<Arc<QuotaGroup>> {
+        let quotas = self.quotas.read().unwrap_or_else(|e| e.into_inner());
+        if quotas.is_empty() {
+            return None;
+        }
+        quotas
+            .iter()
+            .filter(|g| key.starts_with(&g.prefix))
+            .max_by_key(|g| g.prefix.len())
+            .cloned()
+    }
 
-impl MemoryEngine {
-    pub fn new(limit_bytes: u64) -> Result<Self, BlinkError> {
-        Ok(Self {
-            store: DashMap::new(),
-            limit_bytes,
-            current_usage: AtomicU64::new(0),
-            access_counter: AtomicU64::new(0),
-        })
+    /// Rebuilds every group's usage from the store after the groups change.
+    fn recompute_quota_usage(&self) {
+        let quotas = self.quotas.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for group in &quotas {
+            group.usage.store(0, Ordering::Release);
+        }
+        if quotas.is_empty() {
+            return;
+        }
+        for entry in self.store.iter() {
+            let key = entry.key();
+            if let Some(group) = quotas
+                .iter()
+                .filter(|g| key.starts_with(&g.prefix))
+                .max_by_key(|g| g.prefix.len())
+            {
+                group
+                    .usage
+                    .fetch_add(entry.value().size, Ordering::Release);
+            }
+        }
     }
 
-    fn next_counter(&self) -> u64 {
-        self.access_counter.fetch_add(1, Ordering::Relaxed)
+    /// The first group currently over its budget, if any.
+    fn over_quota_group(&self) -> Option<Arc<QuotaGroup>> {
+        let quotas = self.quotas.read().unwrap_or_else(|e| e.into_inner());
+        quotas
+            .iter()
+            .find(|g| g.usage.load(Ordering::Acquire) > g.limit_bytes)
+            .cloned()
     }
 
-    /// Evicts entries with the lowest access counter until there is room
-    /// for `need_bytes` additional bytes.
-    fn evict_until_room(&self, need_bytes: u64) {
-        while self.current_usage.load(Ordering::Acquire) + need_bytes > self.limit_bytes {
-            let mut victim_key: Option<String> = None;
-            let mut victim_counter = u64::MAX;
-            let mut sampled = 0;
+    /// Samples a few evictable entries (optionally only those starting with
+    /// `prefix`) and returns the one with the lowest access counter, never
+    /// `exclude`. Expired entries are taken first. With a prefix, at most
+    /// `EVICTION_SCAN_FACTOR` entries per sample are looked at, and `None`
+    /// means none of them matched.
+    fn sample_victim(&self, prefix: Option<&str>, exclude: &str) -> Option<String> {
+        let now = Instant::now();
+        let mut victim_key: Option<String> = None;
+        let mut victim_counter = u64::MAX;
+        let samples = self.eviction_samples();
+        let scan = match prefix {
+            Some(_) => samples.saturating_mul(EVICTION_SCAN_FACTOR),
+            None => usize::MAX,
+        };
 
-            for entry in self.store.iter() {
-                if sampled >= EVICTION_SAMPLES {
-                    break;
-                }
-                let counter = entry.value().1;
-                if counter < victim_counter {
-                    victim_key = Some(entry.key().clone());
-                    victim_counter = counter;
-                }
-                sampled += 1;
+        for entry in self
+            .store
+            .iter()
+            .take(scan)
+            .filter(|e| {
+                e.key() != exclude
+                    && prefix.is_none_or(|p| e.key().starts_with(p))
+                    && e.is_evictable(now)
+            })
+            .take(samples)
+        {
+            let record = entry.value();
+            let counter = if record.is_expired(now) { 0 } else { record.counter };
+            if counter < victim_counter {
+                victim_key = Some(entry.key().clone());
+                victim_counter = counter;
+            }
+        }
+        victim_key
+    }
+
+    /// Removes `key`, releasing its bytes from the global and quota usage.
+    fn remove_entry(&self, key: &str) -> Option<u64> {
+        self.remove_entry_if(key, |_| true)
+    }
+
+    /// Removes `key` if `pred` holds for its record at removal time.
+    fn remove_entry_if(&self, key: &str, pred: impl FnOnce(&Record) -> bool) -> Option<u64> {
+        let (_, record) = self.store.remove_if(key, |_, r| pred(r))?;
+        self.log_undo(key, Some(&record));
+        let freed = self.release(key, &record);
+        self.notify_watchers(key);
+        Some(freed)
+    }
+
+    /// Adds a record that is being inserted to the key and usage counters.
+    fn charge(&self, key: &str, record: &Record) {
+        self.keys.fetch_add(1, Ordering::Relaxed);
+        if record.expires_at.is_some() {
+            self.expiring.fetch_add(1, Ordering::Relaxed);
+        }
+        self.grow(key, record.size);
+    }
+
+    /// Puts a new record into `entry`, which is vacant or holds an expired
+    /// record, and accounts for both.
+    fn insert_record(&self, key: &str, entry: Entry<'_, String, Record>, record: Record) {
+        self.charge(key, &record);
+        let previous = match entry {
+            Entry::Occupied(mut expired) => Some(expired.insert(record)),
+            Entry::Vacant(vacant) => {
+                vacant.insert(record);
+                None
+            }
+        };
+        self.log_undo(key, previous.as_ref());
+        if let Some(previous) = &previous {
+            self.release(key, previous);
+        }
+    }
+
+    /// Takes a removed record out of the key and usage counters; returns
+    /// its size.
+    fn release(&self, key: &str, record: &Record) -> u64 {
+        let version = self.versions.fetch_add(1, Ordering::Relaxed);
+        self.removals[removal_stripe(key)].store(version, Ordering::Release);
+        self.keys.fetch_sub(1, Ordering::Relaxed);
+        if record.expires_at.is_some() {
+            self.expiring.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.shrink(key, record.size);
+        record.size
+    }
+
+    /// Adds `bytes` to the global usage and that of `key`'s quota group.
+    fn grow(&self, key: &str, bytes: u64) {
+        self.current_usage.fetch_add(bytes, Ordering::Release);
+        if let Some(group) = self.quota_group(key) {
+            group.usage.fetch_add(bytes, Ordering::Release);
+        }
+    }
+
+    fn shrink(&self, key: &str, bytes: u64) {
+        self.current_usage.fetch_sub(bytes, Ordering::Release);
+        if let Some(group) = self.quota_group(key) {
+            group.usage.fetch_sub(bytes, Ordering::Release);
+        }
+    }
+
+    fn evict(&self, key: &str) -> bool {
+        match self.remove_entry(key) {
+            Some(freed) => {
+                trace!(key = %key, freed_bytes = freed, "evicted");
+                self.emit(KeyEventKind::Evict, key);
+                true
             }
+            None => false,
+        }
+    }
 
-            match victim_key {
+    /// Evicts entries with the lowest access counter until there is room
+    /// for `need_bytes` additional bytes. Groups over their quota are
+    /// drained first so one tenant cannot push out everyone else's keys;
+    /// if a round's scan finds none of their keys, it samples every key.
+    fn evict_until_room(&self, need_bytes: u64, exclude: &str) {
+        while self.current_usage.load(Ordering::Acquire) + need_bytes > self.limit_bytes() {
+            let victim = self
+                .over_quota_group()
+                .and_then(|g| self.sample_victim(Some(&g.prefix), exclude))
+                .or_else(|| self.sample_victim(None, exclude));
+            match victim {
                 Some(key) => {
-                    if let Some((_, (value, _))) = self.store.remove(&key) {
-                        let freed = entry_size(&key, &value);
-                        self.current_usage.fetch_sub(freed, Ordering::Release);
-                        trace!(key = %key, freed_bytes = freed, "evicted");
-                    }
+                    self.evict(&key);
                 }
                 None => break,
             }
@@ -81,45 +2545,103 @@ impl MemoryEngine {
 }
 
 impl BlinkStorage for MemoryEngine {
+    /// Stale values are returned like fresh ones; use `get_with_freshness`
+    /// to tell them apart.
     fn get(&self, key: &str) -> Result<Option<Bytes>, BlinkError> {
-        if let Some(mut entry) = self.store.get_mut(key) {
-            entry.value_mut().1 = self.next_counter();
-            Ok(Some(entry.value().0.clone()))
-        } else {
-            Ok(None)
-        }
+        Ok(self.get_with_freshness(key)?.map(|(value, _)| value))
     }
 
     fn set(&self, key: &str, value: Bytes) -> Result<(), BlinkError> {
-        let need = entry_size(key, &value);
-        let old_size = self
-            .store
-            .get(key)
-            .map(|e| entry_size(key, &e.value().0))
-            .unwrap_or(0);
+        self.set_with_expiry(key, value, Expiry::default())
+    }
 
-        let current = self.current_usage.load(Ordering::Acquire);
-        if current + need - old_size > self.limit_bytes {
-            self.evict_until_room(need.saturating_sub(old_size));
+    fn delete(&self, key: &str) -> Result<bool, BlinkError> {
+        let _gate = self.gate();
+        if self.remove_entry(key).is_none() {
+            return Ok(false);
         }
+        self.emit(KeyEventKind::Delete, key);
+        Ok(true)
+    }
 
-        let counter = self.next_counter();
-        self.store.insert(key.to_owned(), (value, counter));
-        self.current_usage.fetch_add(need, Ordering::Release);
-        if old_size > 0 {
-            self.current_usage.fetch_sub(old_size, Ordering::Release);
+    /// Like `set`, clears any expiry. Fails with `WrongType`, changing
+    /// nothing, if the key holds a collection.
+    fn getset(&self, key: &str, value: Bytes) -> Result<Option<Bytes>, BlinkError> {
+        let now = Instant::now();
+        Ok(self
+            .replace(key, value, Expiry::default(), true)?
+            .filter(|old| !old.is_expired(now))
+            .and_then(|old| match old.value {
+                Value::Str(bytes) => Some(bytes),
+                _ => None,
+            }))
+    }
+
+    fn getdel(&self, key: &str) -> Result<Option<Bytes>, BlinkError> {
+        let _gate = self.gate();
+        let now = Instant::now();
+        let mut found = None;
+        let removed = self.remove_entry_if(key, |record| {
+            if record.is_expired(now) {
+                return true;
+            }
+            match &record.value {
+                Value::Str(bytes) => {
+                    found = Some(Ok(bytes.clone()));
+                    true
+                }
+                _ => {
+                    found = Some(Err(BlinkError::WrongType));
+                    false
+                }
+            }
+        });
+        match found {
+            Some(Ok(value)) => {
+                self.emit(KeyEventKind::Delete, key);
+                Ok(Some(value))
+            }
+            Some(Err(e)) => Err(e),
+            None => {
+                if removed.is_some() {
+                    self.emit(KeyEventKind::Expire, key);
+                }
+                Ok(None)
+            }
         }
-        Ok(())
     }
 
-    fn delete(&self, key: &str) -> Result<bool, BlinkError> {
-        if let Some((_, (value, _))) = self.store.remove(key) {
-            let freed = entry_size(key, &value);
-            self.current_usage.fetch_sub(freed, Ordering::Release);
-            Ok(true)
-        } else {
-            Ok(false)
+    /// The value and its version are unchanged, so this is not a write for
+    /// `WATCH` or keyspace events.
+    fn getex(&self, key: &str, expiry: Expiry) -> Result<Option<Bytes>, BlinkError> {
+        expiry.check()?;
+        let _gate = self.gate();
+        let now = Instant::now();
+        {
+            let Some(mut record) = self.store.get_mut(key) else {
+                return Ok(None);
+            };
+            if !record.is_expired(now) {
+                let Value::Str(value) = &record.value else {
+                    return Err(BlinkError::WrongType);
+                };
+                let value = value.clone();
+                self.log_undo(key, Some(&record));
+                match (record.expires_at.is_some(), expiry.hard.is_some()) {
+                    (false, true) => self.expiring.fetch_add(1, Ordering::Relaxed),
+                    (true, false) => self.expiring.fetch_sub(1, Ordering::Relaxed),
+                    _ => 0,
+                };
+                record.counter = self.next_counter();
+                record.stale_at = expiry.soft.map(|d| now + d);
+                record.expires_at = expiry.hard.map(|d| now + d);
+                return Ok(Some(value));
+            }
         }
+        if self.remove_entry_if(key, |r| r.is_expired(now)).is_some() {
+            self.emit(KeyEventKind::Expire, key);
+        }
+        Ok(None)
     }
 
     fn current_usage_bytes(&self) -> Result<u64, BlinkError> {
@@ -204,4 +2726,849 @@ mod tests {
         assert_eq!(&v[..], b"trait_value");
         assert!(store.delete("trait_key").unwrap());
     }
+
+    #[test]
+    fn getset_and_getdel_are_single_steps() {
+        let e = Arc::new(engine(1024));
+        assert_eq!(e.getset("k", Bytes::from_static(b"a")).unwrap(), None);
+        assert_eq!(e.getset("k", Bytes::from_static(b"b")).unwrap().as_deref(), Some(&b"a"[..]));
+        e.hset("h", "f", Bytes::new()).unwrap();
+        assert!(matches!(e.getset("h", Bytes::new()), Err(BlinkError::WrongType)));
+        assert!(matches!(e.getdel("h"), Err(BlinkError::WrongType)));
+        assert_eq!(e.hget("h", "f").unwrap().as_deref(), Some(&b""[..]));
+
+        // A one-time token is handed to exactly one of many claimants.
+        e.set("token", Bytes::from_static(b"secret")).unwrap();
+        let winners: usize = (0..8)
+            .map(|_| {
+                let e = e.clone();
+                std::thread::spawn(move || e.getdel("token").unwrap().is_some() as usize)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .sum();
+        assert_eq!(winners, 1);
+        assert_eq!(e.get("token").unwrap(), None);
+        assert_eq!(e.getdel("token").unwrap(), None);
+    }
+
+    #[test]
+    fn key_management_counts_and_renames() {
+        let e = engine(1024);
+        e.set("a", Bytes::from_static(b"1")).unwrap();
+        e.push("q", End::Left, Bytes::from_static(b"x")).unwrap();
+        e.sadd("s", &["m"]).unwrap();
+        assert_eq!(e.dbsize(), 3);
+        assert_eq!(e.exists(&["a", "q", "nope", "a"]), 3);
+        assert_eq!(e.touch(&["a", "nope"]), 1);
+        assert_eq!(e.key_type("q"), Some("list"));
+        assert_eq!(e.key_type("s"), Some("set"));
+        assert_eq!(e.key_type("nope"), None);
+
+        let usage = e.current_usage_bytes().unwrap();
+        assert!(e.rename("a", "longer", true).unwrap());
+        assert_eq!(e.get("a").unwrap(), None);
+        assert_eq!(e.get("longer").unwrap().as_deref(), Some(&b"1"[..]));
+        assert_eq!(e.current_usage_bytes().unwrap(), usage + 5);
+        assert!(!e.rename("longer", "q", false).unwrap());
+        assert!(e.rename("longer", "q", true).unwrap());
+        assert_eq!(e.key_type("q"), Some("string"));
+        assert_eq!(e.dbsize(), 2);
+        assert!(matches!(e.rename("longer", "x", true), Err(BlinkError::NotFound(_))));
+        assert!(e.rename("q", "q", true).unwrap());
+        assert!(!e.rename("q", "q", false).unwrap());
+
+        e.delete("q").unwrap();
+        e.srem("s", &["m"]).unwrap();
+        assert_eq!(e.dbsize(), 0);
+        assert_eq!(e.current_usage_bytes().unwrap(), 0);
+    }
+
+    #[test]
+    fn failed_rename_leaves_both_keys() {
+        let e = engine(10);
+        e.set_eviction_policy(EvictionPolicy::NoEviction);
+        e.set("k", Bytes::from_static(b"12345")).unwrap();
+        assert!(matches!(e.rename("k", "much-longer", true), Err(BlinkError::AtCapacity)));
+        assert_eq!(e.get("k").unwrap().as_deref(), Some(&b"12345"[..]));
+        assert_eq!(e.dbsize(), 1);
+        assert_eq!(e.current_usage_bytes().unwrap(), 6);
+    }
+
+    #[test]
+    fn touch_protects_keys_from_eviction() {
+        let e = engine(20);
+        e.set_eviction_samples(16).unwrap();
+        e.set("old", Bytes::from_static(b"1234")).unwrap();
+        e.set("new", Bytes::from_static(b"1234")).unwrap();
+        assert_eq!(e.touch(&["old"]), 1);
+        e.set("next", Bytes::from_static(b"1234")).unwrap();
+        assert_eq!(e.exists(&["old"]), 1);
+        assert_eq!(e.exists(&["new"]), 0);
+    }
+
+    #[test]
+    fn quota_evicts_noisy_tenant_first() {
+        let e = engine(100);
+        e.set_quota("noisy:", 20).unwrap();
+        e.set("quiet:1", Bytes::from_static(b"keep")).unwrap();
+        for i in 0..20 {
+            e.set(&format!("noisy:{}", i), Bytes::from_static(b"xxxxx")).unwrap(); // 13 bytes
+        }
+        assert_eq!(&e.get("quiet:1").unwrap().unwrap()[..], b"keep");
+        assert!(e.get("noisy:19").unwrap().is_some());
+        assert!(e.current_usage_bytes().unwrap() <= 100);
+    }
+
+    #[test]
+    fn quota_usage_tracks_group_bytes() {
+        let e = engine(1024);
+        e.set("t:a", Bytes::from_static(b"123")).unwrap(); // 6
+        e.set("t:b", Bytes::from_static(b"123")).unwrap(); // 6
+        e.set("other", Bytes::from_static(b"1")).unwrap();
+        e.set_quota("t:", 100).unwrap();
+        assert_eq!(
+            e.quota_usage(),
+            vec![QuotaUsage {
+                prefix: "t:".into(),
+                used_bytes: 12,
+                limit_bytes: 100
+            }]
+        );
+        e.delete("t:a").unwrap();
+        e.set("t:b", Bytes::from_static(b"1")).unwrap();
+        assert_eq!(e.quota_usage()[0].used_bytes, 4);
+        assert!(e.remove_quota("t:"));
+        assert!(e.quota_usage().is_empty());
+    }
+
+    #[test]
+    fn lowering_limit_evicts_immediately() {
+        let e = engine(100);
+        e.set("a", Bytes::from_static(b"123456789")).unwrap(); // 10
+        e.set("b", Bytes::from_static(b"123456789")).unwrap(); // 10
+        e.set_limit_bytes(15);
+        assert_eq!(e.limit_bytes(), 15);
+        assert!(e.current_usage_bytes().unwrap() <= 15);
+        assert!(e.get("a").unwrap().is_none());
+    }
+
+    #[test]
+    fn noeviction_rejects_writes_over_limit() {
+        let e = engine(10);
+        e.set_eviction_policy("noeviction".parse().unwrap());
+        e.set("a", Bytes::from_static(b"1234")).unwrap();
+        assert!(matches!(
+            e.set("b", Bytes::from_static(b"123456")),
+            Err(BlinkError::AtCapacity)
+        ));
+        assert!(e.get("a").unwrap().is_some());
+        assert!(e.set_eviction_samples(0).is_err());
+    }
+
+    #[tokio::test]
+    async fn get_or_load_fills_missing_key_once() {
+        let e = engine(1024);
+        let v = e
+            .get_or_load("k", || async { Ok(Bytes::from_static(b"db")) })
+            .await
+            .unwrap();
+        assert_eq!(&v[..], b"db");
+        let v = e.get_or_load("k", || async { unreachable!() }).await.unwrap();
+        assert_eq!(&v[..], b"db");
+    }
+
+    #[tokio::test]
+    async fn lease_holder_set_wakes_waiters() {
+        let e = Arc::new(engine(1024));
+        let ttl = Duration::from_secs(5);
+        assert!(matches!(e.get_or_lease("k", ttl).unwrap(), LeaseLookup::Granted));
+        let LeaseLookup::Held(wait) = e.get_or_lease("k", ttl).unwrap() else {
+            panic!("second caller should wait");
+        };
+        let waiter = tokio::spawn(wait.wait());
+        e.set("k", Bytes::from_static(b"v")).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(e.get_or_lease("k", ttl).unwrap(), LeaseLookup::Value(_)));
+
+        // Any write releases the lease, not only a plain `set`.
+        assert!(matches!(e.get_or_lease("log", ttl).unwrap(), LeaseLookup::Granted));
+        let LeaseLookup::Held(wait) = e.get_or_lease("log", ttl).unwrap() else {
+            panic!("second caller should wait");
+        };
+        e.append("log", b"filled").unwrap();
+        tokio::time::timeout(Duration::from_secs(1), wait.wait())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_lease_is_granted_again() {
+        let e = engine(1024);
+        let ttl = Duration::from_millis(100);
+        assert!(matches!(e.get_or_lease("k", ttl).unwrap(), LeaseLookup::Granted));
+        let LeaseLookup::Held(wait) = e.get_or_lease("k", ttl).unwrap() else {
+            panic!("second caller should wait");
+        };
+        wait.wait().await;
+        assert!(matches!(e.get_or_lease("k", ttl).unwrap(), LeaseLookup::Granted));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn getex_replaces_expiry_without_a_write() {
+        let e = engine(1024);
+        let hard = |secs| Expiry {
+            soft: None,
+            hard: Some(Duration::from_secs(secs)),
+        };
+        e.set_with_expiry("k", Bytes::from_static(b"v"), hard(1)).unwrap();
+        let version = e.version("k");
+        assert_eq!(e.getex("k", hard(10)).unwrap().as_deref(), Some(&b"v"[..]));
+        assert_eq!(e.version("k"), version);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(e.getex("k", Expiry::default()).unwrap().as_deref(), Some(&b"v"[..]));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(e.purge_expired(), 0);
+        assert!(e.get("k").unwrap().is_some());
+
+        e.getex("k", hard(1)).unwrap();
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(e.getex("k", hard(1)).unwrap(), None);
+        assert_eq!(e.current_usage_bytes().unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_allows_bursts_then_paces() {
+        let e = engine(1024);
+        let window = Duration::from_secs(1);
+        for remaining in (0..5).rev() {
+            let status = e.rate_limit("api:7", 5, window, 1).unwrap();
+            assert!(status.allowed);
+            assert_eq!(status.remaining, remaining);
+        }
+        let denied = e.rate_limit("api:7", 5, window, 1).unwrap();
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, Duration::from_millis(200));
+        assert_eq!(denied.reset_after, window);
+        // key (5) + 8 bytes of state
+        assert_eq!(e.current_usage_bytes().unwrap(), 13);
+        assert_eq!(e.key_type("api:7"), Some("ratelimit"));
+        assert!(matches!(e.get("api:7"), Err(BlinkError::WrongType)));
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+        assert!(e.rate_limit("api:7", 5, window, 1).unwrap().allowed);
+        assert!(!e.rate_limit("api:7", 5, window, 1).unwrap().allowed);
+        assert_eq!(e.rate_limit("api:7", 5, window, 0).unwrap().remaining, 0);
+        tokio::time::advance(Duration::from_millis(500)).await;
+        let status = e.rate_limit("api:7", 5, window, 2).unwrap();
+        assert!(status.allowed);
+        assert_eq!(status.remaining, 0);
+        assert!(!e.rate_limit("api:7", 5, window, 1).unwrap().allowed);
+
+        // A full bucket is gone: the entry expired and its memory is freed.
+        tokio::time::advance(window).await;
+        assert_eq!(e.purge_expired(), 1);
+        assert_eq!(e.current_usage_bytes().unwrap(), 0);
+        assert!(e.rate_limit("api:7", 5, window, 6).is_err());
+        assert!(e.rate_limit("api:7", 0, window, 0).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn locks_hand_out_increasing_fencing_tokens() {
+        let e = engine(1024);
+        let ttl = Duration::from_secs(10);
+        let first = e.lock("cron", Some("worker-a"), ttl).unwrap().unwrap();
+        assert_eq!(e.lock("cron", Some("worker-b"), ttl).unwrap(), None);
+        // A retry by the holder is harmless.
+        assert_eq!(e.lock("cron", Some("worker-a"), ttl).unwrap(), Some(first));
+        assert_eq!(e.key_type("cron"), Some("lock"));
+        // key (4) + owner (8) + token (8)
+        assert_eq!(e.current_usage_bytes().unwrap(), 20);
+
+        tokio::time::advance(Duration::from_secs(8)).await;
+        assert_eq!(e.extend_lock("cron", "worker-b", ttl).unwrap(), None);
+        assert_eq!(e.extend_lock("cron", "worker-a", ttl).unwrap(), Some(first));
+        tokio::time::advance(Duration::from_secs(8)).await;
+        assert!(!e.unlock("cron", "worker-b").unwrap());
+        assert!(e.unlock("cron", "worker-a").unwrap());
+        assert!(!e.unlock("cron", "worker-a").unwrap());
+
+        let second = e.lock("cron", None, ttl).unwrap().unwrap();
+        assert!(second > first);
+        // Expiry releases a lock whose holder went away.
+        tokio::time::advance(ttl).await;
+        assert_eq!(e.extend_lock("cron", &second.to_string(), ttl).unwrap(), None);
+        let third = e.lock("cron", Some("worker-b"), ttl).unwrap().unwrap();
+        assert!(third > second);
+
+        e.set("plain", Bytes::new()).unwrap();
+        assert!(matches!(e.lock("plain", None, ttl), Err(BlinkError::WrongType)));
+        assert!(matches!(e.unlock("plain", "x"), Err(BlinkError::WrongType)));
+        assert!(e.lock("cron", None, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn only_one_concurrent_locker_wins() {
+        let e = Arc::new(engine(1024));
+        let tokens: Vec<Option<u64>> = (0..8)
+            .map(|i| {
+                let e = e.clone();
+                std::thread::spawn(move || {
+                    e.lock("job", Some(&format!("w{}", i)), Duration::from_secs(5))
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect();
+        assert_eq!(tokens.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn held_locks_and_limiters_are_never_evicted() {
+        let e = engine(300);
+        e.set_eviction_samples(1000).unwrap();
+        let ttl = Duration::from_secs(60);
+        let token = e.lock("job", Some("worker1"), ttl).unwrap();
+        assert!(token.is_some());
+        assert!(e.rate_limit("rl", 1, ttl, 1).unwrap().allowed);
+        for i in 0..20 {
+            e.set(&format!("k{}", i), Bytes::from_static(b"0123456789")).unwrap();
+        }
+        assert_eq!(e.lock("job", Some("worker2"), ttl).unwrap(), None);
+        assert_eq!(e.extend_lock("job", "worker1", ttl).unwrap(), token);
+        assert!(!e.rate_limit("rl", 1, ttl, 1).unwrap().allowed);
+
+        // With only locks left, a write that does not fit is refused.
+        let e = engine(40);
+        e.lock("a", Some("owner-a"), ttl).unwrap();
+        e.lock("b", Some("owner-b"), ttl).unwrap();
+        assert!(matches!(
+            e.set("k", Bytes::from_static(b"0123456789")),
+            Err(BlinkError::AtCapacity)
+        ));
+        assert!(e.current_usage_bytes().unwrap() <= 40);
+    }
+
+    #[test]
+    fn hyperloglogs_take_fixed_space_and_merge() {
+        let e = engine(64 * 1024);
+        let users: Vec<String> = (0..2000).map(|i| format!("user{}", i)).collect();
+        let odd: Vec<&[u8]> = users.iter().skip(1).step_by(2).map(|u| u.as_bytes()).collect();
+        let even: Vec<&[u8]> = users.iter().step_by(2).map(|u| u.as_bytes()).collect();
+
+        assert!(e.pfadd("odd", &odd).unwrap());
+        // A PFADD that changes nothing is not a write.
+        let version = e.version("odd");
+        assert!(!e.pfadd("odd", &odd[..10]).unwrap());
+        assert_eq!(e.version("odd"), version);
+        assert_eq!(e.key_type("odd"), Some("hll"));
+        assert_eq!(e.current_usage_bytes().unwrap(), 3 + HyperLogLog::SIZE as u64);
+        e.pfadd("even", &even).unwrap();
+        let near = |count: u64, expected: u64| count.abs_diff(expected) * 50 < expected;
+        assert!(near(e.pfcount(&["odd"]).unwrap(), 1000));
+        assert!(near(e.pfcount(&["odd", "even", "missing"]).unwrap(), 2000));
+
+        e.pfmerge("all", &["odd", "even"]).unwrap();
+        assert!(near(e.pfcount(&["all"]).unwrap(), 2000));
+        assert_eq!(e.pfcount(&["missing"]).unwrap(), 0);
+        assert_eq!(e.current_usage_bytes().unwrap(), 10 + 3 * HyperLogLog::SIZE as u64);
+
+        e.set("plain", Bytes::new()).unwrap();
+        assert!(matches!(e.pfadd("plain", &[b"x"]), Err(BlinkError::WrongType)));
+        assert!(matches!(e.pfcount(&["odd", "plain"]), Err(BlinkError::WrongType)));
+        // A failed merge leaves the destination alone.
+        assert!(matches!(e.pfmerge("fresh", &["plain"]), Err(BlinkError::WrongType)));
+        assert_eq!(e.exists(&["fresh"]), 0);
+    }
+
+    #[test]
+    fn bloom_filters_are_sized_when_created() {
+        let e = engine(64 * 1024);
+        assert!(!e.bf_exists("seen", b"a").unwrap());
+        assert!(e.bf_add("seen", b"a").unwrap());
+        let version = e.version("seen");
+        assert!(!e.bf_add("seen", b"a").unwrap());
+        assert_eq!(e.version("seen"), version);
+        assert!(e.bf_exists("seen", b"a").unwrap());
+        assert_eq!(e.key_type("seen"), Some("bloom"));
+        let default = BloomFilter::size_for(crate::sketch::DEFAULT_BLOOM_CAPACITY, 0.01).unwrap();
+        assert_eq!(e.current_usage_bytes().unwrap(), 4 + default);
+
+        assert!(!e.bf_reserve("seen", 1000, 0.001).unwrap());
+        assert!(e.bf_reserve("big", 1000, 0.001).unwrap());
+        let big = BloomFilter::size_for(1000, 0.001).unwrap();
+        assert_eq!(e.current_usage_bytes().unwrap(), 4 + default + 3 + big);
+        for i in 0..1000u32 {
+            e.bf_add("big", &i.to_le_bytes()).unwrap();
+        }
+        assert!((0..1000u32).all(|i| e.bf_exists("big", &i.to_le_bytes()).unwrap()));
+        assert_eq!(e.current_usage_bytes().unwrap(), 4 + default + 3 + big);
+
+        assert!(matches!(e.bf_reserve("huge", 1 << 40, 0.01), Err(BlinkError::AtCapacity)));
+        assert!(e.bf_reserve("bad", 10, 0.0).is_err());
+        e.set("plain", Bytes::new()).unwrap();
+        assert!(matches!(e.bf_exists("plain", b"a"), Err(BlinkError::WrongType)));
+    }
+
+    fn event(n: u64) -> Vec<(String, Bytes)> {
+        vec![("n".to_owned(), Bytes::from(n.to_string()))]
+    }
+
+    #[test]
+    fn streams_trim_by_length_and_account_entries() {
+        let e = engine(1024);
+        let id = |ms| StreamId { ms, seq: 0 };
+        for n in 1..=5 {
+            e.xadd("log", Some(id(n)), event(n), None).unwrap();
+        }
+        assert_eq!(e.key_type("log"), Some("stream"));
+        // key (3) + 5 * (ID (16) + "n" + one digit)
+        assert_eq!(e.current_usage_bytes().unwrap(), 3 + 5 * 18);
+        assert!(matches!(
+            e.xadd("log", Some(id(5)), event(5), None),
+            Err(BlinkError::StreamIdTooSmall(_))
+        ));
+        let auto = e.xadd("log", None, event(6), Some(3)).unwrap();
+        assert!(auto > id(5));
+        assert_eq!(e.xlen("log").unwrap(), 3);
+        assert_eq!(e.current_usage_bytes().unwrap(), 3 + 3 * 18);
+
+        let all = e.xrange("log", StreamId::MIN, StreamId::MAX, usize::MAX).unwrap();
+        assert_eq!(all.iter().map(|entry| entry.id).collect::<Vec<_>>(), [id(4), id(5), auto]);
+        assert_eq!(&all[0].fields[0].1[..], b"4");
+        assert_eq!(e.xrange("log", id(5), id(5), 10).unwrap().len(), 1);
+        assert!(e.xrange("log", id(5), id(4), 10).unwrap().is_empty());
+        assert_eq!(e.xread("log", id(4), 1).unwrap()[0].id, id(5));
+
+        assert_eq!(e.xtrim("log", 1).unwrap(), 2);
+        assert_eq!(e.xtrim("log", 1).unwrap(), 0);
+        assert_eq!(e.current_usage_bytes().unwrap(), 3 + 18);
+        // An empty stream keeps its key, and IDs keep increasing.
+        assert_eq!(e.xtrim("log", 0).unwrap(), 1);
+        assert_eq!(e.xlen("log").unwrap(), 0);
+        assert!(e.xadd("log", Some(id(6)), event(6), None).is_err());
+        assert_eq!(e.xlen("missing").unwrap(), 0);
+    }
+
+    #[test]
+    fn consumer_groups_hand_out_each_entry_once() {
+        let e = engine(4096);
+        let id = |ms| StreamId { ms, seq: 0 };
+        assert!(matches!(
+            e.xreadgroup("jobs", "workers", "w1", None, 10),
+            Err(BlinkError::NoGroup(_))
+        ));
+        e.xadd("jobs", Some(id(1)), event(1), None).unwrap();
+        assert!(e.xgroup_create("jobs", "workers", Some(StreamId::MIN)).unwrap());
+        let version = e.version("jobs");
+        assert!(!e.xgroup_create("jobs", "workers", None).unwrap());
+        assert_eq!(e.version("jobs"), version);
+        for n in 2..=4 {
+            e.xadd("jobs", Some(id(n)), event(n), None).unwrap();
+        }
+        let before = e.current_usage_bytes().unwrap();
+        let version = e.version("jobs");
+        let mut events = e.events.subscribe();
+
+        let w1 = e.xreadgroup("jobs", "workers", "w1", None, 3).unwrap();
+        let w2 = e.xreadgroup("jobs", "workers", "w2", None, 3).unwrap();
+        assert_eq!(w1.iter().map(|entry| entry.id).collect::<Vec<_>>(), [id(1), id(2), id(3)]);
+        assert_eq!(w2.iter().map(|entry| entry.id).collect::<Vec<_>>(), [id(4)]);
+        assert!(e.xreadgroup("jobs", "workers", "w2", None, 3).unwrap().is_empty());
+        // Handing out entries is a read: no new version and no event.
+        assert_eq!(e.version("jobs"), version);
+        assert!(events.try_recv().is_err());
+        // Four pending entries of ID (16) + consumer name (2).
+        assert_eq!(e.current_usage_bytes().unwrap(), before + 4 * 18);
+
+        // After a crash, w1 gets its own unacknowledged entries back.
+        assert_eq!(e.xack("jobs", "workers", &[id(1), id(4)]).unwrap(), 2);
+        let again = e.xreadgroup("jobs", "workers", "w1", Some(StreamId::MIN), 10).unwrap();
+        assert_eq!(again.iter().map(|entry| entry.id).collect::<Vec<_>>(), [id(2), id(3)]);
+        let pending = e.xpending("jobs", "workers").unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!((pending[0].consumer.as_str(), pending[0].deliveries), ("w1", 2));
+        assert_eq!(e.current_usage_bytes().unwrap(), before + 2 * 18);
+
+        assert!(e.xgroup_destroy("jobs", "workers").unwrap());
+        assert!(!e.xgroup_destroy("jobs", "workers").unwrap());
+        assert!(matches!(e.xpending("jobs", "workers"), Err(BlinkError::NoGroup(_))));
+        // The group (name + ID) and its pending entries are gone.
+        assert_eq!(e.current_usage_bytes().unwrap(), before - 7 - 16);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn blocking_stream_reads_wait_for_new_entries() {
+        let e = Arc::new(engine(4096));
+        e.xadd("feed", None, event(1), None).unwrap();
+        e.xgroup_create("feed", "g", None).unwrap();
+        let reader = {
+            let e = e.clone();
+            tokio::spawn(async move { e.xread_blocking("feed", None, 10, None).await })
+        };
+        let member = {
+            let e = e.clone();
+            tokio::spawn(async move {
+                e.xreadgroup_blocking("feed", "g", "c", 10, Some(Duration::from_secs(5)))
+                    .await
+            })
+        };
+        tokio::task::yield_now().await;
+        let added = e.xadd("feed", None, event(2), None).unwrap();
+        assert_eq!(reader.await.unwrap().unwrap()[0].id, added);
+        assert_eq!(member.await.unwrap().unwrap()[0].id, added);
+
+        let timed_out = e
+            .xreadgroup_blocking("feed", "g", "c", 10, Some(Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert!(timed_out.is_empty());
+        assert_eq!(e.watched_keys(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn soft_expiry_marks_stale_and_hard_expiry_removes() {
+        let e = engine(1024);
+        let expiry = Expiry {
+            soft: Some(Duration::from_secs(1)),
+            hard: Some(Duration::from_secs(2)),
+        };
+        e.set_with_expiry("k", Bytes::from_static(b"v"), expiry).unwrap();
+        e.set_with_expiry("idle", Bytes::from_static(b"v"), expiry).unwrap();
+        assert_eq!(e.get_with_freshness("k").unwrap().unwrap().1, Freshness::Fresh);
+
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert_eq!(e.get_with_freshness("k").unwrap().unwrap().1, Freshness::Stale);
+        assert!(e.get("k").unwrap().is_some());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(e.get("k").unwrap().is_none());
+        assert_eq!(e.purge_expired(), 1);
+        assert_eq!(e.current_usage_bytes().unwrap(), 0);
+
+        let backwards = Expiry {
+            soft: Some(Duration::from_secs(2)),
+            hard: Some(Duration::from_secs(1)),
+        };
+        assert!(e.set_with_expiry("k", Bytes::new(), backwards).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stale_value_served_while_lease_held() {
+        let e = engine(1024);
+        let expiry = Expiry {
+            soft: Some(Duration::from_secs(1)),
+            hard: None,
+        };
+        e.set_with_expiry("k", Bytes::from_static(b"old"), expiry).unwrap();
+        tokio::time::advance(Duration::from_secs(2)).await;
+
+        let ttl = Duration::from_secs(5);
+        assert!(matches!(e.get_or_lease("k", ttl).unwrap(), LeaseLookup::Granted));
+        assert!(matches!(e.get_or_lease("k", ttl).unwrap(), LeaseLookup::Stale(v) if &v[..] == b"old"));
+        e.set("k", Bytes::from_static(b"new")).unwrap();
+        assert!(matches!(e.get_or_lease("k", ttl).unwrap(), LeaseLookup::Value(v) if &v[..] == b"new"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn subscribe_reports_every_removal_cause() {
+        use tokio_stream::StreamExt;
+
+        let e = engine(10);
+        let mut events = e.subscribe();
+        e.set("a", Bytes::from_static(b"1234")).unwrap();
+        e.delete("a").unwrap();
+        e.set("b", Bytes::from_static(b"1234")).unwrap();
+        e.set("c", Bytes::from_static(b"123456")).unwrap(); // evicts b
+        let expiry = Expiry {
+            soft: None,
+            hard: Some(Duration::from_secs(1)),
+        };
+        e.set_with_expiry("c", Bytes::new(), expiry).unwrap();
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(e.get("c").unwrap().is_none());
+
+        let mut seen = Vec::new();
+        for _ in 0..7 {
+            let event = events.next().await.unwrap().unwrap();
+            seen.push(format!("{} {}", event.kind.as_str(), event.key));
+        }
+        assert_eq!(
+            seen,
+            ["set a", "delete a", "set b", "evict b", "set c", "set c", "expire c"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_change_wakes_on_write_and_cleans_up() {
+        let e = Arc::new(engine(1024));
+        assert_eq!(e.get_versioned("k").unwrap().version, 0);
+        e.set("k", Bytes::from_static(b"v1")).unwrap();
+        let v1 = e.get_versioned("k").unwrap().version;
+        assert_ne!(v1, 0);
+
+        // A stale version returns at once.
+        let now = e.wait_for_change("k", 0, Duration::from_secs(10)).await.unwrap();
+        assert_eq!((now.version, now.value.as_deref()), (v1, Some(&b"v1"[..])));
+
+        // A timeout returns the unchanged state.
+        let unchanged = e.wait_for_change("k", v1, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(unchanged.version, v1);
+
+        let waiter = {
+            let e = e.clone();
+            tokio::spawn(async move { e.wait_for_change("k", v1, Duration::from_secs(10)).await.unwrap() })
+        };
+        tokio::task::yield_now().await;
+        assert_eq!(e.watched_keys(), 1);
+        e.delete("k").unwrap();
+        assert_eq!(waiter.await.unwrap(), Versioned { version: 0, value: None });
+
+        // Abandoned waits remove their registration.
+        let abandoned = {
+            let e = e.clone();
+            tokio::spawn(async move { e.wait_for_change("k", 0, Duration::from_secs(10)).await.unwrap() })
+        };
+        tokio::task::yield_now().await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        assert_eq!(e.watched_keys(), 0);
+    }
+
+    #[tokio::test]
+    async fn transaction_commits_or_rolls_back_everything() {
+        use tokio_stream::StreamExt;
+
+        let e = engine(1024);
+        let mut events = e.subscribe();
+        e.set("a", Bytes::from_static(b"1")).unwrap();
+        e.set("b", Bytes::from_static(b"2")).unwrap();
+        let usage = e.current_usage_bytes().unwrap();
+
+        let failed: Option<Result<(), BlinkError>> = e.transaction(&[], || {
+            e.set("a", Bytes::from_static(b"changed"))?;
+            e.delete("b")?;
+            e.set("c", Bytes::from_static(b"3"))?;
+            Err(BlinkError::AtCapacity)
+        });
+        assert!(matches!(failed, Some(Err(BlinkError::AtCapacity))));
+        assert_eq!(e.get("a").unwrap().as_deref(), Some(&b"1"[..]));
+        assert_eq!(e.get("b").unwrap().as_deref(), Some(&b"2"[..]));
+        assert_eq!(e.get("c").unwrap(), None);
+        assert_eq!(e.current_usage_bytes().unwrap(), usage);
+
+        let watched = vec![("a".to_owned(), e.version("a"))];
+        let committed = e.transaction(&watched, || e.set("a", Bytes::from_static(b"x")));
+        assert!(matches!(committed, Some(Ok(()))));
+        // The version changed, so the same watch now conflicts.
+        assert!(e.transaction(&watched, || Ok::<_, BlinkError>(())).is_none());
+
+        // Only the committed write was announced.
+        e.set("end", Bytes::new()).unwrap();
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            seen.push(events.next().await.unwrap().unwrap().key);
+        }
+        assert_eq!(seen, ["a", "b", "a", "end"]);
+
+        // So does a missing key that was set and removed again.
+        let watched = vec![("gone".to_owned(), e.watch_version("gone"))];
+        e.set("gone", Bytes::new()).unwrap();
+        e.delete("gone").unwrap();
+        assert_eq!(e.version("gone"), 0);
+        assert!(e.transaction(&watched, || Ok::<_, BlinkError>(())).is_none());
+    }
+
+    #[test]
+    fn append_and_ranges_edit_strings_in_place() {
+        let e = engine(64);
+        assert_eq!(e.append("log", b"boot").unwrap(), 4);
+        let before = e.getrange("log", 0, -1).unwrap().as_ptr();
+        assert_eq!(e.append("log", b" ok").unwrap(), 7);
+        assert_eq!(e.get("log").unwrap().as_deref(), Some(&b"boot ok"[..]));
+        assert_eq!(e.current_usage_bytes().unwrap(), 10);
+        // Unshared buffers are reused rather than copied.
+        assert_eq!(e.getrange("log", 0, -1).unwrap().as_ptr(), before);
+
+        assert_eq!(e.setrange("log", 5, b"OK!").unwrap(), 8);
+        assert_eq!(e.getrange("log", -3, -1).unwrap(), "OK!");
+        assert_eq!(e.setrange("pad", 2, b"x").unwrap(), 3);
+        assert_eq!(e.get("pad").unwrap().as_deref(), Some(&b"\0\0x"[..]));
+        assert_eq!(e.setrange("none", 4, b"").unwrap(), 0);
+        assert_eq!(e.get("none").unwrap(), None);
+        assert_eq!(e.strlen("log").unwrap(), 8);
+        assert_eq!(e.strlen("none").unwrap(), 0);
+        assert!(e.getrange("none", 0, -1).unwrap().is_empty());
+        assert_eq!(e.current_usage_bytes().unwrap(), 11 + 6);
+
+        assert!(matches!(e.setrange("log", 100, b"x"), Err(BlinkError::AtCapacity)));
+        assert!(matches!(e.setrange("log", usize::MAX, b"x"), Err(BlinkError::AtCapacity)));
+        e.hset("h", "f", Bytes::new()).unwrap();
+        assert!(matches!(e.append("h", b"x"), Err(BlinkError::WrongType)));
+        assert!(matches!(e.strlen("h"), Err(BlinkError::WrongType)));
+    }
+
+    #[test]
+    fn appending_keeps_room_by_evicting_others() {
+        let e = engine(20);
+        e.set("a", Bytes::from_static(b"123456789")).unwrap();
+        e.append("log", b"12345").unwrap();
+        e.append("log", b"12345").unwrap();
+        assert_eq!(e.get("a").unwrap(), None);
+        assert_eq!(e.current_usage_bytes().unwrap(), 13);
+    }
+
+    #[test]
+    fn hash_fields_are_accounted_and_typed() {
+        let e = engine(1024);
+        assert!(e.hset("user:1", "name", Bytes::from_static(b"alice")).unwrap());
+        assert!(e.hset("user:1", "city", Bytes::from_static(b"oslo")).unwrap());
+        // key (6) + name/alice (9) + city/oslo (8)
+        assert_eq!(e.current_usage_bytes().unwrap(), 23);
+        assert!(!e.hset("user:1", "name", Bytes::from_static(b"al")).unwrap());
+        assert_eq!(e.current_usage_bytes().unwrap(), 20);
+        assert_eq!(e.hget("user:1", "name").unwrap().as_deref(), Some(&b"al"[..]));
+        assert_eq!(e.hget("user:1", "zip").unwrap(), None);
+        assert_eq!(
+            e.hgetall("user:1").unwrap(),
+            vec![
+                ("city".to_owned(), Bytes::from_static(b"oslo")),
+                ("name".to_owned(), Bytes::from_static(b"al")),
+            ]
+        );
+
+        e.set("plain", Bytes::from_static(b"v")).unwrap();
+        assert!(matches!(e.get("user:1"), Err(BlinkError::WrongType)));
+        assert!(matches!(e.hget("plain", "f"), Err(BlinkError::WrongType)));
+        assert!(matches!(e.hset("plain", "f", Bytes::new()), Err(BlinkError::WrongType)));
+
+        assert_eq!(e.hdel("user:1", &["name", "zip"]).unwrap(), 1);
+        assert_eq!(e.hdel("user:1", &["city"]).unwrap(), 1);
+        assert!(e.hgetall("user:1").unwrap().is_empty());
+        assert_eq!(e.current_usage_bytes().unwrap(), 6);
+    }
+
+    #[test]
+    fn list_push_pop_range_and_accounting() {
+        let e = engine(1024);
+        assert_eq!(e.push("q", End::Right, Bytes::from_static(b"b")).unwrap(), 1);
+        assert_eq!(e.push("q", End::Right, Bytes::from_static(b"cc")).unwrap(), 2);
+        assert_eq!(e.push("q", End::Left, Bytes::from_static(b"a")).unwrap(), 3);
+        assert_eq!(e.current_usage_bytes().unwrap(), 5);
+        assert_eq!(e.lrange("q", 0, -1).unwrap(), ["a", "b", "cc"]);
+        assert_eq!(e.lrange("q", -2, 10).unwrap(), ["b", "cc"]);
+        assert!(e.lrange("q", 2, 1).unwrap().is_empty());
+        assert!(e.lrange("missing", 0, -1).unwrap().is_empty());
+
+        assert_eq!(e.pop("q", End::Right).unwrap().as_deref(), Some(&b"cc"[..]));
+        assert_eq!(e.pop("q", End::Left).unwrap().as_deref(), Some(&b"a"[..]));
+        assert!(matches!(e.hget("q", "f"), Err(BlinkError::WrongType)));
+        assert_eq!(e.pop("q", End::Left).unwrap().as_deref(), Some(&b"b"[..]));
+        assert_eq!(e.pop("q", End::Left).unwrap(), None);
+        assert_eq!(e.current_usage_bytes().unwrap(), 0);
+    }
+
+    #[test]
+    fn set_members_are_accounted_and_typed() {
+        let e = engine(1024);
+        assert_eq!(e.sadd("tags", &["rust", "db", "rust"]).unwrap(), 2);
+        assert_eq!(e.sadd("tags", &["db", "cache"]).unwrap(), 1);
+        // key (4) + rust (4) + db (2) + cache (5)
+        assert_eq!(e.current_usage_bytes().unwrap(), 15);
+        assert_eq!(e.smembers("tags").unwrap(), ["cache", "db", "rust"]);
+        assert!(e.sismember("tags", "db").unwrap());
+        assert!(!e.sismember("missing", "db").unwrap());
+        assert!(matches!(e.push("tags", End::Left, Bytes::new()), Err(BlinkError::WrongType)));
+
+        assert_eq!(e.srem("tags", &["db", "nope"]).unwrap(), 1);
+        assert_eq!(e.srem("tags", &["rust", "cache"]).unwrap(), 2);
+        assert!(e.smembers("tags").unwrap().is_empty());
+        assert_eq!(e.current_usage_bytes().unwrap(), 0);
+    }
+
+    #[test]
+    fn cached_entry_sizes_follow_every_change() {
+        let e = engine(64 * 1024);
+        for i in 0..200 {
+            e.push("q", End::Right, Bytes::from(format!("item-{}", i))).unwrap();
+        }
+        e.pop("q", End::Left).unwrap();
+        e.hset("h", "f", Bytes::from_static(b"one")).unwrap();
+        e.hset("h", "f", Bytes::from_static(b"three")).unwrap();
+        e.zadd("z", &[(1.0, "a"), (2.0, "bb")]).unwrap();
+        e.zrem("z", &["a"]).unwrap();
+        e.append("s", b"abc").unwrap();
+        e.setrange("s", 5, b"xyz").unwrap();
+        e.rename("s", "string", true).unwrap();
+        e.xadd("x", None, vec![("f".to_owned(), Bytes::from_static(b"v"))], None).unwrap();
+        e.xgroup_create("x", "g", Some(StreamId::MIN)).unwrap();
+        e.xreadgroup("x", "g", "c", None, 10).unwrap();
+        let mut total = 0;
+        for entry in e.store.iter() {
+            assert_eq!(entry.size, entry_size(entry.key(), &entry.value), "{}", entry.key());
+            total += entry.size;
+        }
+        assert_eq!(e.current_usage_bytes().unwrap(), total);
+    }
+
+    #[test]
+    fn sorted_set_orders_by_score_and_accounts_members() {
+        let e = engine(1024);
+        assert_eq!(e.zadd("board", &[(30.0, "carol"), (10.0, "alice"), (20.0, "bob")]).unwrap(), 3);
+        // key (5) + each member name plus 8 bytes of score
+        assert_eq!(e.current_usage_bytes().unwrap(), 5 + 13 + 13 + 11);
+        assert_eq!(e.zadd("board", &[(5.0, "carol")]).unwrap(), 0);
+        let names = |v: Vec<(String, f64)>| v.into_iter().map(|(m, _)| m).collect::<Vec<_>>();
+        assert_eq!(names(e.zrange("board", 0, -1).unwrap()), ["carol", "alice", "bob"]);
+        assert_eq!(e.zrange("board", -1, -1).unwrap(), [("bob".to_owned(), 20.0)]);
+
+        assert_eq!(e.zincrby("board", 15.0, "alice").unwrap(), 25.0);
+        assert_eq!(e.zincrby("board", 1.5, "dave").unwrap(), 1.5);
+        assert_eq!(
+            names(e.zrangebyscore("board", Bound::Excluded(1.5), Bound::Included(25.0)).unwrap()),
+            ["carol", "bob", "alice"]
+        );
+        assert_eq!(
+            names(e.zrangebyscore("board", Bound::Unbounded, Bound::Excluded(20.0)).unwrap()),
+            ["dave", "carol"]
+        );
+        e.zadd("board", &[(-0.0, "zero")]).unwrap();
+        assert_eq!(
+            e.zrangebyscore("board", Bound::Included(0.0), Bound::Included(0.0)).unwrap(),
+            [("zero".to_owned(), 0.0)]
+        );
+        e.zadd("board", &[(f64::INFINITY, "inf")]).unwrap();
+        assert!(matches!(
+            e.zincrby("board", f64::NEG_INFINITY, "inf"),
+            Err(BlinkError::NotANumber)
+        ));
+        assert!(matches!(e.zadd("board", &[(f64::NAN, "x")]), Err(BlinkError::NotANumber)));
+        assert!(matches!(e.sadd("board", &["x"]), Err(BlinkError::WrongType)));
+
+        assert_eq!(e.zrem("board", &["alice", "bob", "carol", "dave", "zero", "inf"]).unwrap(), 6);
+        assert!(e.zrange("board", 0, -1).unwrap().is_empty());
+        assert_eq!(e.current_usage_bytes().unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn blpop_waits_for_a_push() {
+        let e = Arc::new(engine(1024));
+        let popper = {
+            let e = e.clone();
+            tokio::spawn(async move { e.blpop("jobs", None).await })
+        };
+        tokio::task::yield_now().await;
+        e.push("jobs", End::Right, Bytes::from_static(b"job-1")).unwrap();
+        assert_eq!(popper.await.unwrap().unwrap().as_deref(), Some(&b"job-1"[..]));
+        assert_eq!(e.watched_keys(), 0);
+
+        let timed_out = e.blpop("jobs", Some(Duration::from_secs(1))).await.unwrap();
+        assert_eq!(timed_out, None);
+    }
 }
@@ -2,7 +2,7 @@
 
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum BlinkError {
     #[error("key not found: {0}")]
     NotFound(String),
@@ -10,6 +10,42 @@ pub enum BlinkError {
     #[error("storage is at capacity and eviction failed")]
     AtCapacity,
 
+    #[error("authentication required")]
+    NoAuth,
+
+    #[error("invalid username or password")]
+    AuthFailed,
+
+    #[error("permission denied: {0}")]
+    NoPerm(String),
+
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    #[error("server error: {0}")]
+    Server(String),
+
+    #[error("WRONGTYPE operation against a key holding the wrong kind of value")]
+    WrongType,
+
+    #[error("score is not a number")]
+    NotANumber,
+
+    #[error("stream ID {0} is not greater than the stream's last ID")]
+    StreamIdTooSmall(String),
+
+    #[error("NOGROUP no such consumer group: {0}")]
+    NoGroup(String),
+
+    #[error("codec error: {0}")]
+    Codec(String),
+
+    #[error("timed out: {0}")]
+    Timeout(String),
+
     #[error("internal error: {0}")]
     Internal(String),
 }
@@ -0,0 +1,65 @@
+//! Glob-style pattern matching for key and channel patterns.
+//!
+//! Supports `*` (any run of characters, including none) and `?` (exactly one
+//! character). Everything else matches literally.
+
+/// Returns true if `text` matches `pattern`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p = pattern.as_bytes();
+    let t = text.as_bytes();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last `*` in the pattern and the text index it was tried at.
+    let mut star: Option<(usize, usize)> = None;
+
+    // Width of the character starting at text index `i`, so `?` and `*`
+    // backtracking always step over whole UTF-8 characters.
+    let width = |i: usize| text[i..].chars().next().map_or(1, char::len_utf8);
+
+    while ti < t.len() {
+        if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if pi < p.len() && p[pi] == b'?' {
+            pi += 1;
+            ti += width(ti);
+        } else if pi < p.len() && p[pi] == t[ti] {
+            pi += 1;
+            ti += 1;
+        } else if let Some((star_pi, star_ti)) = star {
+            let next = star_ti + width(star_ti);
+            pi = star_pi + 1;
+            ti = next;
+            star = Some((star_pi, next));
+        } else {
+            return false;
+        }
+    }
+    while pi < p.len() && p[pi] == b'*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_and_wildcards() {
+        assert!(glob_match("a:*", "a:1"));
+        assert!(glob_match("a:*", "a:"));
+        assert!(!glob_match("a:*", "b:1"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("user:?", "user:7"));
+        assert!(!glob_match("user:?", "user:77"));
+        assert!(glob_match("*:cache:*", "svc:cache:item"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+        assert!(glob_match("*", "*x"));
+        assert!(glob_match("*b", "*ab"));
+        assert!(glob_match("a*", "a*"));
+        assert!(glob_match("user:?", "user:é"));
+        assert!(!glob_match("user:?", "user:éé"));
+        assert!(glob_match("*é", "aé"));
+    }
+}
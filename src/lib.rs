@@ -1,14 +1,31 @@
 //! Blink Store: in-memory key-value store with sampled eviction and memory-cap enforcement.
 
+pub mod acl;
+pub mod blocking;
+pub mod client;
+pub mod config;
 pub mod engine;
 pub mod error;
+pub mod glob;
 pub mod logging;
 pub mod protocol;
+pub mod pubsub;
 pub mod server;
+pub mod singleflight;
+pub mod sketch;
+pub mod stream;
+pub mod typed;
 
-pub use engine::{BlinkStorage, MemoryEngine};
+pub use acl::Acl;
+pub use client::{BlinkClient, ClientConfig, Subscriber};
+pub use engine::{
+    BlinkStorage, End, EvictionPolicy, Expiry, Freshness, KeyEvent, KeyEventKind, MemoryEngine,
+    QuotaUsage, RateLimitStatus, Versioned,
+};
 pub use error::BlinkError;
-pub use protocol::{parse_request, Command, Response};
-pub use server::run_tcp;
+pub use protocol::{encode_request, parse_request, parse_response, Command, Response};
+pub use server::{run_tcp, serve_tcp, Database, ServerState};
+pub use stream::{PendingEntry, StreamEntry, StreamId};
+pub use typed::TypedCache;
 #[cfg(unix)]
 pub use server::run_unix;
@@ -2,17 +2,49 @@
 
 use anyhow::Result;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tracing::info;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
 
 /// Holds guards so that logs are flushed on drop.
 pub struct LoggingGuard {
     _file_guard: Option<WorkerGuard>,
+    filter: LogFilterHandle,
+}
+
+impl LoggingGuard {
+    /// Handle for changing the log filter at runtime.
+    pub fn filter_handle(&self) -> LogFilterHandle {
+        self.filter.clone()
+    }
+}
+
+/// Swaps the active `EnvFilter` without restarting (e.g. `CONFIG SET log_level debug`).
+#[derive(Clone)]
+pub struct LogFilterHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    current: Arc<Mutex<String>>,
+}
+
+impl LogFilterHandle {
+    /// The filter directives currently in effect.
+    pub fn current(&self) -> String {
+        self.current.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replaces the filter with `directives` (e.g. `debug` or `blink_store=trace,info`).
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.handle.reload(filter)?;
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = directives.to_owned();
+        info!(filter = %directives, action = "log_filter_changed");
+        Ok(())
+    }
 }
 
 /// Initializes tracing: stdout + optional rolling file in `log_dir`.
@@ -21,6 +53,8 @@ pub fn init_tracing(log_dir: Option<&Path>, env_filter: Option<&str>) -> Result<
     let filter = env_filter
         .map(EnvFilter::new)
         .unwrap_or_else(|| EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()));
+    let directives = filter.to_string();
+    let (filter, reload_handle) = reload::Layer::new(filter);
 
     let (file_guard, file_layer) = if let Some(dir) = log_dir {
         std::fs::create_dir_all(dir)?;
@@ -52,6 +86,10 @@ pub fn init_tracing(log_dir: Option<&Path>, env_filter: Option<&str>) -> Result<
 
     Ok(LoggingGuard {
         _file_guard: file_guard,
+        filter: LogFilterHandle {
+            handle: reload_handle,
+            current: Arc::new(Mutex::new(directives)),
+        },
     })
 }
 
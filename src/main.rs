@@ -1,10 +1,11 @@
 //! CLI for Blink Store.
 
 use anyhow::{Context, Result};
-use blink_store::MemoryEngine;
+use blink_store::config::{ConfigLayer, Settings};
+use blink_store::{Acl, MemoryEngine, ServerState};
 use bytes::Bytes;
 use clap::Parser;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::info;
@@ -17,45 +18,134 @@ struct Cli {
     command: Command,
 }
 
+/// Options shared by `run` and `serve`. Unset options fall back to
+/// `BLINK_*` environment variables, then the config file, then defaults.
+#[derive(clap::Args)]
+struct CommonArgs {
+    /// TOML config file accepting every option below.
+    #[arg(long, env = "BLINK_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Memory limit in bytes [default: 10485760].
+    #[arg(long, env = "BLINK_MEMORY_LIMIT")]
+    memory_limit: Option<u64>,
+
+    /// Prune log files older than this many minutes (0 = disable) [default: 60].
+    #[arg(long, env = "BLINK_RETENTION_MINUTES")]
+    retention_minutes: Option<u64>,
+
+    /// Directory for log files (if unset, logs only to stdout).
+    #[arg(long, env = "BLINK_LOG_DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// Tracing filter, e.g. `debug` [default: blink_store=info,info].
+    #[arg(long, env = "BLINK_LOG_LEVEL")]
+    log_level: Option<String>,
+}
+
+impl CommonArgs {
+    fn layer(&self) -> ConfigLayer {
+        ConfigLayer {
+            memory_limit: self.memory_limit,
+            retention_minutes: self.retention_minutes,
+            log_dir: self.log_dir.clone(),
+            log_level: self.log_level.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Merges these options over the config file (if any) into effective settings.
+    fn settings(&self, layer: ConfigLayer) -> Result<Settings> {
+        let file = match &self.config {
+            Some(path) => ConfigLayer::load(path)?,
+            None => ConfigLayer::default(),
+        };
+        Ok(Settings::resolve(layer.or(file))?)
+    }
+}
+
+#[derive(clap::Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// TCP listen address (e.g. 0.0.0.0:8765).
+    #[arg(long, env = "BLINK_TCP")]
+    tcp: Option<String>,
+
+    /// Unix socket path (e.g. /tmp/blink-store.sock). Unix only.
+    #[arg(long, env = "BLINK_UNIX")]
+    unix: Option<PathBuf>,
+
+    /// allkeys-lru or noeviction [default: allkeys-lru].
+    #[arg(long, env = "BLINK_EVICTION_POLICY")]
+    eviction_policy: Option<String>,
+
+    /// Entries sampled per eviction round [default: 5].
+    #[arg(long, env = "BLINK_EVICTION_SAMPLES")]
+    eviction_samples: Option<usize>,
+
+    /// Close connections idle for this many seconds (0 = never) [default: 0].
+    #[arg(long, env = "BLINK_IDLE_TIMEOUT_SECS")]
+    idle_timeout_secs: Option<u64>,
+
+    /// How long a GETLOCK lease lasts unless the request gives one [default: 5000].
+    #[arg(long, env = "BLINK_LEASE_TIMEOUT_MS")]
+    lease_timeout_ms: Option<u64>,
+
+    /// Number of numbered databases (0..N-1), each with --memory-limit [default: 1].
+    #[arg(long, env = "BLINK_DATABASES")]
+    databases: Option<usize>,
+
+    /// Extra named database as NAME or NAME=BYTES (repeatable).
+    #[arg(long = "db", value_name = "NAME[=BYTES]", env = "BLINK_DB", value_delimiter = ',')]
+    named_dbs: Vec<String>,
+
+    /// Soft byte budget for keys starting with PREFIX, in every database (repeatable).
+    #[arg(long = "quota", value_name = "PREFIX=BYTES", env = "BLINK_QUOTA", value_delimiter = ',')]
+    quotas: Vec<String>,
+
+    /// ACL file with per-user command and key permissions (reloaded on SIGHUP).
+    #[arg(long, env = "BLINK_ACL_FILE")]
+    acl_file: Option<PathBuf>,
+}
+
+impl ServeArgs {
+    fn settings(&self) -> Result<Settings> {
+        let layer = ConfigLayer {
+            tcp: self.tcp.clone(),
+            unix: self.unix.clone(),
+            eviction_policy: self.eviction_policy.clone(),
+            eviction_samples: self.eviction_samples,
+            idle_timeout_secs: self.idle_timeout_secs,
+            lease_timeout_ms: self.lease_timeout_ms,
+            databases: self.databases,
+            db: (!self.named_dbs.is_empty()).then(|| self.named_dbs.clone()),
+            quota: (!self.quotas.is_empty()).then(|| self.quotas.clone()),
+            acl_file: self.acl_file.clone(),
+            ..self.common.layer()
+        };
+        self.common.settings(layer)
+    }
+}
+
 #[derive(clap::Subcommand)]
 enum Command {
     /// Run the store (REPL) with optional log retention
-    Run {
-        /// Memory limit in bytes.
-        #[arg(long, default_value = "10485760")]
-        memory_limit: u64,
+    Run(CommonArgs),
 
-        /// Prune log files older than this many minutes (0 = disable).
-        #[arg(long, default_value = "60")]
-        retention_minutes: u64,
+    /// Serve storage over TCP and/or Unix socket
+    Serve(ServeArgs),
 
-        /// Directory for log files (if unset, logs only to stdout).
-        #[arg(long)]
-        log_dir: Option<PathBuf>,
-    },
+    /// Inspect configuration files
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
 
-    /// Serve storage over TCP and/or Unix socket
-    Serve {
-        /// Memory limit in bytes.
-        #[arg(long, default_value = "10485760")]
-        memory_limit: u64,
-
-        /// TCP listen address (e.g. 0.0.0.0:8765).
-        #[arg(long)]
-        tcp: Option<String>,
-
-        /// Unix socket path (e.g. /tmp/blink-store.sock). Unix only.
-        #[arg(long)]
-        unix: Option<PathBuf>,
-
-        /// Prune log files older than this many minutes (0 = disable).
-        #[arg(long, default_value = "60")]
-        retention_minutes: u64,
-
-        /// Directory for log files.
-        #[arg(long)]
-        log_dir: Option<PathBuf>,
-    },
+#[derive(clap::Subcommand)]
+enum ConfigCommand {
+    /// Validate a config file and print the effective configuration
+    Check(ServeArgs),
 }
 
 #[tokio::main]
@@ -63,82 +153,147 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Command::Run {
-            memory_limit,
-            retention_minutes,
-            log_dir,
-        } => {
+        Command::Run(args) => {
+            let settings = args.settings(args.layer())?;
             let _guard = blink_store::logging::init_tracing(
-                log_dir.as_deref(),
-                Some("blink_store=info,info"),
+                settings.log_dir.as_deref(),
+                Some(&settings.log_level),
             )?;
+            spawn_log_retention(&settings);
 
-            if let Some(ref dir) = log_dir {
-                if retention_minutes > 0 {
-                    let dir = dir.clone();
-                    let retention = Duration::from_secs(retention_minutes * 60);
-                    blink_store::logging::spawn_log_retention_worker(dir, retention);
-                }
-            }
-
-            let store = Arc::new(MemoryEngine::new(memory_limit)?);
-            info!(action = "start", memory_limit, "Store ready");
+            let store = Arc::new(MemoryEngine::new(settings.memory_limit)?);
+            info!(action = "start", memory_limit = settings.memory_limit, "Store ready");
             run_repl(store).await?;
         }
 
-        Command::Serve {
-            memory_limit,
-            tcp,
-            unix,
-            retention_minutes,
-            log_dir,
-        } => {
-            if tcp.is_none() && unix.is_none() {
+        Command::Serve(args) => {
+            let settings = args.settings()?;
+            if settings.tcp.is_none() && settings.unix.is_none() {
                 anyhow::bail!("At least one of --tcp or --unix is required");
             }
-            let _guard = blink_store::logging::init_tracing(
-                log_dir.as_deref(),
-                Some("blink_store=info,info"),
+            let guard = blink_store::logging::init_tracing(
+                settings.log_dir.as_deref(),
+                Some(&settings.log_level),
             )?;
+            spawn_log_retention(&settings);
 
-            if let Some(ref dir) = log_dir {
-                if retention_minutes > 0 {
-                    let dir = dir.clone();
-                    let retention = Duration::from_secs(retention_minutes * 60);
-                    blink_store::logging::spawn_log_retention_worker(dir, retention);
-                }
+            let mut state = build_state(&settings)?.with_log_filter(guard.filter_handle());
+            if let Some(path) = &args.common.config {
+                state = state.with_config_path(path.clone());
             }
+            let state = Arc::new(state);
+            blink_store::server::spawn_expiry_sweep(state.clone());
 
-            let store = Arc::new(MemoryEngine::new(memory_limit)?);
+            #[cfg(unix)]
+            if settings.acl_file.is_some() {
+                spawn_acl_reload_on_sighup(state.clone())?;
+            }
 
-            if let Some(ref addr) = tcp {
-                let store_tcp = store.clone();
+            if let Some(ref addr) = settings.tcp {
+                let state_tcp = state.clone();
                 let addr = addr.clone();
                 tokio::spawn(async move {
-                    let _ = blink_store::run_tcp(&addr, store_tcp).await;
+                    let _ = blink_store::run_tcp(&addr, state_tcp).await;
                 });
             }
             #[cfg(unix)]
-            if let Some(ref path) = unix {
-                let store_unix = store.clone();
+            if let Some(ref path) = settings.unix {
+                let state_unix = state.clone();
                 let path = path.clone();
                 tokio::spawn(async move {
-                    let _ = blink_store::run_unix(&path, store_unix).await;
+                    let _ = blink_store::run_unix(&path, state_unix).await;
                 });
             }
             #[cfg(not(unix))]
-            if unix.is_some() {
+            if settings.unix.is_some() {
                 anyhow::bail!("--unix is only supported on Unix platforms");
             }
 
-            info!(action = "serve", memory_limit, "Server ready");
+            info!(action = "serve", memory_limit = settings.memory_limit, "Server ready");
             tokio::signal::ctrl_c().await.context("wait for ctrl_c")?;
         }
+
+        Command::Config(ConfigCommand::Check(args)) => {
+            let Some(path) = &args.common.config else {
+                anyhow::bail!("config check requires --config <file>");
+            };
+            let settings = args
+                .settings()
+                .with_context(|| format!("invalid config {}", path.display()))?;
+            if let Some(acl) = &settings.acl_file {
+                load_acl(acl)?;
+            }
+            if settings.tcp.is_none() && settings.unix.is_none() {
+                eprintln!("warning: no tcp or unix listener configured; `serve` will refuse to start");
+            }
+            print!("{}", settings);
+        }
     }
 
     Ok(())
 }
 
+fn spawn_log_retention(settings: &Settings) {
+    if let Some(ref dir) = settings.log_dir {
+        if settings.retention_minutes > 0 {
+            let retention = Duration::from_secs(settings.retention_minutes * 60);
+            blink_store::logging::spawn_log_retention_worker(dir.clone(), retention);
+        }
+    }
+}
+
+/// Creates the databases, quotas and ACL described by `settings`.
+fn build_state(settings: &Settings) -> Result<ServerState> {
+    let engine = |limit: u64| -> Result<Arc<MemoryEngine>> {
+        let engine = MemoryEngine::new(limit)?;
+        engine.set_eviction_policy(settings.eviction_policy);
+        engine.set_eviction_samples(settings.eviction_samples)?;
+        Ok(Arc::new(engine))
+    };
+
+    let mut state = ServerState::new(engine(settings.memory_limit)?);
+    for index in 1..settings.databases {
+        state = state.with_database(&index.to_string(), engine(settings.memory_limit)?)?;
+    }
+    for db in &settings.named_dbs {
+        let limit = db.memory_limit.unwrap_or(settings.memory_limit);
+        state = state.with_database(&db.name, engine(limit)?)?;
+    }
+    for quota in &settings.quotas {
+        for db in state.databases() {
+            db.engine().set_quota(&quota.prefix, quota.limit_bytes)?;
+        }
+    }
+    state.set_idle_timeout(Duration::from_secs(settings.idle_timeout_secs));
+    state.set_lease_timeout(Duration::from_millis(settings.lease_timeout_ms));
+    if let Some(path) = &settings.acl_file {
+        let acl = load_acl(path)?;
+        state = state.with_acl(acl);
+    }
+    Ok(state)
+}
+
+fn load_acl(path: &Path) -> Result<Acl> {
+    Acl::load(path).with_context(|| format!("load ACL file {}", path.display()))
+}
+
+/// Reloads the ACL file whenever the process receives SIGHUP.
+#[cfg(unix)]
+fn spawn_acl_reload_on_sighup(state: Arc<ServerState>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup()).context("install SIGHUP handler")?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Some(acl) = state.acl() {
+                if let Err(e) = acl.reload() {
+                    info!(error = %e, action = "acl_reload_failed");
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
 /// Simple REPL: get <k>, set <k> <v>, delete <k>, usage, quit.
 async fn run_repl(store: std::sync::Arc<MemoryEngine>) -> Result<()> {
     use blink_store::BlinkStorage;
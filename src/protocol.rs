@@ -1,12 +1,94 @@
 //! Zero-copy text protocol for Blink Store.
 //!
 //! Line-based protocol (UTF-8, LF line endings):
-//! - `GET <key>`         → `VALUE <base64>` or `NOT_FOUND`
+//! - `GET <key>`         → `VALUE <base64>`, `STALE <base64>` or `NOT_FOUND`
 //! - `SET <key> <value>` → `OK` or `ERROR <msg>`
+//! - `SETEX <key> <soft_ms> <hard_ms> <value>` → `OK` or `ERROR <msg>` (0 = no expiry)
 //! - `DELETE <key>`      → `OK` or `NOT_FOUND`
-//! - `USAGE`             → `USAGE <bytes>`
+//! - `EXISTS <key>...`   → `INTEGER <existing>`
+//! - `TOUCH <key>...`    → `INTEGER <existing>`
+//! - `TYPE <key>`        → `VALUE <base64 string|hash|list|set|zset|hll|bloom|stream|ratelimit|lock|none>`
+//! - `RENAME <key> <newkey>` → `OK` or `ERROR <msg>`
+//! - `RENAMENX <key> <newkey>` → `INTEGER 1`, or `INTEGER 0` if `<newkey>` exists
+//! - `DBSIZE`            → `INTEGER <keys>`
+//! - `GETSET <key> <value>` → `VALUE <base64>` (previous value) or `NOT_FOUND`
+//! - `GETDEL <key>`      → `VALUE <base64>` or `NOT_FOUND`
+//! - `GETEX <key> <soft_ms> <hard_ms>` → `VALUE <base64>` or `NOT_FOUND` (0 = no expiry)
+//! - `APPEND <key> <value>` → `INTEGER <length>`
+//! - `SETRANGE <key> <offset> <value>` → `INTEGER <length>`
+//! - `GETRANGE <key> <start> <end>` → `VALUE <base64>` (empty if missing)
+//! - `STRLEN <key>`      → `INTEGER <length>`
+//! - `HSET <key> <field> <value>` → `INTEGER 1` (new field) or `INTEGER 0`
+//! - `HGET <key> <field>` → `VALUE <base64>` or `NOT_FOUND`
+//! - `HDEL <key> <field>...` → `INTEGER <removed>`
+//! - `HGETALL <key>`     → `VALUES <n> <field> <value>...` (fields base64 too)
+//! - `LPUSH`/`RPUSH <key> <value>` → `INTEGER <length>`
+//! - `LPOP`/`RPOP <key>`  → `VALUE <base64>` or `NOT_FOUND`
+//! - `LRANGE <key> <start> <stop>` → `VALUES <n> <base64>...`
+//! - `BLPOP <key> <timeout_ms>` → `VALUE <base64>`, or `NOT_FOUND` after the
+//!   timeout (0 waits indefinitely)
+//! - `SADD`/`SREM <key> <member>...` → `INTEGER <added/removed>`
+//! - `SMEMBERS <key>`    → `VALUES <n> <base64>...`
+//! - `SISMEMBER <key> <member>` → `INTEGER 1` or `INTEGER 0`
+//! - `ZADD <key> <score> <member> [<score> <member>...]` → `INTEGER <added>`
+//! - `ZINCRBY <key> <delta> <member>` → `VALUE <base64 score>`
+//! - `ZREM <key> <member>...` → `INTEGER <removed>`
+//! - `ZRANGE <key> <start> <stop> [WITHSCORES]` → `VALUES <n> <base64>...`
+//! - `ZRANGEBYSCORE <key> <min> <max> [WITHSCORES]` → `VALUES <n> <base64>...`
+//!   (`(` before a bound excludes it; `-inf`/`+inf` are unbounded)
+//! - `RATELIMIT <key> <max> <window_ms> [cost]` → `INFO allowed=<0|1> remaining=<n>
+//!   retry_after_ms=<ms> reset_after_ms=<ms>`
+//! - `LOCK <key> <ttl_ms> [owner]` → `INTEGER <fencing token>`, or `INTEGER 0` if held
+//! - `EXTEND <key> <ttl_ms> <owner>` → `INTEGER <fencing token>`, or `INTEGER 0` if not held
+//! - `UNLOCK <key> <owner>` → `INTEGER 1` if released, else `INTEGER 0`
+//! - `PFADD <key> <element>...` → `INTEGER 1` if the estimate may have changed, else `INTEGER 0`
+//! - `PFCOUNT <key>...`  → `INTEGER <estimated distinct elements>`
+//! - `PFMERGE <destkey> <sourcekey>...` → `OK`
+//! - `BF.RESERVE <key> <error_rate> <capacity>` → `INTEGER 1`, or `INTEGER 0` if `<key>` exists
+//! - `BF.ADD <key> <item>` → `INTEGER 1` if new, else `INTEGER 0`
+//! - `BF.EXISTS <key> <item>` → `INTEGER 1` if probably added, else `INTEGER 0`
+//! - `XADD <key> [MAXLEN <n>] <id|*> <field> <value>...` → `VALUE <base64 id>`
+//! - `XLEN <key>`        → `INTEGER <entries>`
+//! - `XRANGE <key> <start|-> <end|+> [COUNT <n>]` → `VALUES <n> <base64>...`, per
+//!   entry its ID, its number of fields, then fields and values
+//! - `XTRIM <key> MAXLEN <n>` → `INTEGER <removed>`
+//! - `XREAD <key> <id|$> [COUNT <n>] [BLOCK <ms>]` → entries after `<id>` as for
+//!   XRANGE, waiting up to `<ms>` (0 = indefinitely) for one with BLOCK
+//! - `XGROUP <key> CREATE <group> <id|$>` → `OK` or `ERROR <msg>`
+//! - `XGROUP <key> DESTROY <group>` → `INTEGER 1` if it existed, else `INTEGER 0`
+//! - `XREADGROUP <key> <group> <consumer> <>|id> [COUNT <n>] [BLOCK <ms>]` → entries
+//!   as for XRANGE
+//! - `XACK <key> <group> <id>...` → `INTEGER <acknowledged>`
+//! - `XPENDING <key> <group>` → `VALUES <n> <base64>...`, per entry its ID,
+//!   consumer, idle ms and delivery count
+//! - `USAGE [ALL]`       → `USAGE <bytes>` or `INFO <db>=<bytes>... total=<bytes>`
+//! - `INFO`              → `INFO db=<name> keys=<n> used_bytes=<n> limit_bytes=<n>
+//!   eviction_policy=<policy> databases=<n> subscribers=<n>`
+//! - `GETLOCK <key> [lease_ms]` → `VALUE`/`STALE <base64>` or `LEASE <ms>` (caller recomputes)
+//! - `WAIT <key> <version> <timeout_ms>` → `VERSIONED <version> [<base64>]` once the
+//!   key's version differs from `<version>` or the timeout passes
+//! - `WATCH <key>...`    → `OK`; a later `EXEC` aborts if any of the keys changed
+//! - `MULTI`             → `OK`; later commands reply `QUEUED` until `EXEC`/`DISCARD`
+//! - `EXEC`              → `EXEC <n> <base64 reply>...`, `ABORTED` or `ERROR <msg>`
+//! - `DISCARD`           → `OK`
+//! - `SELECT <db>`       → `OK` or `ERROR <msg>`
+//! - `QUOTA SET <prefix> <bytes>|DEL <prefix>|USAGE` → `OK`, `NOT_FOUND` or `INFO <prefix>=<used>/<limit>...`
+//! - `CONFIG GET <pattern>|SET <param> <value>|REWRITE` → `INFO <param>=<value>...` or `OK`
+//! - `AUTH <user> <pass>` → `OK` or `ERROR <msg>`
+//! - `ACL WHOAMI|LIST|RELOAD` → `VALUE <base64>`, `VALUES <n> <base64>...` or `OK`
+//! - `SUBSCRIBE <channel>...` / `PSUBSCRIBE <pattern>...` → `OK`, then pushed
+//!   `MESSAGE <channel> <base64>` / `PMESSAGE <pattern> <channel> <base64>`
+//! - `UNSUBSCRIBE [<channel-or-pattern>...]` → `OK`
+//! - `PUBLISH <channel> <message>` → `INTEGER <receivers>`
+//! - `WATCHEVENTS [pattern]` → `OK`, then pushed `EVENT <set|delete|evict|expire> <key>`
+//! - `PING`              → `PONG`
 //! - `QUIT`              → connection close
+//!
+//! Clients use `encode_request` and `parse_response`, the inverses of
+//! `parse_request` and `Response::write`.
 
+use crate::engine::KeyEvent;
+use crate::error::BlinkError;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use bytes::Bytes;
 use std::io::Write;
@@ -15,17 +97,377 @@ use std::io::Write;
 pub enum Command {
     Get,
     Set,
+    SetEx,
     Delete,
+    Exists,
+    Touch,
+    Type,
+    Rename,
+    RenameNx,
+    DbSize,
+    GetSet,
+    GetDel,
+    GetEx,
+    Append,
+    SetRange,
+    GetRange,
+    StrLen,
+    HSet,
+    HGet,
+    HDel,
+    HGetAll,
+    LPush,
+    RPush,
+    LPop,
+    RPop,
+    LRange,
+    BLPop,
+    SAdd,
+    SRem,
+    SMembers,
+    SIsMember,
+    ZAdd,
+    ZIncrBy,
+    ZRem,
+    ZRange,
+    ZRangeByScore,
+    RateLimit,
+    Lock,
+    Extend,
+    Unlock,
+    PfAdd,
+    PfCount,
+    PfMerge,
+    BfReserve,
+    BfAdd,
+    BfExists,
+    XAdd,
+    XLen,
+    XRange,
+    XTrim,
+    XRead,
+    XGroup,
+    XReadGroup,
+    XAck,
+    XPending,
     Usage,
+    Info,
+    GetLock,
+    Wait,
+    Watch,
+    Multi,
+    Exec,
+    Discard,
+    Select,
+    Quota,
+    Config,
+    Auth,
+    Acl,
+    Subscribe,
+    PSubscribe,
+    Unsubscribe,
+    Publish,
+    WatchEvents,
+    Ping,
     Quit,
 }
 
-#[derive(Debug)]
+/// How the text after a command name maps onto `(key, value)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgShape {
+    /// No arguments (`PING`).
+    None,
+    /// The rest of the line is the key (`GET <key>`).
+    Key,
+    /// First token is the key, the rest of the line the value (`SET <key> <value>`).
+    KeyValue,
+    /// The rest of the line is the value; the key is empty (`SELECT <db>`).
+    Value,
+}
+
+impl Command {
+    pub const ALL: &'static [Command] = &[
+        Command::Get,
+        Command::Set,
+        Command::SetEx,
+        Command::Delete,
+        Command::Exists,
+        Command::Touch,
+        Command::Type,
+        Command::Rename,
+        Command::RenameNx,
+        Command::DbSize,
+        Command::GetSet,
+        Command::GetDel,
+        Command::GetEx,
+        Command::Append,
+        Command::SetRange,
+        Command::GetRange,
+        Command::StrLen,
+        Command::HSet,
+        Command::HGet,
+        Command::HDel,
+        Command::HGetAll,
+        Command::LPush,
+        Command::RPush,
+        Command::LPop,
+        Command::RPop,
+        Command::LRange,
+        Command::BLPop,
+        Command::SAdd,
+        Command::SRem,
+        Command::SMembers,
+        Command::SIsMember,
+        Command::ZAdd,
+        Command::ZIncrBy,
+        Command::ZRem,
+        Command::ZRange,
+        Command::ZRangeByScore,
+        Command::RateLimit,
+        Command::Lock,
+        Command::Extend,
+        Command::Unlock,
+        Command::PfAdd,
+        Command::PfCount,
+        Command::PfMerge,
+        Command::BfReserve,
+        Command::BfAdd,
+        Command::BfExists,
+        Command::XAdd,
+        Command::XLen,
+        Command::XRange,
+        Command::XTrim,
+        Command::XRead,
+        Command::XGroup,
+        Command::XReadGroup,
+        Command::XAck,
+        Command::XPending,
+        Command::Usage,
+        Command::Info,
+        Command::GetLock,
+        Command::Wait,
+        Command::Watch,
+        Command::Multi,
+        Command::Exec,
+        Command::Discard,
+        Command::Select,
+        Command::Quota,
+        Command::Config,
+        Command::Auth,
+        Command::Acl,
+        Command::Subscribe,
+        Command::PSubscribe,
+        Command::Unsubscribe,
+        Command::Publish,
+        Command::WatchEvents,
+        Command::Ping,
+        Command::Quit,
+    ];
+
+    /// Looks up a command by name, case-insensitively.
+    pub fn from_name(name: &str) -> Option<Command> {
+        Command::ALL
+            .iter()
+            .find(|c| c.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    pub fn arg_shape(&self) -> ArgShape {
+        match self {
+            Command::Get
+            | Command::Delete
+            | Command::Type
+            | Command::GetDel
+            | Command::StrLen
+            | Command::HGetAll
+            | Command::LPop
+            | Command::RPop
+            | Command::SMembers
+            | Command::XLen => ArgShape::Key,
+            Command::Set
+            | Command::SetEx
+            | Command::Exists
+            | Command::Touch
+            | Command::Rename
+            | Command::RenameNx
+            | Command::GetSet
+            | Command::GetEx
+            | Command::Append
+            | Command::SetRange
+            | Command::GetRange
+            | Command::HSet
+            | Command::HGet
+            | Command::HDel
+            | Command::LPush
+            | Command::RPush
+            | Command::LRange
+            | Command::BLPop
+            | Command::SAdd
+            | Command::SRem
+            | Command::SIsMember
+            | Command::ZAdd
+            | Command::ZIncrBy
+            | Command::ZRem
+            | Command::ZRange
+            | Command::ZRangeByScore
+            | Command::RateLimit
+            | Command::Lock
+            | Command::Extend
+            | Command::Unlock
+            | Command::PfAdd
+            | Command::PfCount
+            | Command::PfMerge
+            | Command::BfReserve
+            | Command::BfAdd
+            | Command::BfExists
+            | Command::XAdd
+            | Command::XRange
+            | Command::XTrim
+            | Command::XRead
+            | Command::XGroup
+            | Command::XReadGroup
+            | Command::XAck
+            | Command::XPending
+            | Command::GetLock
+            | Command::Wait
+            | Command::Auth
+            | Command::Acl
+            | Command::Publish => ArgShape::KeyValue,
+            Command::Usage
+            | Command::Select
+            | Command::Quota
+            | Command::Config
+            | Command::Subscribe
+            | Command::PSubscribe
+            | Command::Unsubscribe
+            | Command::WatchEvents
+            | Command::Watch => ArgShape::Value,
+            Command::DbSize
+            | Command::Info
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Ping
+            | Command::Quit => ArgShape::None,
+        }
+    }
+
+    /// Lowercase command name, as used in ACL rules.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get => "get",
+            Command::Set => "set",
+            Command::SetEx => "setex",
+            Command::Delete => "delete",
+            Command::Exists => "exists",
+            Command::Touch => "touch",
+            Command::Type => "type",
+            Command::Rename => "rename",
+            Command::RenameNx => "renamenx",
+            Command::DbSize => "dbsize",
+            Command::GetSet => "getset",
+            Command::GetDel => "getdel",
+            Command::GetEx => "getex",
+            Command::Append => "append",
+            Command::SetRange => "setrange",
+            Command::GetRange => "getrange",
+            Command::StrLen => "strlen",
+            Command::HSet => "hset",
+            Command::HGet => "hget",
+            Command::HDel => "hdel",
+            Command::HGetAll => "hgetall",
+            Command::LPush => "lpush",
+            Command::RPush => "rpush",
+            Command::LPop => "lpop",
+            Command::RPop => "rpop",
+            Command::LRange => "lrange",
+            Command::BLPop => "blpop",
+            Command::SAdd => "sadd",
+            Command::SRem => "srem",
+            Command::SMembers => "smembers",
+            Command::SIsMember => "sismember",
+            Command::ZAdd => "zadd",
+            Command::ZIncrBy => "zincrby",
+            Command::ZRem => "zrem",
+            Command::ZRange => "zrange",
+            Command::ZRangeByScore => "zrangebyscore",
+            Command::RateLimit => "ratelimit",
+            Command::Lock => "lock",
+            Command::Extend => "extend",
+            Command::Unlock => "unlock",
+            Command::PfAdd => "pfadd",
+            Command::PfCount => "pfcount",
+            Command::PfMerge => "pfmerge",
+            Command::BfReserve => "bf.reserve",
+            Command::BfAdd => "bf.add",
+            Command::BfExists => "bf.exists",
+            Command::XAdd => "xadd",
+            Command::XLen => "xlen",
+            Command::XRange => "xrange",
+            Command::XTrim => "xtrim",
+            Command::XRead => "xread",
+            Command::XGroup => "xgroup",
+            Command::XReadGroup => "xreadgroup",
+            Command::XAck => "xack",
+            Command::XPending => "xpending",
+            Command::Usage => "usage",
+            Command::Info => "info",
+            Command::GetLock => "getlock",
+            Command::Wait => "wait",
+            Command::Watch => "watch",
+            Command::Multi => "multi",
+            Command::Exec => "exec",
+            Command::Discard => "discard",
+            Command::Select => "select",
+            Command::Quota => "quota",
+            Command::Config => "config",
+            Command::Auth => "auth",
+            Command::Acl => "acl",
+            Command::Subscribe => "subscribe",
+            Command::PSubscribe => "psubscribe",
+            Command::Unsubscribe => "unsubscribe",
+            Command::Publish => "publish",
+            Command::WatchEvents => "watchevents",
+            Command::Ping => "ping",
+            Command::Quit => "quit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Response {
     Ok,
+    Pong,
     Value(Bytes),
+    /// A value past its soft expiry; the client should revalidate it.
+    Stale(Bytes),
     NotFound,
     Usage(u64),
+    /// A count or other integer result.
+    Integer(i64),
+    Values(Vec<Bytes>),
+    Info(Vec<(String, String)>),
+    /// `GETLOCK` miss: the caller holds the key's lease for this many ms.
+    Lease(u64),
+    /// `WAIT` reply: the key's version and value (`None` if missing).
+    Versioned {
+        version: u64,
+        value: Option<Bytes>,
+    },
+    /// A command was queued by `MULTI` for the next `EXEC`.
+    Queued,
+    /// `EXEC`: the replies of the queued commands, in order.
+    Exec(Vec<Response>),
+    /// `EXEC` did nothing because a `WATCH`ed key changed.
+    Aborted,
+    /// Pushed to subscribers; `pattern` is set for `PSUBSCRIBE` matches.
+    Message {
+        channel: String,
+        pattern: Option<String>,
+        payload: Bytes,
+    },
+    /// Pushed to `WATCHEVENTS` connections.
+    Event(KeyEvent),
     Error(String),
 }
 
@@ -33,10 +475,59 @@ impl Response {
     pub fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
         match self {
             Response::Ok => writeln!(w, "OK"),
+            Response::Pong => writeln!(w, "PONG"),
             Response::Value(v) => writeln!(w, "VALUE {}", BASE64.encode(v)),
+            Response::Stale(v) => writeln!(w, "STALE {}", BASE64.encode(v)),
             Response::NotFound => writeln!(w, "NOT_FOUND"),
             Response::Usage(n) => writeln!(w, "USAGE {}", n),
-            Response::Error(msg) => writeln!(w, "ERROR {}", msg.replace('\n', " ")),
+            Response::Integer(n) => writeln!(w, "INTEGER {}", n),
+            Response::Values(vs) => {
+                write!(w, "VALUES {}", vs.len())?;
+                for v in vs {
+                    write!(w, " {}", BASE64.encode(v))?;
+                }
+                writeln!(w)
+            }
+            Response::Info(fields) => {
+                write!(w, "INFO")?;
+                for (k, v) in fields {
+                    write!(w, " {}={}", k, v)?;
+                }
+                writeln!(w)
+            }
+            Response::Lease(ms) => writeln!(w, "LEASE {}", ms),
+            Response::Versioned {
+                version,
+                value: None,
+            } => writeln!(w, "VERSIONED {}", version),
+            Response::Versioned {
+                version,
+                value: Some(v),
+            } => writeln!(w, "VERSIONED {} {}", version, BASE64.encode(v)),
+            Response::Queued => writeln!(w, "QUEUED"),
+            Response::Exec(replies) => {
+                write!(w, "EXEC {}", replies.len())?;
+                for reply in replies {
+                    let mut line = Vec::new();
+                    reply.write(&mut line)?;
+                    line.pop();
+                    write!(w, " {}", BASE64.encode(line))?;
+                }
+                writeln!(w)
+            }
+            Response::Aborted => writeln!(w, "ABORTED"),
+            Response::Message {
+                channel,
+                pattern: None,
+                payload,
+            } => writeln!(w, "MESSAGE {} {}", channel, BASE64.encode(payload)),
+            Response::Message {
+                channel,
+                pattern: Some(pattern),
+                payload,
+            } => writeln!(w, "PMESSAGE {} {} {}", pattern, channel, BASE64.encode(payload)),
+            Response::Event(event) => writeln!(w, "EVENT {} {}", event.kind.as_str(), event.key),
+            Response::Error(msg) => writeln!(w, "ERROR {}", msg.replace(['\n', '\r'], " ")),
         }
     }
 }
@@ -50,23 +541,173 @@ pub fn parse_request(line: &str) -> Option<(Command, &str, &str)> {
     }
     let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
     let rest = rest.trim_start();
+    let cmd = Command::from_name(cmd)?;
+
+    Some(match cmd.arg_shape() {
+        ArgShape::None => (cmd, "", ""),
+        ArgShape::Key => (cmd, rest, ""),
+        ArgShape::KeyValue => {
+            let (key, value) = split_key_value(rest);
+            (cmd, key, value)
+        }
+        ArgShape::Value => (cmd, "", rest),
+    })
+}
 
-    if cmd.eq_ignore_ascii_case("GET") {
-        Some((Command::Get, rest.trim(), ""))
-    } else if cmd.eq_ignore_ascii_case("DELETE") {
-        Some((Command::Delete, rest.trim(), ""))
-    } else if cmd.eq_ignore_ascii_case("SET") {
-        let (key, value) = rest
-            .split_once(char::is_whitespace)
-            .unwrap_or((rest, ""));
-        Some((Command::Set, key, value.trim_start()))
-    } else if cmd.eq_ignore_ascii_case("USAGE") {
-        Some((Command::Usage, "", ""))
-    } else if cmd.eq_ignore_ascii_case("QUIT") {
-        Some((Command::Quit, "", ""))
-    } else {
-        None
+/// Parses one response line back into a `Response`; inverse of
+/// `Response::write`. Only the line ending is stripped, since trailing spaces
+/// can be significant (e.g. an empty last value in `VALUES`).
+pub fn parse_response(line: &str) -> Result<Response, BlinkError> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let decode = |b64: &str| {
+        BASE64
+            .decode(b64)
+            .map(Bytes::from)
+            .map_err(|e| BlinkError::Protocol(format!("bad base64: {}", e)))
+    };
+    match kind {
+        "OK" => Ok(Response::Ok),
+        "PONG" => Ok(Response::Pong),
+        "NOT_FOUND" => Ok(Response::NotFound),
+        "VALUE" => Ok(Response::Value(decode(rest)?)),
+        "STALE" => Ok(Response::Stale(decode(rest)?)),
+        "USAGE" => rest
+            .parse()
+            .map(Response::Usage)
+            .map_err(|_| BlinkError::Protocol(format!("bad usage: {}", line))),
+        "INTEGER" => rest
+            .parse()
+            .map(Response::Integer)
+            .map_err(|_| BlinkError::Protocol(format!("bad integer: {}", line))),
+        "VALUES" => {
+            let mut parts = rest.split(' ');
+            let n: usize = parts
+                .next()
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| BlinkError::Protocol(format!("bad values: {}", line)))?;
+            let values = parts.map(decode).collect::<Result<Vec<_>, _>>()?;
+            if values.len() != n {
+                return Err(BlinkError::Protocol(format!("bad values: {}", line)));
+            }
+            Ok(Response::Values(values))
+        }
+        "INFO" => Ok(Response::Info(
+            rest.split(' ')
+                .filter(|f| !f.is_empty())
+                .map(|f| {
+                    let (k, v) = f.split_once('=').unwrap_or((f, ""));
+                    (k.to_owned(), v.to_owned())
+                })
+                .collect(),
+        )),
+        "LEASE" => rest
+            .parse()
+            .map(Response::Lease)
+            .map_err(|_| BlinkError::Protocol(format!("bad lease: {}", line))),
+        "VERSIONED" => {
+            // A missing key has no value field at all; an empty value still
+            // has the separating space.
+            let (version, value) = match rest.split_once(' ') {
+                Some((version, value)) => (version, Some(decode(value)?)),
+                None => (rest, None),
+            };
+            let version = version
+                .parse()
+                .map_err(|_| BlinkError::Protocol(format!("bad version: {}", line)))?;
+            Ok(Response::Versioned { version, value })
+        }
+        "QUEUED" => Ok(Response::Queued),
+        "ABORTED" => Ok(Response::Aborted),
+        "EXEC" => {
+            let bad = || BlinkError::Protocol(format!("bad exec: {}", line));
+            let mut parts = rest.split(' ');
+            let n: usize = parts.next().and_then(|n| n.parse().ok()).ok_or_else(bad)?;
+            let replies = parts
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    let reply = decode(p)?;
+                    parse_response(std::str::from_utf8(&reply).map_err(|_| bad())?)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if replies.len() != n {
+                return Err(bad());
+            }
+            Ok(Response::Exec(replies))
+        }
+        "MESSAGE" | "PMESSAGE" => {
+            let bad = || BlinkError::Protocol(format!("bad message: {}", line));
+            let (pattern, rest) = if kind == "PMESSAGE" {
+                let (pattern, rest) = rest.split_once(' ').ok_or_else(bad)?;
+                (Some(pattern.to_owned()), rest)
+            } else {
+                (None, rest)
+            };
+            let (channel, payload) = rest.split_once(' ').ok_or_else(bad)?;
+            Ok(Response::Message {
+                channel: channel.to_owned(),
+                pattern,
+                payload: decode(payload)?,
+            })
+        }
+        "EVENT" => {
+            let (kind, key) = rest
+                .split_once(' ')
+                .ok_or_else(|| BlinkError::Protocol(format!("bad event: {}", line)))?;
+            Ok(Response::Event(KeyEvent {
+                kind: kind.parse()?,
+                key: key.to_owned(),
+            }))
+        }
+        "ERROR" => Ok(Response::Error(rest.to_owned())),
+        _ => Err(BlinkError::Protocol(format!("unknown reply: {}", line))),
+    }
+}
+
+/// Encodes a request line (with trailing LF) that `parse_request` reads back
+/// as exactly `(cmd, key, value)`. Requests the line format cannot carry
+/// (whitespace in keys, line breaks or surrounding whitespace in values,
+/// arguments the command does not take) are rejected.
+pub fn encode_request(cmd: Command, key: &str, value: &str) -> Result<String, BlinkError> {
+    let invalid = |why: &str| Err(BlinkError::Protocol(format!("{}: {}", cmd.name(), why)));
+    if key.contains(char::is_whitespace) {
+        return invalid("key must not contain whitespace");
+    }
+    if value.contains(['\n', '\r']) {
+        return invalid("value must not contain line breaks");
     }
+    if value.trim() != value {
+        return invalid("value must not start or end with whitespace");
+    }
+    match cmd.arg_shape() {
+        ArgShape::None if !key.is_empty() || !value.is_empty() => {
+            return invalid("takes no arguments")
+        }
+        ArgShape::Key if !value.is_empty() => return invalid("takes only a key"),
+        ArgShape::KeyValue if key.is_empty() && !value.is_empty() => {
+            return invalid("value requires a key")
+        }
+        ArgShape::Value if !key.is_empty() => return invalid("takes no key"),
+        _ => {}
+    }
+    let mut line = String::with_capacity(cmd.name().len() + key.len() + value.len() + 3);
+    line.push_str(&cmd.name().to_ascii_uppercase());
+    for part in [key, value] {
+        if !part.is_empty() {
+            line.push(' ');
+            line.push_str(part);
+        }
+    }
+    line.push('\n');
+    Ok(line)
+}
+
+/// Splits `rest` into its first whitespace-delimited token and the remainder.
+fn split_key_value(rest: &str) -> (&str, &str) {
+    let (key, value) = rest
+        .split_once(char::is_whitespace)
+        .unwrap_or((rest, ""));
+    (key, value.trim_start())
 }
 
 #[cfg(test)]
@@ -110,4 +751,177 @@ mod tests {
         assert_eq!(cmd, Command::Get);
         assert_eq!(key, "FOO");
     }
+
+    #[test]
+    fn parse_select_and_usage_all() {
+        let (cmd, key, db) = parse_request("SELECT staging").unwrap();
+        assert_eq!(cmd, Command::Select);
+        assert!(key.is_empty());
+        assert_eq!(db, "staging");
+        let (cmd, _, scope) = parse_request("usage all").unwrap();
+        assert_eq!(cmd, Command::Usage);
+        assert_eq!(scope, "all");
+    }
+
+    #[test]
+    fn parse_auth_and_acl() {
+        let (cmd, user, pass) = parse_request("AUTH alice s3cret").unwrap();
+        assert_eq!(cmd, Command::Auth);
+        assert_eq!(user, "alice");
+        assert_eq!(pass, "s3cret");
+        let (cmd, sub, _) = parse_request("acl whoami").unwrap();
+        assert_eq!(cmd, Command::Acl);
+        assert_eq!(sub, "whoami");
+    }
+
+    #[test]
+    fn write_values() {
+        let mut buf = Vec::new();
+        Response::Values(vec![Bytes::from_static(b"a"), Bytes::from_static(b"bc")])
+            .write(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"VALUES 2 YQ== YmM=\n");
+    }
+
+    #[test]
+    fn write_info() {
+        let mut buf = Vec::new();
+        Response::Info(vec![("0".into(), "12".into()), ("total".into(), "12".into())])
+            .write(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"INFO 0=12 total=12\n");
+    }
+
+    #[test]
+    fn parse_response_reads_written_lines() {
+        for r in [
+            Response::Ok,
+            Response::NotFound,
+            Response::Usage(42),
+            Response::Value(Bytes::from_static(b"hello")),
+            Response::Values(vec![Bytes::from_static(b"a")]),
+            Response::Error("bad thing".into()),
+        ] {
+            let mut buf = Vec::new();
+            r.write(&mut buf).unwrap();
+            let line = std::str::from_utf8(&buf).unwrap().trim_end();
+            assert_eq!(parse_response(line).unwrap(), r);
+        }
+        assert!(parse_response("WHAT").is_err());
+    }
+
+    #[test]
+    fn encode_request_reads_back() {
+        let line = encode_request(Command::Set, "k", "v with spaces").unwrap();
+        assert_eq!(line, "SET k v with spaces\n");
+        assert_eq!(
+            parse_request(&line).unwrap(),
+            (Command::Set, "k", "v with spaces")
+        );
+        assert_eq!(encode_request(Command::Usage, "", "").unwrap(), "USAGE\n");
+        assert!(encode_request(Command::Get, "a b", "").is_err());
+        assert!(encode_request(Command::Set, "k", "x\ny").is_err());
+    }
+
+    #[test]
+    fn encode_request_rejects_unrepresentable_args() {
+        assert!(encode_request(Command::Ping, "k", "").is_err());
+        assert!(encode_request(Command::Get, "k", "v").is_err());
+        assert!(encode_request(Command::Select, "k", "1").is_err());
+        assert!(encode_request(Command::Set, "", "v").is_err());
+        assert!(encode_request(Command::Set, "k", " v").is_err());
+    }
+
+    mod props {
+        use super::*;
+        use crate::engine::KeyEventKind;
+        use proptest::prelude::*;
+
+        fn token() -> impl Strategy<Value = String> {
+            "[^\\s=]{1,12}"
+        }
+
+        fn bytes() -> impl Strategy<Value = Bytes> {
+            proptest::collection::vec(any::<u8>(), 0..32).prop_map(Bytes::from)
+        }
+
+        fn response() -> impl Strategy<Value = Response> {
+            let leaf = single_response();
+            prop_oneof![
+                4 => leaf.clone(),
+                1 => proptest::collection::vec(leaf, 0..4).prop_map(Response::Exec),
+            ]
+        }
+
+        fn single_response() -> BoxedStrategy<Response> {
+            prop_oneof![
+                Just(Response::Ok),
+                Just(Response::Pong),
+                Just(Response::NotFound),
+                any::<u64>().prop_map(Response::Usage),
+                any::<u64>().prop_map(Response::Lease),
+                any::<i64>().prop_map(Response::Integer),
+                (
+                    proptest::sample::select(vec![
+                        KeyEventKind::Set,
+                        KeyEventKind::Delete,
+                        KeyEventKind::Evict,
+                        KeyEventKind::Expire,
+                    ]),
+                    token()
+                )
+                    .prop_map(|(kind, key)| Response::Event(KeyEvent { kind, key })),
+                (token(), proptest::option::of(token()), bytes()).prop_map(
+                    |(channel, pattern, payload)| Response::Message {
+                        channel,
+                        pattern,
+                        payload,
+                    }
+                ),
+                bytes().prop_map(Response::Value),
+                bytes().prop_map(Response::Stale),
+                (any::<u64>(), proptest::option::of(bytes()))
+                    .prop_map(|(version, value)| Response::Versioned { version, value }),
+                proptest::collection::vec(bytes(), 0..6).prop_map(Response::Values),
+                proptest::collection::vec((token(), "[^\\s]{0,12}"), 0..6)
+                    .prop_map(Response::Info),
+                "[^\r\n]{0,40}".prop_map(Response::Error),
+                Just(Response::Queued),
+                Just(Response::Aborted),
+            ]
+            .boxed()
+        }
+
+        /// A (command, key, value) that `encode_request` accepts.
+        fn request() -> impl Strategy<Value = (Command, String, String)> {
+            let value = "([^\\s]([^\r\n]{0,20}[^\\s])?)?";
+            (proptest::sample::select(Command::ALL), token(), value).prop_map(
+                |(cmd, key, value)| match cmd.arg_shape() {
+                    ArgShape::None => (cmd, String::new(), String::new()),
+                    ArgShape::Key => (cmd, key, String::new()),
+                    ArgShape::KeyValue => (cmd, key, value),
+                    ArgShape::Value => (cmd, String::new(), value),
+                },
+            )
+        }
+
+        proptest! {
+            #[test]
+            fn response_round_trips(r in response()) {
+                let mut buf = Vec::new();
+                r.write(&mut buf).unwrap();
+                let line = String::from_utf8(buf).unwrap();
+                prop_assert_eq!(parse_response(&line).unwrap(), r);
+            }
+
+            #[test]
+            fn request_round_trips((cmd, key, value) in request()) {
+                let line = encode_request(cmd, &key, &value).unwrap();
+                prop_assert_eq!(
+                    parse_request(&line),
+                    Some((cmd, key.as_str(), value.as_str()))
+                );
+            }
+        }
+    }
 }
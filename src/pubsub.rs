@@ -0,0 +1,229 @@
+//! Publish/subscribe hub behind `SUBSCRIBE`, `PSUBSCRIBE` and `PUBLISH`.
+//!
+//! Every subscriber has a bounded buffer. `publish` never waits: a
+//! subscriber whose buffer is full is dropped from the hub, and its
+//! `Subscription::recv` returns `None` so the server can close the
+//! connection instead of letting one slow reader hold memory for everyone.
+
+use crate::glob::glob_match;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::info;
+
+/// Messages buffered per subscriber before it is considered too slow.
+pub const DEFAULT_SUBSCRIBER_BUFFER: usize = 1024;
+
+/// A published message as delivered to one subscriber.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: String,
+    /// The `PSUBSCRIBE` pattern that matched, if not an exact subscription.
+    pub pattern: Option<String>,
+    pub payload: Bytes,
+}
+
+struct Subscriber {
+    tx: mpsc::Sender<Message>,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+}
+
+impl Subscriber {
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+pub struct PubSub {
+    buffer: usize,
+    next_id: AtomicU64,
+    subscribers: RwLock<HashMap<u64, Subscriber>>,
+}
+
+impl PubSub {
+    /// Hub whose subscribers may each fall `buffer` messages behind.
+    pub fn new(buffer: usize) -> Arc<Self> {
+        Arc::new(Self {
+            buffer: buffer.max(1),
+            next_id: AtomicU64::new(0),
+            subscribers: RwLock::new(HashMap::new()),
+        })
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<u64, Subscriber>> {
+        self.subscribers.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<u64, Subscriber>> {
+        self.subscribers.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a subscriber with no channels yet.
+    pub fn subscriber(self: &Arc<Self>) -> Subscription {
+        let (tx, rx) = mpsc::channel(self.buffer);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.write().insert(
+            id,
+            Subscriber {
+                tx,
+                channels: HashSet::new(),
+                patterns: HashSet::new(),
+            },
+        );
+        Subscription {
+            id,
+            hub: self.clone(),
+            rx,
+        }
+    }
+
+    /// Delivers `payload` to every subscriber of `channel` (once each, even
+    /// if several of its patterns match) and returns how many received it.
+    pub fn publish(&self, channel: &str, payload: Bytes) -> usize {
+        let mut delivered = 0;
+        let mut slow = Vec::new();
+        for (id, sub) in self.read().iter() {
+            let pattern = if sub.channels.contains(channel) {
+                None
+            } else {
+                match sub.patterns.iter().find(|p| glob_match(p, channel)) {
+                    Some(p) => Some(p.clone()),
+                    None => continue,
+                }
+            };
+            let message = Message {
+                channel: channel.to_owned(),
+                pattern,
+                payload: payload.clone(),
+            };
+            match sub.tx.try_send(message) {
+                Ok(()) => delivered += 1,
+                Err(TrySendError::Full(_)) => slow.push(*id),
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+        if !slow.is_empty() {
+            let mut subscribers = self.write();
+            for id in slow {
+                if subscribers.remove(&id).is_some() {
+                    info!(subscriber = id, action = "pubsub_drop_slow_subscriber");
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Number of connected subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        self.read().len()
+    }
+}
+
+/// One subscriber's handle; unregisters itself when dropped.
+pub struct Subscription {
+    id: u64,
+    hub: Arc<PubSub>,
+    rx: mpsc::Receiver<Message>,
+}
+
+impl Subscription {
+    fn update(&self, f: impl FnOnce(&mut Subscriber)) -> usize {
+        match self.hub.write().get_mut(&self.id) {
+            Some(sub) => {
+                f(sub);
+                sub.count()
+            }
+            None => 0,
+        }
+    }
+
+    /// Subscribes to `channel`; returns the number of active subscriptions.
+    pub fn subscribe(&self, channel: &str) -> usize {
+        self.update(|sub| {
+            sub.channels.insert(channel.to_owned());
+        })
+    }
+
+    /// Subscribes to channels matching a glob `pattern`.
+    pub fn psubscribe(&self, pattern: &str) -> usize {
+        self.update(|sub| {
+            sub.patterns.insert(pattern.to_owned());
+        })
+    }
+
+    /// Drops the channel or pattern `name`, or every subscription if `None`.
+    pub fn unsubscribe(&self, name: Option<&str>) -> usize {
+        self.update(|sub| match name {
+            Some(name) => {
+                sub.channels.remove(name);
+                sub.patterns.remove(name);
+            }
+            None => {
+                sub.channels.clear();
+                sub.patterns.clear();
+            }
+        })
+    }
+
+    /// Number of active channel and pattern subscriptions.
+    pub fn count(&self) -> usize {
+        self.update(|_| {})
+    }
+
+    /// Next message; `None` once the hub dropped this subscriber for
+    /// falling too far behind.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.hub.write().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn publish_reaches_channel_and_pattern_subscribers() {
+        let hub = PubSub::new(8);
+        let mut exact = hub.subscriber();
+        let mut pattern = hub.subscriber();
+        assert_eq!(exact.subscribe("cache:users"), 1);
+        assert_eq!(pattern.psubscribe("cache:*"), 1);
+
+        assert_eq!(hub.publish("cache:users", Bytes::from_static(b"7")), 2);
+        assert_eq!(hub.publish("other", Bytes::from_static(b"x")), 0);
+        assert_eq!(exact.recv().await.unwrap().pattern, None);
+        assert_eq!(
+            pattern.recv().await.unwrap().pattern.as_deref(),
+            Some("cache:*")
+        );
+
+        assert_eq!(exact.unsubscribe(None), 0);
+        assert_eq!(hub.publish("cache:users", Bytes::new()), 1);
+        drop(pattern);
+        assert_eq!(hub.subscriber_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_is_dropped() {
+        let hub = PubSub::new(2);
+        let mut slow = hub.subscriber();
+        slow.subscribe("c");
+        for _ in 0..3 {
+            hub.publish("c", Bytes::new());
+        }
+        assert_eq!(hub.subscriber_count(), 0);
+        assert!(slow.recv().await.is_some());
+        assert!(slow.recv().await.is_some());
+        assert!(slow.recv().await.is_none());
+    }
+}
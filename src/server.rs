@@ -1,27 +1,251 @@
 //! TCP and Unix socket server exposing MemoryEngine over the text protocol.
 
-use crate::engine::{BlinkStorage, MemoryEngine};
+use crate::acl::{Acl, AclUser, DEFAULT_USER};
+use crate::config;
+use crate::engine::{
+    BlinkStorage, End, EvictionPolicy, Expiry, Freshness, KeyEvent, LeaseLookup, MemoryEngine,
+};
 use crate::error::BlinkError;
+use crate::glob::glob_match;
+use crate::logging::LogFilterHandle;
 use crate::protocol::{parse_request, Command, Response};
+use crate::pubsub::{Message, PubSub, Subscription, DEFAULT_SUBSCRIBER_BUFFER};
+use crate::stream::{StreamEntry, StreamId};
 use bytes::Bytes;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
-use tokio::net::{TcpListener, TcpStream};
-use tracing::info;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use tracing::{info, trace};
 
-pub async fn run_tcp(addr: &str, store: Arc<MemoryEngine>) -> Result<(), BlinkError> {
+/// A named logical database backed by its own engine.
+pub struct Database {
+    name: String,
+    engine: Arc<MemoryEngine>,
+}
+
+impl Database {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn engine(&self) -> &Arc<MemoryEngine> {
+        &self.engine
+    }
+}
+
+/// State shared by every connection: the databases, optional access control
+/// and runtime-tunable settings.
+pub struct ServerState {
+    databases: Vec<Database>,
+    acl: Option<Acl>,
+    log_filter: Option<LogFilterHandle>,
+    config_path: Option<PathBuf>,
+    /// Close connections idle for this many seconds (0 = never).
+    idle_timeout_secs: AtomicU64,
+    /// Lease granted by `GETLOCK` when the request does not give one.
+    lease_timeout_ms: AtomicU64,
+    pubsub: Arc<PubSub>,
+}
+
+impl ServerState {
+    /// Creates a server with a single database `0` backed by `store`.
+    pub fn new(store: Arc<MemoryEngine>) -> Self {
+        Self {
+            databases: vec![Database {
+                name: "0".to_owned(),
+                engine: store,
+            }],
+            acl: None,
+            log_filter: None,
+            config_path: None,
+            idle_timeout_secs: AtomicU64::new(0),
+            lease_timeout_ms: AtomicU64::new(config::DEFAULT_LEASE_TIMEOUT_MS),
+            pubsub: PubSub::new(DEFAULT_SUBSCRIBER_BUFFER),
+        }
+    }
+
+    /// Adds another database, selectable by `name` or by its index.
+    pub fn with_database(mut self, name: &str, engine: Arc<MemoryEngine>) -> Result<Self, BlinkError> {
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '=') {
+            return Err(BlinkError::Config(format!("invalid database name '{}'", name)));
+        }
+        if self.find_database(name).is_some() {
+            return Err(BlinkError::Config(format!("duplicate database '{}'", name)));
+        }
+        self.databases.push(Database {
+            name: name.to_owned(),
+            engine,
+        });
+        Ok(self)
+    }
+
+    /// Enables access control; connections must then authenticate unless
+    /// the ACL defines a passwordless `default` user.
+    pub fn with_acl(mut self, acl: Acl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Lets `CONFIG SET log_level` change the tracing filter.
+    pub fn with_log_filter(mut self, handle: LogFilterHandle) -> Self {
+        self.log_filter = Some(handle);
+        self
+    }
+
+    /// File that `CONFIG REWRITE` writes the runtime settings back to.
+    pub fn with_config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs.load(Ordering::Acquire))
+    }
+
+    pub fn set_idle_timeout(&self, timeout: Duration) {
+        self.idle_timeout_secs
+            .store(timeout.as_secs(), Ordering::Release);
+    }
+
+    pub fn lease_timeout(&self) -> Duration {
+        Duration::from_millis(self.lease_timeout_ms.load(Ordering::Acquire))
+    }
+
+    pub fn set_lease_timeout(&self, timeout: Duration) {
+        self.lease_timeout_ms
+            .store(timeout.as_millis() as u64, Ordering::Release);
+    }
+
+    /// The default database (index 0).
+    pub fn store(&self) -> &Arc<MemoryEngine> {
+        &self.databases[0].engine
+    }
+
+    pub fn databases(&self) -> &[Database] {
+        &self.databases
+    }
+
+    /// Resolves a database by name first, then by index.
+    pub fn find_database(&self, name_or_index: &str) -> Option<usize> {
+        self.databases
+            .iter()
+            .position(|db| db.name == name_or_index)
+            .or_else(|| {
+                name_or_index
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&i| i < self.databases.len())
+            })
+    }
+
+    pub fn acl(&self) -> Option<&Acl> {
+        self.acl.as_ref()
+    }
+
+    /// Server-wide pub/sub hub (channels are shared by every database).
+    pub fn pubsub(&self) -> &Arc<PubSub> {
+        &self.pubsub
+    }
+}
+
+/// Per-connection state.
+struct Session {
+    /// Authenticated ACL user name; `None` until AUTH when ACLs are enabled.
+    user: Option<String>,
+    /// Index into `ServerState::databases`, changed by SELECT.
+    db: usize,
+    /// Set while the connection has channel subscriptions (push mode).
+    subscription: Option<Subscription>,
+    /// Set by WATCHEVENTS (push mode until the connection closes).
+    events: Option<EventWatch>,
+    /// Commands queued since MULTI; `None` outside a transaction.
+    queued: Option<Vec<(Command, String, String)>>,
+    /// A command was rejected while queuing, so EXEC must fail.
+    queue_failed: bool,
+    /// Keys WATCHed for the next EXEC: database, key and version seen.
+    watched: Vec<(usize, String, u64)>,
+}
+
+impl Session {
+    fn new(state: &ServerState) -> Self {
+        let user = match state.acl() {
+            Some(acl) => acl.default_user().map(|u| u.name().to_owned()),
+            None => Some(DEFAULT_USER.to_owned()),
+        };
+        Self {
+            user,
+            db: 0,
+            subscription: None,
+            events: None,
+            queued: None,
+            queue_failed: false,
+            watched: Vec::new(),
+        }
+    }
+
+    fn in_push_mode(&self) -> bool {
+        self.subscription.is_some() || self.events.is_some()
+    }
+
+    /// Resolves the session's user against the current ACL, so that reloads
+    /// apply to open connections. `Ok(None)` means ACLs are disabled.
+    fn acl_user(&self, acl: Option<&Acl>) -> Result<Option<Arc<AclUser>>, BlinkError> {
+        let Some(acl) = acl else {
+            return Ok(None);
+        };
+        self.user
+            .as_deref()
+            .and_then(|name| acl.user(name))
+            .map(Some)
+            .ok_or(BlinkError::NoAuth)
+    }
+}
+
+/// How often `spawn_expiry_sweep` purges hard-expired entries.
+pub const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Periodically removes hard-expired entries from every database, so keys
+/// that are never read again do not hold memory until evicted.
+pub fn spawn_expiry_sweep(state: Arc<ServerState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+        loop {
+            tick.tick().await;
+            for db in state.databases() {
+                let purged = db.engine.purge_expired();
+                if purged > 0 {
+                    trace!(db = %db.name, purged, action = "expiry_sweep");
+                }
+            }
+        }
+    })
+}
+
+pub async fn run_tcp(addr: &str, state: Arc<ServerState>) -> Result<(), BlinkError> {
     let listener = TcpListener::bind(addr)
         .await
         .map_err(|e| BlinkError::Internal(format!("TCP bind {}: {}", addr, e)))?;
     info!(action = "tcp_listen", addr = %addr);
+    serve_tcp(listener, state).await
+}
+
+/// Serves connections from an already-bound listener (e.g. one bound to port 0).
+pub async fn serve_tcp(listener: TcpListener, state: Arc<ServerState>) -> Result<(), BlinkError> {
     loop {
         let (stream, peer) = listener
             .accept()
             .await
             .map_err(|e| BlinkError::Internal(format!("accept: {}", e)))?;
-        let store = store.clone();
+        let state = state.clone();
         tokio::spawn(async move {
-            if let Err(e) = serve_stream(stream, store).await {
+            let (reader, writer) = stream.into_split();
+            if let Err(e) = serve_stream(reader, writer, state).await {
                 info!(peer = ?peer, error = %e, action = "serve_stream_error");
             }
         });
@@ -31,7 +255,7 @@ pub async fn run_tcp(addr: &str, store: Arc<MemoryEngine>) -> Result<(), BlinkEr
 #[cfg(unix)]
 pub async fn run_unix(
     path: &std::path::Path,
-    store: Arc<MemoryEngine>,
+    state: Arc<ServerState>,
 ) -> Result<(), BlinkError> {
     use tokio::net::UnixListener;
     let _ = std::fs::remove_file(path);
@@ -43,36 +267,170 @@ pub async fn run_unix(
             .accept()
             .await
             .map_err(|e| BlinkError::Internal(format!("unix accept: {}", e)))?;
-        let store = store.clone();
+        let state = state.clone();
         tokio::spawn(async move {
-            let _ = serve_unix_stream(stream, store).await;
+            let (reader, writer) = stream.into_split();
+            let _ = serve_stream(reader, writer, state).await;
         });
     }
 }
 
-async fn serve_stream(
-    mut stream: TcpStream,
-    store: Arc<MemoryEngine>,
-) -> Result<(), BlinkError> {
-    let (reader, mut writer) = stream.split();
+/// A `WATCHEVENTS` registration: keyspace events of one database, filtered
+/// by a glob and by the ACL key patterns of the user who asked.
+struct EventWatch {
+    events: BroadcastStream<KeyEvent>,
+    pattern: String,
+    user: Option<Arc<AclUser>>,
+}
+
+impl EventWatch {
+    /// Next matching event; `None` once this watcher has missed events.
+    async fn next(&mut self) -> Option<KeyEvent> {
+        while let Some(item) = self.events.next().await {
+            let event = item.ok()?;
+            if glob_match(&self.pattern, &event.key)
+                && self.user.as_ref().is_none_or(|u| u.can_access(&event.key))
+            {
+                return Some(event);
+            }
+        }
+        None
+    }
+}
+
+async fn next_message(subscription: Option<&mut Subscription>) -> Option<Message> {
+    match subscription {
+        Some(subscription) => subscription.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_event(watch: Option<&mut EventWatch>) -> Option<KeyEvent> {
+    match watch {
+        Some(watch) => watch.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// What woke a connection's read loop.
+enum Incoming {
+    /// A full request line is in the buffer.
+    Line,
+    Closed,
+    IdleTimeout,
+    /// A message for this connection's subscriptions.
+    Message(Message),
+    Event(KeyEvent),
+    /// The connection fell too far behind its push stream; carries the reason.
+    Dropped(&'static str),
+}
+
+/// Waits for the next request line or, in push mode, a published message.
+/// Uses `read_until` because it keeps partial lines in `line` when the
+/// message branch wins the `select!`.
+async fn next_incoming<R>(
+    reader: &mut AsyncBufReader<R>,
+    line: &mut Vec<u8>,
+    session: &mut Session,
+    idle_timeout: Duration,
+) -> Result<Incoming, BlinkError>
+where
+    R: AsyncRead + Unpin,
+{
+    let read = |n: std::io::Result<usize>| match n {
+        Ok(0) => Ok(Incoming::Closed),
+        Ok(_) => Ok(Incoming::Line),
+        Err(e) => Err(BlinkError::Internal(format!("read: {}", e))),
+    };
+    if session.in_push_mode() {
+        // Push connections are idle by design, so no idle timeout here.
+        return tokio::select! {
+            n = reader.read_until(b'\n', line) => read(n),
+            message = next_message(session.subscription.as_mut()) => Ok(match message {
+                Some(message) => Incoming::Message(message),
+                None => Incoming::Dropped("subscriber too slow, disconnected"),
+            }),
+            event = next_event(session.events.as_mut()) => Ok(match event {
+                Some(event) => Incoming::Event(event),
+                None => Incoming::Dropped("event watcher too slow, disconnected"),
+            }),
+        };
+    }
+    if idle_timeout.is_zero() {
+        return read(reader.read_until(b'\n', line).await);
+    }
+    match tokio::time::timeout(idle_timeout, reader.read_until(b'\n', line)).await {
+        Ok(n) => read(n),
+        Err(_) => Ok(Incoming::IdleTimeout),
+    }
+}
+
+/// Resolves once the peer closes the connection. Requests pipelined behind
+/// a blocking one stay buffered for the next read.
+async fn peer_closed<R>(reader: &mut AsyncBufReader<R>)
+where
+    R: AsyncRead + Unpin,
+{
+    match reader.fill_buf().await {
+        Ok([]) | Err(_) => {}
+        Ok(_) => std::future::pending().await,
+    }
+}
+
+async fn serve_stream<R, W>(
+    reader: R,
+    mut writer: W,
+    state: Arc<ServerState>,
+) -> Result<(), BlinkError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
     let mut reader = AsyncBufReader::new(reader);
-    let mut line = String::new();
+    let mut line = Vec::new();
     let mut resp_buf = Vec::with_capacity(256);
+    let mut session = Session::new(&state);
 
     loop {
-        line.clear();
-        let n = reader
-            .read_line(&mut line)
-            .await
-            .map_err(|e| BlinkError::Internal(format!("read: {}", e)))?;
-        if n == 0 {
-            break;
-        }
-
-        let response = match parse_request(line.trim()) {
-            Some((Command::Quit, _, _)) => break,
-            Some((cmd, key, value)) => handle_command(cmd, key, value, &store),
-            None => Response::Error("unknown command".into()),
+        let timeout = state.idle_timeout();
+        let response = match next_incoming(&mut reader, &mut line, &mut session, timeout).await? {
+            Incoming::Closed => break,
+            Incoming::IdleTimeout => {
+                info!(action = "idle_timeout", secs = timeout.as_secs());
+                break;
+            }
+            Incoming::Dropped(reason) => {
+                info!(reason, action = "push_disconnect_slow_reader");
+                let _ = writer.write_all(format!("ERROR {}\n", reason).as_bytes()).await;
+                break;
+            }
+            Incoming::Event(event) => Response::Event(event),
+            Incoming::Message(message) => Response::Message {
+                channel: message.channel,
+                pattern: message.pattern,
+                payload: message.payload,
+            },
+            Incoming::Line => {
+                let request = std::str::from_utf8(&line)
+                    .map_err(|_| BlinkError::Protocol("request is not valid UTF-8".into()))
+                    .map(|l| parse_request(l.trim()));
+                let response = match request {
+                    Ok(Some((Command::Quit, _, _))) => break,
+                    Ok(Some((cmd, key, value))) if may_block(cmd) => {
+                        // Blocking requests end when the client goes away,
+                        // so their registrations do not outlive it.
+                        tokio::select! {
+                            response = dispatch(cmd, key, value, &state, &mut session) => response,
+                            _ = peer_closed(&mut reader) => break,
+                        }
+                    }
+                    Ok(Some((cmd, key, value))) => dispatch(cmd, key, value, &state, &mut session).await,
+                    Ok(None) => Response::Error("unknown command".into()),
+                    Err(e) => Response::Error(e.to_string()),
+                };
+                line.clear();
+                response
+            }
         };
 
         resp_buf.clear();
@@ -87,52 +445,792 @@ async fn serve_stream(
     Ok(())
 }
 
-#[cfg(unix)]
-async fn serve_unix_stream(
-    stream: tokio::net::UnixStream,
-    store: Arc<MemoryEngine>,
-) -> Result<(), BlinkError> {
-    let (reader, mut writer) = stream.into_split();
-    let mut reader = AsyncBufReader::new(reader);
-    let mut line = String::new();
-    let mut resp_buf = Vec::with_capacity(256);
+/// Handles connection-level commands and enforces ACLs before touching storage.
+async fn dispatch(
+    cmd: Command,
+    key: &str,
+    value: &str,
+    state: &ServerState,
+    session: &mut Session,
+) -> Response {
+    if session.in_push_mode()
+        && !matches!(
+            cmd,
+            Command::Subscribe
+                | Command::PSubscribe
+                | Command::Unsubscribe
+                | Command::WatchEvents
+                | Command::Ping
+        )
+    {
+        return Response::Error(
+            "only (P)SUBSCRIBE, UNSUBSCRIBE, WATCHEVENTS, PING and QUIT are allowed in push mode"
+                .into(),
+        );
+    }
+    if session.queued.is_some()
+        && !matches!(
+            cmd,
+            Command::Multi | Command::Exec | Command::Discard | Command::Watch
+        )
+    {
+        return queue_command(cmd, key, value, state, session);
+    }
+    match cmd {
+        Command::Ping => return Response::Pong,
+        Command::Auth => return handle_auth(key, value, state, session),
+        Command::Acl => return handle_acl(key, state, session),
+        _ => {}
+    }
+    let user = match session.acl_user(state.acl()) {
+        Ok(user) => user,
+        Err(e) => return Response::Error(e.to_string()),
+    };
+    if let Some(user) = &user {
+        if let Err(e) = check_keys(user, cmd, key, value) {
+            return Response::Error(e.to_string());
+        }
+    }
+    match cmd {
+        Command::Select => handle_select(value, state, session),
+        Command::Usage if value.eq_ignore_ascii_case("ALL") => handle_usage_all(state),
+        Command::Info => handle_info(state, session),
+        Command::Quota => handle_quota(value, &state.databases[session.db].engine),
+        Command::Config => handle_config(value, state, &state.databases[session.db].engine),
+        Command::Subscribe | Command::PSubscribe | Command::Unsubscribe => {
+            handle_subscribe(cmd, value, state, session)
+        }
+        Command::Publish => {
+            if key.is_empty() {
+                return Response::Error("PUBLISH requires channel".into());
+            }
+            let receivers = state
+                .pubsub
+                .publish(key, Bytes::copy_from_slice(value.as_bytes()));
+            Response::Integer(receivers as i64)
+        }
+        Command::WatchEvents => {
+            let pattern = if value.is_empty() { "*" } else { value };
+            session.events = Some(EventWatch {
+                events: state.databases[session.db].engine.subscribe(),
+                pattern: pattern.to_owned(),
+                user,
+            });
+            Response::Ok
+        }
+        Command::GetLock => {
+            handle_getlock(key, value, state, &state.databases[session.db].engine).await
+        }
+        Command::Wait => handle_wait(key, value, &state.databases[session.db].engine).await,
+        Command::BLPop => handle_blpop(key, value, &state.databases[session.db].engine).await,
+        Command::XRead | Command::XReadGroup => {
+            handle_xread(cmd, key, value, &state.databases[session.db].engine).await
+        }
+        Command::Multi => {
+            if session.queued.is_some() {
+                return Response::Error("MULTI calls can not be nested".into());
+            }
+            session.queued = Some(Vec::new());
+            Response::Ok
+        }
+        Command::Discard => {
+            if session.queued.take().is_none() {
+                return Response::Error("DISCARD without MULTI".into());
+            }
+            session.queue_failed = false;
+            session.watched.clear();
+            Response::Ok
+        }
+        Command::Watch => handle_watch(value, user.as_deref(), state, session),
+        Command::Exec => handle_exec(state, session).await,
+        Command::Rename | Command::RenameNx | Command::PfMerge => {
+            handle_exclusive(cmd, key, value, &state.databases[session.db].engine).await
+        }
+        _ => handle_command(cmd, key, value, &state.databases[session.db].engine),
+    }
+}
 
+/// `AclUser::check` for `key` and any further keys a command names in its
+/// value (`EXISTS a b`, `RENAME a b`).
+fn check_keys(user: &AclUser, cmd: Command, key: &str, value: &str) -> Result<(), BlinkError> {
+    user.check(cmd, key)?;
+    if matches!(
+        cmd,
+        Command::Exists
+            | Command::Touch
+            | Command::Rename
+            | Command::RenameNx
+            | Command::PfCount
+            | Command::PfMerge
+    ) {
+        for other in value.split_whitespace() {
+            user.check(cmd, other)?;
+        }
+    }
+    Ok(())
+}
+
+/// Queues `cmd` for EXEC. A command that cannot run inside a transaction,
+/// or that the user may not run, fails the whole transaction.
+fn queue_command(
+    cmd: Command,
+    key: &str,
+    value: &str,
+    state: &ServerState,
+    session: &mut Session,
+) -> Response {
+    let checked = if !transactional(cmd) {
+        Err(format!("{} is not allowed in MULTI", cmd.name().to_ascii_uppercase()))
+    } else {
+        match session.acl_user(state.acl()) {
+            Ok(Some(user)) => check_keys(&user, cmd, key, value).map_err(|e| e.to_string()),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    };
+    match checked {
+        Ok(()) => {
+            session
+                .queued
+                .get_or_insert_with(Vec::new)
+                .push((cmd, key.to_owned(), value.to_owned()));
+            Response::Queued
+        }
+        Err(msg) => {
+            session.queue_failed = true;
+            Response::Error(msg)
+        }
+    }
+}
+
+/// `WATCH <key>...`: remembers the keys' versions; the next EXEC aborts if
+/// any of them changed in the meantime.
+fn handle_watch(keys: &str, user: Option<&AclUser>, state: &ServerState, session: &mut Session) -> Response {
+    if session.queued.is_some() {
+        return Response::Error("WATCH inside MULTI is not allowed".into());
+    }
+    if keys.is_empty() {
+        return Response::Error("WATCH requires key".into());
+    }
+    let store = &state.databases[session.db].engine;
+    for key in keys.split_whitespace() {
+        if let Some(user) = user {
+            if let Err(e) = user.check(Command::Watch, key) {
+                return Response::Error(e.to_string());
+            }
+        }
+        session
+            .watched
+            .push((session.db, key.to_owned(), store.watch_version(key)));
+    }
+    Response::Ok
+}
+
+/// `EXEC`: runs the queued commands atomically against the selected
+/// database. Nothing is applied if a WATCHed key changed, queuing failed or
+/// any command fails. Runs on a blocking thread, since the transaction waits
+/// for every operation in flight on the database to finish.
+async fn handle_exec(state: &ServerState, session: &mut Session) -> Response {
+    let Some(queued) = session.queued.take() else {
+        return Response::Error("EXEC without MULTI".into());
+    };
+    let watched = std::mem::take(&mut session.watched);
+    if std::mem::take(&mut session.queue_failed) {
+        return Response::Error("EXECABORT transaction discarded because of earlier errors".into());
+    }
+    // Keys watched in another database are checked here but not locked.
+    let mut local = Vec::new();
+    for (db, key, version) in watched {
+        if db == session.db {
+            local.push((key, version));
+        } else if state.databases[db].engine.watch_version(&key) != version {
+            return Response::Aborted;
+        }
+    }
+    let store = state.databases[session.db].engine.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        store.transaction(&local, || {
+            queued
+                .iter()
+                .map(|(cmd, key, value)| match handle_command(*cmd, key, value, &store) {
+                    Response::Error(msg) => Err(msg),
+                    reply => Ok(reply),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+    })
+    .await;
+    match result {
+        Ok(None) => Response::Aborted,
+        Ok(Some(Ok(replies))) => Response::Exec(replies),
+        Ok(Some(Err(msg))) => Response::Error(format!("EXECABORT {}", msg)),
+        Err(e) => Response::Error(format!("EXECABORT {}", e)),
+    }
+}
+
+/// Runs a command that locks the whole engine (RENAME, RENAMENX, PFMERGE)
+/// on the blocking pool, so waiting for in-flight operations to drain does
+/// not stall an async worker.
+async fn handle_exclusive(cmd: Command, key: &str, value: &str, store: &Arc<MemoryEngine>) -> Response {
+    let store = store.clone();
+    let (key, value) = (key.to_owned(), value.to_owned());
+    tokio::task::spawn_blocking(move || handle_command(cmd, &key, &value, &store))
+        .await
+        .unwrap_or_else(|e| Response::Error(e.to_string()))
+}
+
+/// `SUBSCRIBE`, `PSUBSCRIBE` and `UNSUBSCRIBE` with space-separated names.
+/// The connection stays in push mode while it has any subscription.
+fn handle_subscribe(cmd: Command, names: &str, state: &ServerState, session: &mut Session) -> Response {
+    if cmd == Command::Unsubscribe {
+        if let Some(subscription) = &session.subscription {
+            let mut remaining = subscription.count();
+            if names.is_empty() {
+                remaining = subscription.unsubscribe(None);
+            }
+            for name in names.split_whitespace() {
+                remaining = subscription.unsubscribe(Some(name));
+            }
+            if remaining == 0 {
+                session.subscription = None;
+            }
+        }
+        return Response::Ok;
+    }
+    if names.is_empty() {
+        return Response::Error(format!("{} requires channel", cmd.name().to_ascii_uppercase()));
+    }
+    let subscription = session
+        .subscription
+        .get_or_insert_with(|| state.pubsub.subscriber());
+    for name in names.split_whitespace() {
+        if cmd == Command::Subscribe {
+            subscription.subscribe(name);
+        } else {
+            subscription.psubscribe(name);
+        }
+    }
+    Response::Ok
+}
+
+/// `GETLOCK <key> [lease_ms]`: the value if present; otherwise the first
+/// caller gets a lease to recompute it and the others wait until the key is
+/// set or the lease expires (then one of them is granted a new lease).
+async fn handle_getlock(
+    key: &str,
+    lease_ms: &str,
+    state: &ServerState,
+    store: &MemoryEngine,
+) -> Response {
+    if key.is_empty() {
+        return Response::Error("GETLOCK requires key".into());
+    }
+    let ttl = if lease_ms.is_empty() {
+        state.lease_timeout()
+    } else {
+        match lease_ms.parse::<u64>() {
+            Ok(ms) if ms > 0 => Duration::from_millis(ms),
+            _ => return Response::Error("GETLOCK lease must be a positive integer (ms)".into()),
+        }
+    };
     loop {
-        line.clear();
-        let n = reader
-            .read_line(&mut line)
-            .await
-            .map_err(|e| BlinkError::Internal(format!("read: {}", e)))?;
-        if n == 0 {
-            break;
+        match store.get_or_lease(key, ttl) {
+            Ok(LeaseLookup::Value(v)) => return Response::Value(v),
+            Ok(LeaseLookup::Stale(v)) => return Response::Stale(v),
+            Ok(LeaseLookup::Granted) => return Response::Lease(ttl.as_millis() as u64),
+            Ok(LeaseLookup::Held(wait)) => wait.wait().await,
+            Err(e) => return Response::Error(e.to_string()),
         }
+    }
+}
 
-        let response = match parse_request(line.trim()) {
-            Some((Command::Quit, _, _)) => break,
-            Some((cmd, key, value)) => handle_command(cmd, key, value, &store),
-            None => Response::Error("unknown command".into()),
+/// `WAIT <key> <version> <timeout_ms>`: the key's current version and value
+/// once its version differs from `<version>`, or after the timeout. Version 0
+/// stands for a missing key; timeout 0 reads without blocking.
+async fn handle_wait(key: &str, args: &str, store: &MemoryEngine) -> Response {
+    if key.is_empty() {
+        return Response::Error("WAIT requires key".into());
+    }
+    let mut parts = args.split_whitespace().map(|p| p.parse::<u64>());
+    let (version, timeout_ms) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(version)), Some(Ok(timeout_ms)), None) => (version, timeout_ms),
+        _ => return Response::Error("WAIT expects <key> <version> <timeout_ms>".into()),
+    };
+    match store
+        .wait_for_change(key, version, Duration::from_millis(timeout_ms))
+        .await
+    {
+        Ok(current) => Response::Versioned {
+            version: current.version,
+            value: current.value,
+        },
+        Err(e) => Response::Error(e.to_string()),
+    }
+}
+
+/// `BLPOP <key> <timeout_ms>`: pops the head of a list, waiting for a push
+/// if it is empty. `NOT_FOUND` after the timeout; 0 waits indefinitely.
+async fn handle_blpop(key: &str, timeout_ms: &str, store: &MemoryEngine) -> Response {
+    if key.is_empty() {
+        return Response::Error("BLPOP requires key".into());
+    }
+    let timeout = match timeout_ms.parse::<u64>() {
+        Ok(0) => None,
+        Ok(ms) => Some(Duration::from_millis(ms)),
+        Err(_) => return Response::Error("BLPOP expects <key> <timeout_ms>".into()),
+    };
+    match store.blpop(key, timeout).await {
+        Ok(Some(v)) => Response::Value(v),
+        Ok(None) => Response::NotFound,
+        Err(e) => Response::Error(e.to_string()),
+    }
+}
+
+/// `XREAD <key> <id|$> [COUNT <n>] [BLOCK <ms>]` and `XREADGROUP <key>
+/// <group> <consumer> <>|id> [COUNT <n>] [BLOCK <ms>]`. With BLOCK, waits
+/// for a new entry (0 waits indefinitely); an empty `VALUES` after the
+/// timeout. BLOCK is ignored when XREADGROUP re-reads pending entries.
+async fn handle_xread(cmd: Command, key: &str, args: &str, store: &MemoryEngine) -> Response {
+    let mut parts = args.split_whitespace();
+    let result = if cmd == Command::XRead {
+        let id = parts.next();
+        let (id, count, block) = match (id, stream_read_options(parts)) {
+            (Some(id), Some((count, block))) if !key.is_empty() => (id, count, block),
+            _ => return Response::Error("XREAD expects <key> <id|$> [COUNT <n>] [BLOCK <ms>]".into()),
+        };
+        let after = match id {
+            "$" => None,
+            id => match id.parse::<StreamId>() {
+                Ok(id) => Some(id),
+                Err(e) => return Response::Error(e.to_string()),
+            },
+        };
+        match (after, block) {
+            (after, Some(timeout)) => store.xread_blocking(key, after, count, timeout).await,
+            (Some(after), None) => store.xread(key, after, count),
+            // Nothing is newer than the last entry without waiting.
+            (None, None) => Ok(Vec::new()),
+        }
+    } else {
+        let (group, consumer, id) = (parts.next(), parts.next(), parts.next());
+        let (group, consumer, id, count, block) = match (group, consumer, id, stream_read_options(parts)) {
+            (Some(group), Some(consumer), Some(id), Some((count, block))) if !key.is_empty() => {
+                (group, consumer, id, count, block)
+            }
+            _ => {
+                return Response::Error(
+                    "XREADGROUP expects <key> <group> <consumer> <>|id> [COUNT <n>] [BLOCK <ms>]".into(),
+                )
+            }
         };
+        match (id, block) {
+            (">", Some(timeout)) => store.xreadgroup_blocking(key, group, consumer, count, timeout).await,
+            (">", None) => store.xreadgroup(key, group, consumer, None, count),
+            (id, _) => match id.parse::<StreamId>() {
+                Ok(id) => store.xreadgroup(key, group, consumer, Some(id), count),
+                Err(e) => return Response::Error(e.to_string()),
+            },
+        }
+    };
+    match result {
+        Ok(entries) => stream_entries(entries),
+        Err(e) => Response::Error(e.to_string()),
+    }
+}
 
-        resp_buf.clear();
-        response
-            .write(&mut resp_buf)
-            .map_err(|e| BlinkError::Internal(e.to_string()))?;
-        writer
-            .write_all(&resp_buf)
-            .await
-            .map_err(|e| BlinkError::Internal(format!("write: {}", e)))?;
+fn handle_select(db: &str, state: &ServerState, session: &mut Session) -> Response {
+    if db.is_empty() {
+        return Response::Error("SELECT requires database".into());
+    }
+    match state.find_database(db) {
+        Some(index) => {
+            session.db = index;
+            Response::Ok
+        }
+        None => Response::Error(format!("unknown database '{}'", db)),
+    }
+}
+
+/// Reports usage for every database plus the total.
+fn handle_usage_all(state: &ServerState) -> Response {
+    let mut fields = Vec::with_capacity(state.databases.len() + 1);
+    let mut total = 0u64;
+    for db in &state.databases {
+        match db.engine.current_usage_bytes() {
+            Ok(n) => {
+                total += n;
+                fields.push((db.name.clone(), n.to_string()));
+            }
+            Err(e) => return Response::Error(e.to_string()),
+        }
+    }
+    fields.push(("total".to_owned(), total.to_string()));
+    Response::Info(fields)
+}
+
+/// Reports the selected database's keys, usage and limits plus server-wide
+/// counts, for monitoring.
+fn handle_info(state: &ServerState, session: &Session) -> Response {
+    let db = &state.databases[session.db];
+    let used = match db.engine.current_usage_bytes() {
+        Ok(n) => n,
+        Err(e) => return Response::Error(e.to_string()),
+    };
+    Response::Info(vec![
+        ("db".to_owned(), db.name.clone()),
+        ("keys".to_owned(), db.engine.dbsize().to_string()),
+        ("used_bytes".to_owned(), used.to_string()),
+        ("limit_bytes".to_owned(), db.engine.limit_bytes().to_string()),
+        ("eviction_policy".to_owned(), db.engine.eviction_policy().as_str().to_owned()),
+        ("databases".to_owned(), state.databases.len().to_string()),
+        ("subscribers".to_owned(), state.pubsub.subscriber_count().to_string()),
+    ])
+}
+
+fn handle_auth(user: &str, password: &str, state: &ServerState, session: &mut Session) -> Response {
+    let Some(acl) = state.acl() else {
+        return Response::Error("AUTH called but no ACL is configured".into());
+    };
+    if user.is_empty() {
+        return Response::Error("AUTH requires user and password".into());
+    }
+    match acl.authenticate(user, password) {
+        Ok(u) => {
+            session.user = Some(u.name().to_owned());
+            Response::Ok
+        }
+        Err(e) => {
+            info!(user = %user, action = "auth_failed");
+            Response::Error(e.to_string())
+        }
+    }
+}
+
+fn handle_acl(sub: &str, state: &ServerState, session: &Session) -> Response {
+    let user = match session.acl_user(state.acl()) {
+        Ok(user) => user,
+        Err(e) => return Response::Error(e.to_string()),
+    };
+    if sub.eq_ignore_ascii_case("WHOAMI") {
+        let name = user.as_ref().map(|u| u.name()).unwrap_or(DEFAULT_USER);
+        return Response::Value(Bytes::copy_from_slice(name.as_bytes()));
+    }
+    if let Some(user) = &user {
+        if let Err(e) = user.check(Command::Acl, "") {
+            return Response::Error(e.to_string());
+        }
+    }
+    let Some(acl) = state.acl() else {
+        return Response::Error("no ACL is configured".into());
+    };
+    if sub.eq_ignore_ascii_case("LIST") {
+        Response::Values(acl.list().into_iter().map(Bytes::from).collect())
+    } else if sub.eq_ignore_ascii_case("RELOAD") {
+        match acl.reload() {
+            Ok(_) => Response::Ok,
+            Err(e) => Response::Error(e.to_string()),
+        }
+    } else {
+        Response::Error("ACL requires WHOAMI, LIST or RELOAD".into())
     }
+}
+
+/// `QUOTA SET <prefix> <bytes>`, `QUOTA DEL <prefix>` or `QUOTA USAGE` on
+/// the current database.
+fn handle_quota(args: &str, store: &MemoryEngine) -> Response {
+    let mut parts = args.split_whitespace();
+    let sub = parts.next().unwrap_or("");
+    if sub.eq_ignore_ascii_case("SET") {
+        let (Some(prefix), Some(limit)) = (parts.next(), parts.next()) else {
+            return Response::Error("QUOTA SET requires prefix and bytes".into());
+        };
+        let Ok(limit) = limit.parse::<u64>() else {
+            return Response::Error("QUOTA SET bytes must be an integer".into());
+        };
+        match store.set_quota(prefix, limit) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e.to_string()),
+        }
+    } else if sub.eq_ignore_ascii_case("DEL") {
+        match parts.next() {
+            Some(prefix) if store.remove_quota(prefix) => Response::Ok,
+            Some(_) => Response::NotFound,
+            None => Response::Error("QUOTA DEL requires prefix".into()),
+        }
+    } else if sub.eq_ignore_ascii_case("USAGE") {
+        Response::Info(
+            store
+                .quota_usage()
+                .into_iter()
+                .map(|q| (q.prefix, format!("{}/{}", q.used_bytes, q.limit_bytes)))
+                .collect(),
+        )
+    } else {
+        Response::Error("QUOTA requires SET, DEL or USAGE".into())
+    }
+}
+
+/// Current value of a runtime parameter; `memory_limit` and the eviction
+/// settings are per database.
+fn config_value(param: &str, state: &ServerState, store: &MemoryEngine) -> Option<String> {
+    match param {
+        "memory_limit" => Some(store.limit_bytes().to_string()),
+        "eviction_policy" => Some(store.eviction_policy().as_str().to_owned()),
+        "eviction_samples" => Some(store.eviction_samples().to_string()),
+        "log_level" => state.log_filter.as_ref().map(|h| h.current()),
+        "idle_timeout_secs" => Some(state.idle_timeout().as_secs().to_string()),
+        "lease_timeout_ms" => Some(state.lease_timeout().as_millis().to_string()),
+        _ => None,
+    }
+}
+
+fn set_config_value(
+    param: &str,
+    value: &str,
+    state: &ServerState,
+    store: &MemoryEngine,
+) -> Result<(), BlinkError> {
+    let parse_u64 = || {
+        value
+            .parse::<u64>()
+            .map_err(|_| BlinkError::Config(format!("{} must be an integer", param)))
+    };
+    match param {
+        "memory_limit" => store.set_limit_bytes(parse_u64()?),
+        "eviction_policy" => store.set_eviction_policy(value.parse::<EvictionPolicy>()?),
+        "eviction_samples" => store.set_eviction_samples(parse_u64()? as usize)?,
+        "log_level" => match &state.log_filter {
+            Some(handle) => handle
+                .set(value)
+                .map_err(|e| BlinkError::Config(format!("log_level: {}", e)))?,
+            None => return Err(BlinkError::Config("log_level is not reloadable".into())),
+        },
+        "idle_timeout_secs" => state.set_idle_timeout(Duration::from_secs(parse_u64()?)),
+        "lease_timeout_ms" => match parse_u64()? {
+            0 => return Err(BlinkError::Config("lease_timeout_ms must be at least 1".into())),
+            ms => state.set_lease_timeout(Duration::from_millis(ms)),
+        },
+        _ => return Err(BlinkError::Config(format!("unknown parameter '{}'", param))),
+    }
+    info!(param = %param, value = %value, action = "config_set");
     Ok(())
 }
 
+/// `CONFIG GET <pattern>`, `CONFIG SET <param> <value>` or `CONFIG REWRITE`.
+fn handle_config(args: &str, state: &ServerState, store: &MemoryEngine) -> Response {
+    let (sub, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let rest = rest.trim();
+    if sub.eq_ignore_ascii_case("GET") {
+        if rest.is_empty() {
+            return Response::Error("CONFIG GET requires pattern".into());
+        }
+        Response::Info(
+            config::PARAMS
+                .iter()
+                .filter(|p| glob_match(rest, p))
+                .filter_map(|p| config_value(p, state, store).map(|v| ((*p).to_owned(), v)))
+                .collect(),
+        )
+    } else if sub.eq_ignore_ascii_case("SET") {
+        let (param, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let value = value.trim();
+        if param.is_empty() || value.is_empty() {
+            return Response::Error("CONFIG SET requires parameter and value".into());
+        }
+        match set_config_value(param, value, state, store) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e.to_string()),
+        }
+    } else if sub.eq_ignore_ascii_case("REWRITE") {
+        let Some(path) = &state.config_path else {
+            return Response::Error("server was not started with a config file".into());
+        };
+        // Per-database settings are written from database 0, which the
+        // top-level config keys describe.
+        let values: Vec<(String, String)> = config::PARAMS
+            .iter()
+            .filter_map(|p| config_value(p, state, state.store()).map(|v| ((*p).to_owned(), v)))
+            .collect();
+        match config::rewrite(path, &values) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e.to_string()),
+        }
+    } else {
+        Response::Error("CONFIG requires GET, SET or REWRITE".into())
+    }
+}
+
+/// Splits `<soft_ms> <hard_ms> <value>` (0 = no expiry) for SETEX.
+fn parse_expiry(args: &str) -> Result<(Expiry, &str), BlinkError> {
+    let invalid = || BlinkError::Protocol("SETEX expects <soft_ms> <hard_ms> <value>".into());
+    let mut parts = args.splitn(3, char::is_whitespace);
+    let mut next_ms = || -> Result<Option<Duration>, BlinkError> {
+        let ms = parts
+            .next()
+            .and_then(|p| p.parse::<u64>().ok())
+            .ok_or_else(invalid)?;
+        Ok((ms > 0).then(|| Duration::from_millis(ms)))
+    };
+    let expiry = Expiry {
+        soft: next_ms()?,
+        hard: next_ms()?,
+    };
+    Ok((expiry, parts.next().unwrap_or("").trim_start()))
+}
+
+/// Commands that can wait for another client's write.
+fn may_block(cmd: Command) -> bool {
+    matches!(
+        cmd,
+        Command::Wait | Command::GetLock | Command::BLPop | Command::XRead | Command::XReadGroup
+    )
+}
+
+/// Commands `handle_command` runs that MULTI may queue.
+fn transactional(cmd: Command) -> bool {
+    matches!(
+        cmd,
+        Command::Get
+            | Command::Set
+            | Command::SetEx
+            | Command::Delete
+            | Command::Exists
+            | Command::Touch
+            | Command::Type
+            | Command::Rename
+            | Command::RenameNx
+            | Command::DbSize
+            | Command::GetSet
+            | Command::GetDel
+            | Command::GetEx
+            | Command::Append
+            | Command::SetRange
+            | Command::GetRange
+            | Command::StrLen
+            | Command::HSet
+            | Command::HGet
+            | Command::HDel
+            | Command::HGetAll
+            | Command::LPush
+            | Command::RPush
+            | Command::LPop
+            | Command::RPop
+            | Command::LRange
+            | Command::SAdd
+            | Command::SRem
+            | Command::SMembers
+            | Command::SIsMember
+            | Command::ZAdd
+            | Command::ZIncrBy
+            | Command::ZRem
+            | Command::ZRange
+            | Command::ZRangeByScore
+            | Command::RateLimit
+            | Command::Lock
+            | Command::Extend
+            | Command::Unlock
+            | Command::PfAdd
+            | Command::PfCount
+            | Command::PfMerge
+            | Command::BfReserve
+            | Command::BfAdd
+            | Command::BfExists
+            | Command::XAdd
+            | Command::XLen
+            | Command::XRange
+            | Command::XTrim
+            | Command::XGroup
+            | Command::XAck
+            | Command::XPending
+    )
+}
+
+/// Parses a sorted-set score; `inf`, `+inf` and `-inf` are accepted, NaN
+/// is not.
+fn parse_score(s: &str) -> Option<f64> {
+    s.parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+/// Parses a `ZRANGEBYSCORE` bound; a leading `(` makes it exclusive.
+fn parse_score_bound(s: &str) -> Option<Bound<f64>> {
+    match s.strip_prefix('(') {
+        Some(score) => parse_score(score).map(Bound::Excluded),
+        None => parse_score(s).map(Bound::Included),
+    }
+}
+
+/// `ZRANGE`-style reply: members, each followed by its score if asked.
+fn scored_members(members: Vec<(String, f64)>, with_scores: bool) -> Response {
+    Response::Values(
+        members
+            .into_iter()
+            .flat_map(|(member, score)| {
+                let score = with_scores.then(|| Bytes::from(score.to_string()));
+                std::iter::once(Bytes::from(member)).chain(score)
+            })
+            .collect(),
+    )
+}
+
+/// Parses an `XRANGE` bound: `-` and `+` are the smallest and largest IDs,
+/// and `<ms>` alone is the first ID of that millisecond, or the last as an
+/// `end`.
+fn parse_stream_bound(s: &str, end: bool) -> Option<StreamId> {
+    match s {
+        "-" => Some(StreamId::MIN),
+        "+" => Some(StreamId::MAX),
+        _ if end && !s.contains('-') => s.parse().ok().map(|ms| StreamId { ms, seq: u64::MAX }),
+        _ => s.parse().ok(),
+    }
+}
+
+/// Trailing `COUNT <n>` and `BLOCK <ms>` options of stream reads: the
+/// entry limit (unlimited if absent or 0) and, with BLOCK, how long to wait
+/// (`None` for 0, which waits indefinitely).
+fn stream_read_options<'a>(
+    mut parts: impl Iterator<Item = &'a str>,
+) -> Option<(usize, Option<Option<Duration>>)> {
+    let (mut count, mut block) = (usize::MAX, None);
+    while let Some(option) = parts.next() {
+        let arg = parts.next()?.parse::<u64>().ok()?;
+        if option.eq_ignore_ascii_case("COUNT") {
+            count = match arg {
+                0 => usize::MAX,
+                n => usize::try_from(n).unwrap_or(usize::MAX),
+            };
+        } else if option.eq_ignore_ascii_case("BLOCK") {
+            block = Some((arg > 0).then(|| Duration::from_millis(arg)));
+        } else {
+            return None;
+        }
+    }
+    Some((count, block))
+}
+
+/// Stream entries as `VALUES`: per entry its ID, its number of fields, then
+/// each field and value.
+fn stream_entries(entries: Vec<StreamEntry>) -> Response {
+    let mut values = Vec::new();
+    for entry in entries {
+        values.push(Bytes::from(entry.id.to_string()));
+        values.push(Bytes::from(entry.fields.len().to_string()));
+        for (field, value) in entry.fields {
+            values.push(Bytes::from(field));
+            values.push(value);
+        }
+    }
+    Response::Values(values)
+}
+
 fn handle_command(cmd: Command, key: &str, value: &str, store: &MemoryEngine) -> Response {
     match cmd {
         Command::Get => {
             if key.is_empty() {
                 return Response::Error("GET requires key".into());
             }
-            match store.get(key) {
-                Ok(Some(v)) => Response::Value(v),
+            match store.get_with_freshness(key) {
+                Ok(Some((v, Freshness::Fresh))) => Response::Value(v),
+                Ok(Some((v, Freshness::Stale))) => Response::Stale(v),
                 Ok(None) => Response::NotFound,
                 Err(e) => Response::Error(e.to_string()),
             }
@@ -146,6 +1244,19 @@ fn handle_command(cmd: Command, key: &str, value: &str, store: &MemoryEngine) ->
                 Err(e) => Response::Error(e.to_string()),
             }
         }
+        Command::SetEx => {
+            if key.is_empty() {
+                return Response::Error("SETEX requires key".into());
+            }
+            let (expiry, value) = match parse_expiry(value) {
+                Ok(parsed) => parsed,
+                Err(e) => return Response::Error(e.to_string()),
+            };
+            match store.set_with_expiry(key, Bytes::copy_from_slice(value.as_bytes()), expiry) {
+                Ok(()) => Response::Ok,
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
         Command::Delete => {
             if key.is_empty() {
                 return Response::Error("DELETE requires key".into());
@@ -156,10 +1267,969 @@ fn handle_command(cmd: Command, key: &str, value: &str, store: &MemoryEngine) ->
                 Err(e) => Response::Error(e.to_string()),
             }
         }
+        Command::Exists | Command::Touch => {
+            if key.is_empty() {
+                return Response::Error(format!("{} requires key", cmd.name().to_ascii_uppercase()));
+            }
+            let keys: Vec<&str> = std::iter::once(key).chain(value.split_whitespace()).collect();
+            let n = if cmd == Command::Exists {
+                store.exists(&keys)
+            } else {
+                store.touch(&keys)
+            };
+            Response::Integer(n as i64)
+        }
+        Command::Type => {
+            if key.is_empty() {
+                return Response::Error("TYPE requires key".into());
+            }
+            Response::Value(Bytes::from_static(store.key_type(key).unwrap_or("none").as_bytes()))
+        }
+        Command::Rename | Command::RenameNx => {
+            if key.is_empty() || value.is_empty() || value.contains(char::is_whitespace) {
+                return Response::Error(format!(
+                    "{} expects <key> <newkey>",
+                    cmd.name().to_ascii_uppercase()
+                ));
+            }
+            match (cmd, store.rename(key, value, cmd == Command::Rename)) {
+                (Command::Rename, Ok(_)) => Response::Ok,
+                (_, Ok(renamed)) => Response::Integer(renamed as i64),
+                (_, Err(e)) => Response::Error(e.to_string()),
+            }
+        }
+        Command::DbSize => Response::Integer(store.dbsize() as i64),
+        Command::GetSet | Command::GetDel | Command::GetEx => {
+            if key.is_empty() {
+                return Response::Error(format!("{} requires key", cmd.name().to_ascii_uppercase()));
+            }
+            let result = match cmd {
+                Command::GetSet => store.getset(key, Bytes::copy_from_slice(value.as_bytes())),
+                Command::GetDel => store.getdel(key),
+                _ => match parse_expiry(value) {
+                    Ok((expiry, "")) => store.getex(key, expiry),
+                    _ => return Response::Error("GETEX expects <key> <soft_ms> <hard_ms>".into()),
+                },
+            };
+            match result {
+                Ok(Some(v)) => Response::Value(v),
+                Ok(None) => Response::NotFound,
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::Append => {
+            if key.is_empty() {
+                return Response::Error("APPEND requires key".into());
+            }
+            match store.append(key, value.as_bytes()) {
+                Ok(len) => Response::Integer(len as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::SetRange => {
+            let (offset, value) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+            let offset = match offset.parse::<usize>() {
+                Ok(offset) if !key.is_empty() => offset,
+                _ => return Response::Error("SETRANGE expects <key> <offset> <value>".into()),
+            };
+            match store.setrange(key, offset, value.trim_start().as_bytes()) {
+                Ok(len) => Response::Integer(len as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::GetRange => {
+            let mut bounds = value.split_whitespace().map(|p| p.parse::<i64>());
+            let (start, end) = match (bounds.next(), bounds.next(), bounds.next()) {
+                (Some(Ok(start)), Some(Ok(end)), None) if !key.is_empty() => (start, end),
+                _ => return Response::Error("GETRANGE expects <key> <start> <end>".into()),
+            };
+            match store.getrange(key, start, end) {
+                Ok(bytes) => Response::Value(bytes),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::StrLen => {
+            if key.is_empty() {
+                return Response::Error("STRLEN requires key".into());
+            }
+            match store.strlen(key) {
+                Ok(len) => Response::Integer(len as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::HSet => {
+            let (field, value) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+            if key.is_empty() || field.is_empty() {
+                return Response::Error("HSET requires key and field".into());
+            }
+            match store.hset(key, field, Bytes::copy_from_slice(value.trim_start().as_bytes())) {
+                Ok(is_new) => Response::Integer(is_new as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::HGet => {
+            if key.is_empty() || value.is_empty() {
+                return Response::Error("HGET requires key and field".into());
+            }
+            match store.hget(key, value) {
+                Ok(Some(v)) => Response::Value(v),
+                Ok(None) => Response::NotFound,
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::HDel => {
+            let fields: Vec<&str> = value.split_whitespace().collect();
+            if key.is_empty() || fields.is_empty() {
+                return Response::Error("HDEL requires key and field".into());
+            }
+            match store.hdel(key, &fields) {
+                Ok(removed) => Response::Integer(removed as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::HGetAll => {
+            if key.is_empty() {
+                return Response::Error("HGETALL requires key".into());
+            }
+            match store.hgetall(key) {
+                Ok(fields) => Response::Values(
+                    fields
+                        .into_iter()
+                        .flat_map(|(f, v)| [Bytes::from(f), v])
+                        .collect(),
+                ),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::LPush | Command::RPush => {
+            if key.is_empty() {
+                return Response::Error(format!("{} requires key", cmd.name().to_ascii_uppercase()));
+            }
+            let end = if cmd == Command::LPush { End::Left } else { End::Right };
+            match store.push(key, end, Bytes::copy_from_slice(value.as_bytes())) {
+                Ok(len) => Response::Integer(len as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::LPop | Command::RPop => {
+            if key.is_empty() {
+                return Response::Error(format!("{} requires key", cmd.name().to_ascii_uppercase()));
+            }
+            let end = if cmd == Command::LPop { End::Left } else { End::Right };
+            match store.pop(key, end) {
+                Ok(Some(v)) => Response::Value(v),
+                Ok(None) => Response::NotFound,
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::LRange => {
+            let mut bounds = value.split_whitespace().map(|p| p.parse::<i64>());
+            let (start, stop) = match (bounds.next(), bounds.next(), bounds.next()) {
+                (Some(Ok(start)), Some(Ok(stop)), None) if !key.is_empty() => (start, stop),
+                _ => return Response::Error("LRANGE expects <key> <start> <stop>".into()),
+            };
+            match store.lrange(key, start, stop) {
+                Ok(items) => Response::Values(items),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::SAdd | Command::SRem => {
+            let members: Vec<&str> = value.split_whitespace().collect();
+            if key.is_empty() || members.is_empty() {
+                return Response::Error(format!(
+                    "{} requires key and member",
+                    cmd.name().to_ascii_uppercase()
+                ));
+            }
+            let result = if cmd == Command::SAdd {
+                store.sadd(key, &members)
+            } else {
+                store.srem(key, &members)
+            };
+            match result {
+                Ok(n) => Response::Integer(n as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::SMembers => {
+            if key.is_empty() {
+                return Response::Error("SMEMBERS requires key".into());
+            }
+            match store.smembers(key) {
+                Ok(members) => Response::Values(members.into_iter().map(Bytes::from).collect()),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::SIsMember => {
+            if key.is_empty() || value.is_empty() {
+                return Response::Error("SISMEMBER requires key and member".into());
+            }
+            match store.sismember(key, value) {
+                Ok(found) => Response::Integer(found as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::ZAdd => {
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            let entries: Option<Vec<(f64, &str)>> = parts
+                .chunks(2)
+                .map(|pair| match pair {
+                    [score, member] => parse_score(score).map(|score| (score, *member)),
+                    _ => None,
+                })
+                .collect();
+            let entries = match entries {
+                Some(entries) if !key.is_empty() && !entries.is_empty() => entries,
+                _ => return Response::Error("ZADD expects <key> <score> <member>...".into()),
+            };
+            match store.zadd(key, &entries) {
+                Ok(added) => Response::Integer(added as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::ZIncrBy => {
+            let mut parts = value.split_whitespace();
+            let (delta, member) = match (parts.next().and_then(parse_score), parts.next(), parts.next()) {
+                (Some(delta), Some(member), None) if !key.is_empty() => (delta, member),
+                _ => return Response::Error("ZINCRBY expects <key> <delta> <member>".into()),
+            };
+            match store.zincrby(key, delta, member) {
+                Ok(score) => Response::Value(Bytes::from(score.to_string())),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::ZRem => {
+            let members: Vec<&str> = value.split_whitespace().collect();
+            if key.is_empty() || members.is_empty() {
+                return Response::Error("ZREM requires key and member".into());
+            }
+            match store.zrem(key, &members) {
+                Ok(removed) => Response::Integer(removed as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::ZRange | Command::ZRangeByScore => {
+            let mut parts = value.split_whitespace();
+            let (from, to) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let with_scores = match (parts.next(), parts.next()) {
+                (None, _) => false,
+                (Some(flag), None) if flag.eq_ignore_ascii_case("WITHSCORES") => true,
+                _ => return Response::Error(format!("{} has too many arguments", cmd.name().to_ascii_uppercase())),
+            };
+            let result = if cmd == Command::ZRange {
+                match (from.parse::<i64>(), to.parse::<i64>()) {
+                    (Ok(start), Ok(stop)) if !key.is_empty() => store.zrange(key, start, stop),
+                    _ => return Response::Error("ZRANGE expects <key> <start> <stop> [WITHSCORES]".into()),
+                }
+            } else {
+                match (parse_score_bound(from), parse_score_bound(to)) {
+                    (Some(min), Some(max)) if !key.is_empty() => store.zrangebyscore(key, min, max),
+                    _ => {
+                        return Response::Error(
+                            "ZRANGEBYSCORE expects <key> <min> <max> [WITHSCORES]".into(),
+                        )
+                    }
+                }
+            };
+            match result {
+                Ok(members) => scored_members(members, with_scores),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::RateLimit => {
+            let mut parts = value.split_whitespace();
+            let max = parts.next().and_then(|p| p.parse::<u32>().ok());
+            let window_ms = parts.next().and_then(|p| p.parse::<u64>().ok());
+            let cost = parts.next().map_or(Some(1), |p| p.parse::<u32>().ok());
+            let (max, window_ms, cost) = match (max, window_ms, cost, parts.next()) {
+                (Some(max), Some(window_ms), Some(cost), None) if !key.is_empty() => (max, window_ms, cost),
+                _ => return Response::Error("RATELIMIT expects <key> <max> <window_ms> [cost]".into()),
+            };
+            match store.rate_limit(key, max, Duration::from_millis(window_ms), cost) {
+                Ok(status) => {
+                    // Rounded up, so waiting `retry_after_ms` is always enough.
+                    let ms = |d: Duration| d.as_nanos().div_ceil(1_000_000).to_string();
+                    Response::Info(vec![
+                        ("allowed".into(), (status.allowed as u8).to_string()),
+                        ("remaining".into(), status.remaining.to_string()),
+                        ("retry_after_ms".into(), ms(status.retry_after)),
+                        ("reset_after_ms".into(), ms(status.reset_after)),
+                    ])
+                }
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::Lock => {
+            let mut parts = value.split_whitespace();
+            let ttl_ms = parts.next().and_then(|p| p.parse::<u64>().ok());
+            let (ttl_ms, owner) = match (ttl_ms, parts.next(), parts.next()) {
+                (Some(ttl_ms), owner, None) if !key.is_empty() => (ttl_ms, owner),
+                _ => return Response::Error("LOCK expects <key> <ttl_ms> [owner]".into()),
+            };
+            match store.lock(key, owner, Duration::from_millis(ttl_ms)) {
+                Ok(token) => Response::Integer(token.unwrap_or(0) as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::Extend => {
+            let mut parts = value.split_whitespace();
+            let ttl_ms = parts.next().and_then(|p| p.parse::<u64>().ok());
+            let (ttl_ms, owner) = match (ttl_ms, parts.next(), parts.next()) {
+                (Some(ttl_ms), Some(owner), None) if !key.is_empty() => (ttl_ms, owner),
+                _ => return Response::Error("EXTEND expects <key> <ttl_ms> <owner>".into()),
+            };
+            match store.extend_lock(key, owner, Duration::from_millis(ttl_ms)) {
+                Ok(token) => Response::Integer(token.unwrap_or(0) as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::Unlock => {
+            if key.is_empty() || value.is_empty() || value.contains(char::is_whitespace) {
+                return Response::Error("UNLOCK expects <key> <owner>".into());
+            }
+            match store.unlock(key, value) {
+                Ok(released) => Response::Integer(released as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::PfAdd => {
+            let elements: Vec<&[u8]> = value.split_whitespace().map(str::as_bytes).collect();
+            if key.is_empty() || elements.is_empty() {
+                return Response::Error("PFADD requires key and element".into());
+            }
+            match store.pfadd(key, &elements) {
+                Ok(changed) => Response::Integer(changed as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::PfCount => {
+            if key.is_empty() {
+                return Response::Error("PFCOUNT requires key".into());
+            }
+            let keys: Vec<&str> = std::iter::once(key).chain(value.split_whitespace()).collect();
+            match store.pfcount(&keys) {
+                Ok(count) => Response::Integer(count as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::PfMerge => {
+            if key.is_empty() {
+                return Response::Error("PFMERGE requires destination key".into());
+            }
+            let sources: Vec<&str> = value.split_whitespace().collect();
+            match store.pfmerge(key, &sources) {
+                Ok(()) => Response::Ok,
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::BfReserve => {
+            let mut parts = value.split_whitespace();
+            let error_rate = parts.next().and_then(|p| p.parse::<f64>().ok());
+            let capacity = parts.next().and_then(|p| p.parse::<u64>().ok());
+            let (error_rate, capacity) = match (error_rate, capacity, parts.next()) {
+                (Some(error_rate), Some(capacity), None) if !key.is_empty() => (error_rate, capacity),
+                _ => return Response::Error("BF.RESERVE expects <key> <error_rate> <capacity>".into()),
+            };
+            match store.bf_reserve(key, capacity, error_rate) {
+                Ok(created) => Response::Integer(created as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::BfAdd | Command::BfExists => {
+            if key.is_empty() || value.is_empty() {
+                return Response::Error(format!(
+                    "{} requires key and item",
+                    cmd.name().to_ascii_uppercase()
+                ));
+            }
+            let result = if cmd == Command::BfAdd {
+                store.bf_add(key, value.as_bytes())
+            } else {
+                store.bf_exists(key, value.as_bytes())
+            };
+            match result {
+                Ok(found) => Response::Integer(found as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::XAdd => {
+            let usage = || Response::Error("XADD expects <key> [MAXLEN <n>] <id|*> <field> <value>...".into());
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            let (max_len, rest) = match parts.as_slice() {
+                [flag, n, rest @ ..] if flag.eq_ignore_ascii_case("MAXLEN") => match n.parse::<usize>() {
+                    Ok(n) => (Some(n), rest),
+                    Err(_) => return usage(),
+                },
+                rest => (None, rest),
+            };
+            let (id, pairs) = match rest {
+                [id, pairs @ ..] if !key.is_empty() && !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+                    (*id, pairs)
+                }
+                _ => return usage(),
+            };
+            let id = match id {
+                "*" => None,
+                id => match id.parse::<StreamId>() {
+                    Ok(id) => Some(id),
+                    Err(e) => return Response::Error(e.to_string()),
+                },
+            };
+            let fields = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].to_owned(), Bytes::copy_from_slice(pair[1].as_bytes())))
+                .collect();
+            match store.xadd(key, id, fields, max_len) {
+                Ok(id) => Response::Value(Bytes::from(id.to_string())),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::XLen => {
+            if key.is_empty() {
+                return Response::Error("XLEN requires key".into());
+            }
+            match store.xlen(key) {
+                Ok(n) => Response::Integer(n as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::XRange => {
+            let mut parts = value.split_whitespace();
+            let start = parts.next().and_then(|s| parse_stream_bound(s, false));
+            let end = parts.next().and_then(|s| parse_stream_bound(s, true));
+            let (start, end, count) = match (start, end, stream_read_options(parts)) {
+                (Some(start), Some(end), Some((count, None))) if !key.is_empty() => (start, end, count),
+                _ => return Response::Error("XRANGE expects <key> <start> <end> [COUNT <n>]".into()),
+            };
+            match store.xrange(key, start, end, count) {
+                Ok(entries) => stream_entries(entries),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::XTrim => {
+            let mut parts = value.split_whitespace();
+            let max_len = match (parts.next(), parts.next(), parts.next()) {
+                (Some(flag), Some(n), None) if !key.is_empty() && flag.eq_ignore_ascii_case("MAXLEN") => {
+                    n.parse::<usize>().ok()
+                }
+                _ => None,
+            };
+            let Some(max_len) = max_len else {
+                return Response::Error("XTRIM expects <key> MAXLEN <n>".into());
+            };
+            match store.xtrim(key, max_len) {
+                Ok(removed) => Response::Integer(removed as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::XGroup => {
+            let parts: Vec<&str> = value.split_whitespace().collect();
+            let result = match parts.as_slice() {
+                [sub, group, id] if !key.is_empty() && sub.eq_ignore_ascii_case("CREATE") => {
+                    let start = match *id {
+                        "$" => None,
+                        id => match id.parse::<StreamId>() {
+                            Ok(id) => Some(id),
+                            Err(e) => return Response::Error(e.to_string()),
+                        },
+                    };
+                    store.xgroup_create(key, group, start).map(|created| {
+                        if created {
+                            Response::Ok
+                        } else {
+                            Response::Error(format!("consumer group '{}' already exists", group))
+                        }
+                    })
+                }
+                [sub, group] if !key.is_empty() && sub.eq_ignore_ascii_case("DESTROY") => store
+                    .xgroup_destroy(key, group)
+                    .map(|destroyed| Response::Integer(destroyed as i64)),
+                _ => {
+                    return Response::Error(
+                        "XGROUP expects <key> CREATE <group> <id|$> or <key> DESTROY <group>".into(),
+                    )
+                }
+            };
+            result.unwrap_or_else(|e| Response::Error(e.to_string()))
+        }
+        Command::XAck => {
+            let mut parts = value.split_whitespace();
+            let group = parts.next().filter(|_| !key.is_empty());
+            let ids: Result<Vec<StreamId>, BlinkError> = parts.map(str::parse).collect();
+            let (group, ids) = match (group, ids) {
+                (Some(group), Ok(ids)) if !ids.is_empty() => (group, ids),
+                (_, Err(e)) => return Response::Error(e.to_string()),
+                _ => return Response::Error("XACK expects <key> <group> <id>...".into()),
+            };
+            match store.xack(key, group, &ids) {
+                Ok(acked) => Response::Integer(acked as i64),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Command::XPending => {
+            if key.is_empty() || value.is_empty() || value.contains(char::is_whitespace) {
+                return Response::Error("XPENDING expects <key> <group>".into());
+            }
+            match store.xpending(key, value) {
+                Ok(pending) => Response::Values(
+                    pending
+                        .into_iter()
+                        .flat_map(|p| {
+                            [
+                                Bytes::from(p.id.to_string()),
+                                Bytes::from(p.consumer),
+                                Bytes::from(p.idle.as_millis().to_string()),
+                                Bytes::from(p.deliveries.to_string()),
+                            ]
+                        })
+                        .collect(),
+                ),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
         Command::Usage => match store.current_usage_bytes() {
             Ok(n) => Response::Usage(n),
             Err(e) => Response::Error(e.to_string()),
         },
-        Command::Quit => unreachable!(),
+        Command::GetLock
+        | Command::Wait
+        | Command::BLPop
+        | Command::XRead
+        | Command::XReadGroup
+        | Command::Watch
+        | Command::Multi
+        | Command::Exec
+        | Command::Discard
+        | Command::Subscribe
+        | Command::PSubscribe
+        | Command::Unsubscribe
+        | Command::Publish
+        | Command::WatchEvents
+        | Command::Select
+        | Command::Info
+        | Command::Quota
+        | Command::Config
+        | Command::Auth
+        | Command::Acl
+        | Command::Ping
+        | Command::Quit => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine() -> Arc<MemoryEngine> {
+        Arc::new(MemoryEngine::new(1024).unwrap())
+    }
+
+    #[test]
+    fn find_database_by_name_or_index() {
+        let state = ServerState::new(engine())
+            .with_database("staging", engine())
+            .unwrap();
+        assert_eq!(state.find_database("0"), Some(0));
+        assert_eq!(state.find_database("staging"), Some(1));
+        assert_eq!(state.find_database("1"), Some(1));
+        assert_eq!(state.find_database("2"), None);
+        assert!(state.with_database("staging", engine()).is_err());
+    }
+
+    #[tokio::test]
+    async fn select_isolates_keys() {
+        let state = ServerState::new(engine())
+            .with_database("staging", engine())
+            .unwrap();
+        let mut session = Session::new(&state);
+        dispatch(Command::Set, "k", "v", &state, &mut session).await;
+        assert!(matches!(
+            dispatch(Command::Select, "", "staging", &state, &mut session).await,
+            Response::Ok
+        ));
+        assert!(matches!(
+            dispatch(Command::Get, "k", "", &state, &mut session).await,
+            Response::NotFound
+        ));
+        match dispatch(Command::Usage, "", "ALL", &state, &mut session).await {
+            Response::Info(fields) => {
+                assert_eq!(fields.last().unwrap(), &("total".to_owned(), "2".to_owned()))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match dispatch(Command::Info, "", "", &state, &mut session).await {
+            Response::Info(fields) => {
+                assert!(fields.contains(&("db".to_owned(), "staging".to_owned())));
+                assert!(fields.contains(&("keys".to_owned(), "0".to_owned())));
+                assert!(fields.contains(&("databases".to_owned(), "2".to_owned())));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn auth_failures_and_missing_permissions_are_refused() {
+        // sha256("secret")
+        let acl = Acl::from_rules(
+            "user ops on #2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b +get ~a:*\n",
+        )
+        .unwrap();
+        let state = ServerState::new(engine()).with_acl(acl);
+        let mut session = Session::new(&state);
+        assert!(matches!(
+            dispatch(Command::Get, "a:1", "", &state, &mut session).await,
+            Response::Error(msg) if msg == "authentication required"
+        ));
+        assert!(matches!(
+            dispatch(Command::Auth, "ops", "wrong", &state, &mut session).await,
+            Response::Error(msg) if msg == "invalid username or password"
+        ));
+        assert!(matches!(
+            dispatch(Command::Get, "a:1", "", &state, &mut session).await,
+            Response::Error(msg) if msg == "authentication required"
+        ));
+        assert!(matches!(
+            dispatch(Command::Auth, "ops", "secret", &state, &mut session).await,
+            Response::Ok
+        ));
+        assert!(matches!(
+            dispatch(Command::Get, "a:1", "", &state, &mut session).await,
+            Response::NotFound
+        ));
+        for (cmd, key) in [(Command::Set, "a:1"), (Command::Get, "b:1")] {
+            assert!(matches!(
+                dispatch(cmd, key, "v", &state, &mut session).await,
+                Response::Error(msg) if msg.starts_with("permission denied")
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn key_commands_check_every_key_against_acl() {
+        let acl = Acl::from_rules("user default on nopass +@all ~a:*\n").unwrap();
+        let state = ServerState::new(engine()).with_acl(acl);
+        let mut session = Session::new(&state);
+        dispatch(Command::Set, "a:1", "v", &state, &mut session).await;
+        for (cmd, key, value) in [
+            (Command::Rename, "a:1", "b:1"),
+            (Command::Exists, "a:1", "b:1"),
+            (Command::Touch, "a:1", "a:2 b:1"),
+            (Command::PfCount, "a:1", "b:1"),
+            (Command::PfMerge, "a:hll", "a:1 b:1"),
+        ] {
+            assert!(matches!(
+                dispatch(cmd, key, value, &state, &mut session).await,
+                Response::Error(msg) if msg.contains("b:1")
+            ));
+        }
+        assert!(matches!(
+            dispatch(Command::Rename, "a:1", "a:2", &state, &mut session).await,
+            Response::Ok
+        ));
+        assert!(matches!(
+            dispatch(Command::Exists, "a:1", "a:2 a:2", &state, &mut session).await,
+            Response::Integer(2)
+        ));
+        assert!(matches!(
+            dispatch(Command::Type, "a:2", "", &state, &mut session).await,
+            Response::Value(kind) if kind == "string"
+        ));
+        assert!(matches!(
+            dispatch(Command::DbSize, "", "", &state, &mut session).await,
+            Response::Integer(1)
+        ));
+        assert!(matches!(
+            dispatch(Command::Rename, "a:1", "a:3", &state, &mut session).await,
+            Response::Error(msg) if msg.contains("not found")
+        ));
+    }
+
+    #[tokio::test]
+    async fn config_set_and_get() {
+        let state = ServerState::new(engine());
+        let mut session = Session::new(&state);
+        assert!(matches!(
+            dispatch(Command::Config, "", "SET memory_limit 2048", &state, &mut session).await,
+            Response::Ok
+        ));
+        assert!(matches!(
+            dispatch(Command::Config, "", "SET idle_timeout_secs 30", &state, &mut session).await,
+            Response::Ok
+        ));
+        assert_eq!(state.store().limit_bytes(), 2048);
+        assert_eq!(state.idle_timeout(), Duration::from_secs(30));
+        match dispatch(Command::Config, "", "GET eviction_*", &state, &mut session).await {
+            Response::Info(fields) => assert_eq!(
+                fields,
+                vec![
+                    ("eviction_policy".to_owned(), "allkeys-lru".to_owned()),
+                    ("eviction_samples".to_owned(), "5".to_owned()),
+                ]
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            dispatch(Command::Config, "", "SET bogus 1", &state, &mut session).await,
+            Response::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn getlock_grants_one_lease_and_waiters_get_the_value() {
+        let state = Arc::new(ServerState::new(engine()));
+        let mut session = Session::new(&state);
+        assert_eq!(
+            dispatch(Command::GetLock, "k", "", &state, &mut session).await,
+            Response::Lease(config::DEFAULT_LEASE_TIMEOUT_MS)
+        );
+        let waiter = {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let mut session = Session::new(&state);
+                dispatch(Command::GetLock, "k", "100", &state, &mut session).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        dispatch(Command::Set, "k", "fresh", &state, &mut session).await;
+        assert_eq!(
+            waiter.await.unwrap(),
+            Response::Value(Bytes::from_static(b"fresh"))
+        );
+        assert!(matches!(
+            dispatch(Command::GetLock, "k", "0", &state, &mut session).await,
+            Response::Error(_)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn setex_reports_stale_then_expires() {
+        let state = ServerState::new(engine());
+        let mut session = Session::new(&state);
+        assert_eq!(
+            dispatch(Command::SetEx, "k", "100 200 some value", &state, &mut session).await,
+            Response::Ok
+        );
+        tokio::time::advance(Duration::from_millis(150)).await;
+        assert_eq!(
+            dispatch(Command::Get, "k", "", &state, &mut session).await,
+            Response::Stale(Bytes::from_static(b"some value"))
+        );
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(
+            dispatch(Command::Get, "k", "", &state, &mut session).await,
+            Response::NotFound
+        );
+        assert!(matches!(
+            dispatch(Command::SetEx, "k", "soon v", &state, &mut session).await,
+            Response::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn watchevents_pushes_matching_key_events() {
+        let state = Arc::new(ServerState::new(engine()));
+        let (client, server) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(serve_stream(server_read, server_write, state.clone()));
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = AsyncBufReader::new(client_read).lines();
+
+        client_write.write_all(b"WATCHEVENTS user:*\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "OK");
+        state.store().set("other", Bytes::from_static(b"x")).unwrap();
+        state.store().set("user:1", Bytes::from_static(b"x")).unwrap();
+        state.store().delete("user:1").unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "EVENT set user:1");
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "EVENT delete user:1");
+
+        client_write.write_all(b"GET user:1\n").await.unwrap();
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("ERROR"));
+    }
+
+    #[tokio::test]
+    async fn wait_blocks_until_change_and_cleans_up_on_disconnect() {
+        let state = Arc::new(ServerState::new(engine()));
+        let (client, server) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server);
+        let conn = tokio::spawn(serve_stream(server_read, server_write, state.clone()));
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = AsyncBufReader::new(client_read).lines();
+
+        client_write.write_all(b"WAIT k 0 0\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "VERSIONED 0");
+
+        // A request pipelined behind WAIT is answered after it.
+        client_write.write_all(b"WAIT k 0 10000\nPING\n").await.unwrap();
+        tokio::task::yield_now().await;
+        state.store().set("k", Bytes::from_static(b"v")).unwrap();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        let version = state.store().get_versioned("k").unwrap().version;
+        assert_eq!(reply, format!("VERSIONED {} dg==", version));
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "PONG");
+
+        client_write
+            .write_all(format!("WAIT k {} 60000\n", version).as_bytes())
+            .await
+            .unwrap();
+        while state.store().watched_keys() == 0 {
+            tokio::task::yield_now().await;
+        }
+        drop(client_write);
+        drop(lines);
+        conn.await.unwrap().unwrap();
+        assert_eq!(state.store().watched_keys(), 0);
+    }
+
+    #[tokio::test]
+    async fn multi_exec_is_all_or_nothing_and_honours_watch() {
+        let state = ServerState::new(engine());
+        let mut session = Session::new(&state);
+        let mut other = Session::new(&state);
+
+        assert_eq!(dispatch(Command::Multi, "", "", &state, &mut session).await, Response::Ok);
+        assert_eq!(
+            dispatch(Command::Set, "user:1", "alice", &state, &mut session).await,
+            Response::Queued
+        );
+        dispatch(Command::Set, "by-name:alice", "1", &state, &mut session).await;
+        assert_eq!(state.store().get("user:1").unwrap(), None);
+        assert_eq!(
+            dispatch(Command::Exec, "", "", &state, &mut session).await,
+            Response::Exec(vec![Response::Ok, Response::Ok])
+        );
+        assert!(state.store().get("by-name:alice").unwrap().is_some());
+
+        // A failing command undoes the ones before it.
+        dispatch(Command::Multi, "", "", &state, &mut session).await;
+        dispatch(Command::Delete, "user:1", "", &state, &mut session).await;
+        dispatch(Command::SetEx, "user:1", "bad", &state, &mut session).await;
+        assert!(matches!(
+            dispatch(Command::Exec, "", "", &state, &mut session).await,
+            Response::Error(msg) if msg.starts_with("EXECABORT")
+        ));
+        assert!(state.store().get("user:1").unwrap().is_some());
+
+        // Commands that cannot be queued fail EXEC outright.
+        dispatch(Command::Multi, "", "", &state, &mut session).await;
+        assert!(matches!(
+            dispatch(Command::Select, "", "0", &state, &mut session).await,
+            Response::Error(_)
+        ));
+        assert!(matches!(
+            dispatch(Command::Exec, "", "", &state, &mut session).await,
+            Response::Error(_)
+        ));
+
+        dispatch(Command::Watch, "", "user:1", &state, &mut session).await;
+        dispatch(Command::Multi, "", "", &state, &mut session).await;
+        dispatch(Command::Delete, "user:1", "", &state, &mut session).await;
+        dispatch(Command::Set, "user:1", "bob", &state, &mut other).await;
+        assert_eq!(
+            dispatch(Command::Exec, "", "", &state, &mut session).await,
+            Response::Aborted
+        );
+        assert_eq!(&state.store().get("user:1").unwrap().unwrap()[..], b"bob");
+    }
+
+    #[tokio::test]
+    async fn blpop_parks_connection_until_push() {
+        let state = Arc::new(ServerState::new(engine()));
+        let (client, server) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(serve_stream(server_read, server_write, state.clone()));
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = AsyncBufReader::new(client_read).lines();
+
+        client_write.write_all(b"BLPOP jobs 0\n").await.unwrap();
+        while state.store().watched_keys() == 0 {
+            tokio::task::yield_now().await;
+        }
+        client_write.write_all(b"RPUSH jobs job 1\n").await.unwrap();
+        let mut other = Session::new(&state);
+        dispatch(Command::RPush, "jobs", "job 2", &state, &mut other).await;
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "VALUE am9iIDI=");
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "INTEGER 1");
+
+        client_write.write_all(b"BLPOP jobs 10\nLRANGE jobs 0 -1\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "VALUE am9iIDE=");
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "VALUES 0");
+    }
+
+    #[tokio::test]
+    async fn stream_commands_read_and_park_consumers() {
+        let state = Arc::new(ServerState::new(engine()));
+        let mut session = Session::new(&state);
+        assert!(matches!(
+            dispatch(Command::XAdd, "events", "1-1 kind signup", &state, &mut session).await,
+            Response::Value(id) if id == "1-1"
+        ));
+        for bad in ["1-1 kind login", "* kind", "MAXLEN x * kind login"] {
+            assert!(matches!(
+                dispatch(Command::XAdd, "events", bad, &state, &mut session).await,
+                Response::Error(_)
+            ));
+        }
+        dispatch(Command::XAdd, "events", "MAXLEN 5 2 kind login", &state, &mut session).await;
+        match dispatch(Command::XRange, "events", "- 1 COUNT 5", &state, &mut session).await {
+            Response::Values(values) => assert_eq!(values, ["1-1", "1", "kind", "signup"]),
+            other => panic!("unexpected {:?}", other),
+        }
+        // COUNT 0 means no limit.
+        match dispatch(Command::XRange, "events", "- + COUNT 0", &state, &mut session).await {
+            Response::Values(values) => assert_eq!(values.len(), 8),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            dispatch(Command::XGroup, "events", "CREATE mailers $", &state, &mut session).await,
+            Response::Ok
+        ));
+        assert!(matches!(
+            dispatch(Command::XGroup, "events", "CREATE mailers 0", &state, &mut session).await,
+            Response::Error(_)
+        ));
+
+        let (client, server) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(serve_stream(server_read, server_write, state.clone()));
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = AsyncBufReader::new(client_read).lines();
+        client_write
+            .write_all(b"XREADGROUP events mailers m1 > BLOCK 0\n")
+            .await
+            .unwrap();
+        while state.store().watched_keys() == 0 {
+            tokio::task::yield_now().await;
+        }
+        dispatch(Command::XAdd, "events", "3-0 kind welcome", &state, &mut session).await;
+        // VALUES 4 3-0 1 kind welcome, base64-encoded
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "VALUES 4 My0w MQ== a2luZA== d2VsY29tZQ=="
+        );
+
+        match dispatch(Command::XPending, "events", "mailers", &state, &mut session).await {
+            Response::Values(values) => {
+                assert_eq!(values.len(), 4);
+                assert_eq!(values[..2], ["3-0", "m1"]);
+                assert_eq!(values[3], "1");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            dispatch(Command::XAck, "events", "mailers 3-0 9-9", &state, &mut session).await,
+            Response::Integer(1)
+        ));
+        assert!(matches!(
+            dispatch(Command::XReadGroup, "events", "nobody m1 >", &state, &mut session).await,
+            Response::Error(msg) if msg.starts_with("NOGROUP")
+        ));
+        assert!(matches!(
+            dispatch(Command::XTrim, "events", "MAXLEN 1", &state, &mut session).await,
+            Response::Integer(2)
+        ));
+        assert!(matches!(
+            dispatch(Command::XLen, "events", "", &state, &mut session).await,
+            Response::Integer(1)
+        ));
     }
 }
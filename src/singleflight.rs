@@ -0,0 +1,117 @@
+//! Coalesces concurrent loads of the same key into one call.
+
+use crate::error::BlinkError;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Result of one in-flight load, shared by every caller waiting on it.
+type Flight = Arc<OnceCell<Result<Bytes, BlinkError>>>;
+
+/// Per-key single-flight groups: while a load for a key is running, further
+/// callers for that key wait for its result instead of starting their own.
+#[derive(Default)]
+pub struct SingleFlight {
+    inflight: Mutex<HashMap<String, Flight>>,
+}
+
+impl SingleFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `load` unless a load for `key` is already running, in which case
+    /// its result is returned instead. If the running caller is cancelled, a
+    /// waiting caller runs its own `load`. Results, including errors, are
+    /// shared only with callers that were waiting; nothing is remembered.
+    pub async fn run<F, Fut>(&self, key: &str, load: F) -> Result<Bytes, BlinkError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Bytes, BlinkError>>,
+    {
+        let flight = self
+            .lock()
+            .entry(key.to_owned())
+            .or_default()
+            .clone();
+        let result = flight.get_or_init(load).await.clone();
+
+        let mut inflight = self.lock();
+        if inflight.get(key).is_some_and(|f| Arc::ptr_eq(f, &flight)) {
+            inflight.remove(key);
+        }
+        result
+    }
+
+    /// Number of keys with a load in progress.
+    pub fn in_flight(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Flight>> {
+        self.inflight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_load() {
+        let group = Arc::new(SingleFlight::new());
+        let loads = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let group = group.clone();
+                let loads = loads.clone();
+                tokio::spawn(async move {
+                    group
+                        .run("k", || async move {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok(Bytes::from_static(b"v"))
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(&task.await.unwrap().unwrap()[..], b"v");
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(group.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_leader_hands_over() {
+        let group = Arc::new(SingleFlight::new());
+        let leader = {
+            let group = group.clone();
+            tokio::spawn(async move {
+                group
+                    .run("k", || async {
+                        std::future::pending::<()>().await;
+                        unreachable!()
+                    })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let follower = {
+            let group = group.clone();
+            tokio::spawn(async move {
+                group
+                    .run("k", || async { Ok(Bytes::from_static(b"mine")) })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        leader.abort();
+        assert_eq!(&follower.await.unwrap().unwrap()[..], b"mine");
+    }
+}
@@ -0,0 +1,198 @@
+//! Fixed-size probabilistic sets: HyperLogLog for counting distinct items
+//! and Bloom filters for membership.
+//!
+//! Both hash items with the standard library's SipHash under fixed keys, so
+//! results are stable within a process but not across Rust releases.
+
+use crate::error::BlinkError;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+fn hash64(item: &[u8], seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Bits of the hash that pick a register.
+const HLL_PRECISION: u32 = 14;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// Distinct-count estimator with a standard error of about 0.8%.
+#[derive(Clone)]
+pub struct HyperLogLog {
+    /// Per register, the longest run of leading zeros seen, plus one.
+    registers: Box<[u8]>,
+}
+
+impl HyperLogLog {
+    /// Bytes used by every HyperLogLog, whatever it has counted.
+    pub const SIZE: usize = HLL_REGISTERS;
+
+    /// Adds `item`; returns true if the estimate may have changed.
+    pub fn add(&mut self, item: &[u8]) -> bool {
+        let hash = hash64(item, 0);
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        // The guard bit bounds the rank when the remaining bits are all zero.
+        let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Folds `other` in, so this counts the union of both; returns true if
+    /// anything changed.
+    pub fn merge(&mut self, other: &HyperLogLog) -> bool {
+        let mut changed = false;
+        for (mine, theirs) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if theirs > mine {
+                *mine = *theirs;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Estimated number of distinct items added.
+    pub fn count(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let (sum, zeros) = self.registers.iter().fold((0.0, 0u32), |(sum, zeros), &r| {
+            (sum + 2f64.powi(-(r as i32)), zeros + (r == 0) as u32)
+        });
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let estimate = alpha * m * m / sum;
+        // Small cardinalities are estimated better by counting empty registers.
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; HLL_REGISTERS].into_boxed_slice(),
+        }
+    }
+}
+
+/// Capacity of a Bloom filter created by adding to a missing key.
+pub const DEFAULT_BLOOM_CAPACITY: u64 = 100;
+/// False-positive rate of a Bloom filter created by adding to a missing key.
+pub const DEFAULT_BLOOM_ERROR_RATE: f64 = 0.01;
+
+/// Membership filter: answers "maybe present" or "definitely absent". Past
+/// its capacity it keeps working, but false positives become more likely.
+#[derive(Clone)]
+pub struct BloomFilter {
+    bits: Box<[u64]>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Bytes a filter for `capacity` items at `error_rate` false positives
+    /// will use, so callers can check it fits before allocating.
+    pub fn size_for(capacity: u64, error_rate: f64) -> Result<u64, BlinkError> {
+        if capacity == 0 || !(error_rate > 0.0 && error_rate < 1.0) {
+            return Err(BlinkError::Config(
+                "bloom filter needs a positive capacity and an error rate between 0 and 1".into(),
+            ));
+        }
+        let bits = (-(capacity as f64) * error_rate.ln() / std::f64::consts::LN_2.powi(2)).ceil();
+        Ok((bits as u64).div_ceil(64).saturating_mul(8))
+    }
+
+    pub fn new(capacity: u64, error_rate: f64) -> Result<Self, BlinkError> {
+        let words = (Self::size_for(capacity, error_rate)? / 8) as usize;
+        let hashes = ((words * 64) as f64 / capacity as f64 * std::f64::consts::LN_2).round();
+        Ok(Self {
+            bits: vec![0; words].into_boxed_slice(),
+            hashes: hashes.clamp(1.0, 32.0) as u32,
+        })
+    }
+
+    /// Bytes used by the bit array.
+    pub fn size(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    /// Adds `item`; returns true if it was definitely not present before.
+    pub fn add(&mut self, item: &[u8]) -> bool {
+        let mut added = false;
+        for bit in self.positions(item) {
+            let (word, mask) = (bit / 64, 1 << (bit % 64));
+            added |= self.bits[word] & mask == 0;
+            self.bits[word] |= mask;
+        }
+        added
+    }
+
+    /// False means `item` was never added; true means it probably was.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.positions(item)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Bit positions for `item`, by double hashing.
+    fn positions(&self, item: &[u8]) -> impl Iterator<Item = usize> {
+        let (h1, h2) = (hash64(item, 0), hash64(item, 1) | 1);
+        let len = (self.bits.len() * 64) as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_BLOOM_CAPACITY, DEFAULT_BLOOM_ERROR_RATE)
+            .unwrap_or_else(|_| unreachable!())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hyperloglog_estimates_within_a_few_percent() {
+        let mut hll = HyperLogLog::default();
+        assert_eq!(hll.count(), 0);
+        for i in 0..100_000u32 {
+            hll.add(&i.to_le_bytes());
+        }
+        assert!(!hll.add(&7u32.to_le_bytes()));
+        let count = hll.count() as f64;
+        assert!((count - 100_000.0).abs() / 100_000.0 < 0.03, "estimate {}", count);
+
+        let mut other = HyperLogLog::default();
+        for i in 50_000..150_000u32 {
+            other.add(&i.to_le_bytes());
+        }
+        assert!(hll.merge(&other));
+        let union = hll.count() as f64;
+        assert!((union - 150_000.0).abs() / 150_000.0 < 0.03, "estimate {}", union);
+    }
+
+    #[test]
+    fn bloom_filter_has_no_false_negatives_and_few_false_positives() {
+        let mut filter = BloomFilter::new(1_000, 0.01).unwrap();
+        assert_eq!(filter.size(), BloomFilter::size_for(1_000, 0.01).unwrap() as usize);
+        for i in 0..1_000u32 {
+            assert!(filter.add(&i.to_le_bytes()));
+        }
+        assert!(!filter.add(&5u32.to_le_bytes()));
+        assert!((0..1_000u32).all(|i| filter.contains(&i.to_le_bytes())));
+        let false_positives = (1_000..11_000u32)
+            .filter(|i| filter.contains(&i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+        assert!(BloomFilter::new(0, 0.01).is_err());
+        assert!(BloomFilter::new(10, 1.0).is_err());
+    }
+}
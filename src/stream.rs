@@ -0,0 +1,414 @@
+//! Append-only streams: entries with time-ordered IDs, read by ID range or
+//! through consumer groups that remember which entries each consumer has
+//! been handed and not yet acknowledged.
+
+use crate::error::BlinkError;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// ID of a stream entry: milliseconds since the Unix epoch, then a sequence
+/// number for entries added in the same millisecond. Written `<ms>-<seq>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = BlinkError;
+
+    /// Parses `<ms>-<seq>`, or `<ms>` for `<ms>-0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = s.split_once('-').unwrap_or((s, "0"));
+        match (ms.parse(), seq.parse()) {
+            (Ok(ms), Ok(seq)) => Ok(StreamId { ms, seq }),
+            _ => Err(BlinkError::Protocol(format!("invalid stream ID '{}'", s))),
+        }
+    }
+}
+
+/// One entry read from a stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: Vec<(String, Bytes)>,
+}
+
+/// An entry handed to a consumer of a group and not yet acknowledged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub id: StreamId,
+    pub consumer: String,
+    /// Time since the entry was last handed to `consumer`.
+    pub idle: Duration,
+    /// How many times the entry was handed out.
+    pub deliveries: u64,
+}
+
+/// Bytes an ID counts as, in entries, groups and pending lists.
+const ID_SIZE: u64 = 16;
+
+/// Bytes an entry with `fields` counts as.
+pub(crate) fn entry_size(fields: &[(String, Bytes)]) -> u64 {
+    ID_SIZE + fields.iter().map(|(f, v)| (f.len() + v.len()) as u64).sum::<u64>()
+}
+
+/// Bytes a consumer group with no pending entries counts as.
+pub(crate) fn group_size(name: &str) -> u64 {
+    name.len() as u64 + ID_SIZE
+}
+
+fn pending_size(consumer: &str) -> u64 {
+    ID_SIZE + consumer.len() as u64
+}
+
+/// The value of a stream key. An entry counts as its ID plus its field
+/// names and values; a group as its name plus an ID; a pending entry as its
+/// ID plus the consumer's name.
+#[derive(Clone, Default)]
+pub(crate) struct Stream {
+    entries: BTreeMap<StreamId, Vec<(String, Bytes)>>,
+    /// Largest ID ever added, even if trimmed since; new IDs must exceed it.
+    last_id: StreamId,
+    groups: HashMap<String, ConsumerGroup>,
+}
+
+#[derive(Clone)]
+struct ConsumerGroup {
+    /// Entries up to this ID have been handed to some consumer.
+    last_delivered: StreamId,
+    pending: BTreeMap<StreamId, Delivery>,
+}
+
+#[derive(Clone)]
+struct Delivery {
+    consumer: String,
+    delivered_at: Instant,
+    count: u64,
+}
+
+impl ConsumerGroup {
+    fn size(&self, name: &str) -> u64 {
+        group_size(name)
+            + self.pending.values().map(|d| pending_size(&d.consumer)).sum::<u64>()
+    }
+}
+
+impl Stream {
+    pub(crate) fn size(&self) -> u64 {
+        self.entries.values().map(|f| entry_size(f)).sum::<u64>()
+            + self.groups.iter().map(|(name, g)| g.size(name)).sum::<u64>()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// `requested`, if it is greater than every ID so far; otherwise a new
+    /// ID from the clock, kept increasing if the clock goes back.
+    pub(crate) fn next_id(&self, requested: Option<StreamId>) -> Result<StreamId, BlinkError> {
+        if let Some(id) = requested {
+            if id <= self.last_id {
+                return Err(BlinkError::StreamIdTooSmall(id.to_string()));
+            }
+            return Ok(id);
+        }
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        if now_ms > self.last_id.ms {
+            Ok(StreamId { ms: now_ms, seq: 0 })
+        } else if self.last_id.seq < u64::MAX {
+            Ok(StreamId {
+                ms: self.last_id.ms,
+                seq: self.last_id.seq + 1,
+            })
+        } else {
+            Err(BlinkError::StreamIdTooSmall(StreamId::MAX.to_string()))
+        }
+    }
+
+    /// Adds an entry under `id`, which must come from `next_id`; returns
+    /// the bytes added.
+    pub(crate) fn add(&mut self, id: StreamId, fields: Vec<(String, Bytes)>) -> u64 {
+        let added = entry_size(&fields);
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        added
+    }
+
+    /// Bytes the `n` oldest entries count as, excluding pending lists.
+    pub(crate) fn oldest_size(&self, n: usize) -> u64 {
+        self.entries.values().take(n).map(|f| entry_size(f)).sum()
+    }
+
+    /// Removes the oldest entries until at most `max_len` remain, along
+    /// with their places in pending lists; returns how many were removed
+    /// and the bytes freed.
+    pub(crate) fn trim(&mut self, max_len: usize) -> (usize, u64) {
+        let mut removed = 0;
+        let mut freed = 0;
+        while self.entries.len() > max_len {
+            let Some((id, fields)) = self.entries.pop_first() else {
+                break;
+            };
+            removed += 1;
+            freed += entry_size(&fields);
+            for group in self.groups.values_mut() {
+                if let Some(delivery) = group.pending.remove(&id) {
+                    freed += pending_size(&delivery.consumer);
+                }
+            }
+        }
+        (removed, freed)
+    }
+
+    /// Up to `count` entries with IDs within `start..end`, oldest first.
+    pub(crate) fn range(&self, start: Bound<StreamId>, end: Bound<StreamId>, count: usize) -> Vec<StreamEntry> {
+        // `BTreeMap::range` panics on a range that ends before it starts.
+        let empty = match (start, end) {
+            (Bound::Included(s), Bound::Included(e)) => s > e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => s >= e,
+            _ => false,
+        };
+        if empty {
+            return Vec::new();
+        }
+        self.entries
+            .range((start, end))
+            .take(count)
+            .map(|(id, fields)| StreamEntry {
+                id: *id,
+                fields: fields.clone(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn has_group(&self, group: &str) -> bool {
+        self.groups.contains_key(group)
+    }
+
+    /// Creates `group`, which will hand out entries after `start`; returns
+    /// the bytes added, or `None` if it exists.
+    pub(crate) fn create_group(&mut self, group: &str, start: StreamId) -> Option<u64> {
+        if self.groups.contains_key(group) {
+            return None;
+        }
+        let created = ConsumerGroup {
+            last_delivered: start,
+            pending: BTreeMap::new(),
+        };
+        let added = created.size(group);
+        self.groups.insert(group.to_owned(), created);
+        Some(added)
+    }
+
+    /// Removes `group` and its pending list; returns the bytes freed.
+    pub(crate) fn destroy_group(&mut self, group: &str) -> Option<u64> {
+        let removed = self.groups.remove(group)?;
+        Some(removed.size(group))
+    }
+
+    /// Whether `deliver` would return anything, without changing the group.
+    pub(crate) fn can_deliver(&self, group: &str, consumer: &str, after: Option<StreamId>) -> Result<bool, BlinkError> {
+        let group = self.group(group)?;
+        Ok(match after {
+            None => self
+                .entries
+                .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                .next()
+                .is_some(),
+            Some(after) => group
+                .pending
+                .range((Bound::Excluded(after), Bound::Unbounded))
+                .any(|(_, d)| d.consumer == consumer),
+        })
+    }
+
+    /// Bytes `deliver` of new entries to `consumer` would add.
+    pub(crate) fn deliver_cost(&self, group: &str, consumer: &str, count: usize) -> u64 {
+        let Ok(group) = self.group(group) else {
+            return 0;
+        };
+        let new = self
+            .entries
+            .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+            .take(count)
+            .count();
+        new as u64 * pending_size(consumer)
+    }
+
+    /// Hands up to `count` entries to `consumer` of `group`. Without
+    /// `after`, these are entries no consumer of the group has seen, which
+    /// join the pending list. With it, they are entries already pending for
+    /// `consumer` with IDs after `after`, handed out again. Returns the
+    /// entries and the bytes added.
+    pub(crate) fn deliver(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: usize,
+        now: Instant,
+    ) -> Result<(Vec<StreamEntry>, u64), BlinkError> {
+        let entries = &self.entries;
+        let group = self
+            .groups
+            .get_mut(group)
+            .ok_or_else(|| BlinkError::NoGroup(group.to_owned()))?;
+        let mut out = Vec::new();
+        let mut added = 0;
+        match after {
+            None => {
+                for (id, fields) in entries
+                    .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                    .take(count)
+                {
+                    let delivery = Delivery {
+                        consumer: consumer.to_owned(),
+                        delivered_at: now,
+                        count: 1,
+                    };
+                    group.pending.insert(*id, delivery);
+                    group.last_delivered = *id;
+                    added += pending_size(consumer);
+                    out.push(StreamEntry {
+                        id: *id,
+                        fields: fields.clone(),
+                    });
+                }
+            }
+            Some(after) => {
+                let pending = group
+                    .pending
+                    .range_mut((Bound::Excluded(after), Bound::Unbounded))
+                    .filter(|(_, d)| d.consumer == consumer)
+                    .take(count);
+                for (id, delivery) in pending {
+                    delivery.delivered_at = now;
+                    delivery.count += 1;
+                    if let Some(fields) = entries.get(id) {
+                        out.push(StreamEntry {
+                            id: *id,
+                            fields: fields.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok((out, added))
+    }
+
+    /// Removes `ids` from the pending list of `group`; returns how many
+    /// were pending and the bytes freed.
+    pub(crate) fn ack(&mut self, group: &str, ids: &[StreamId]) -> (usize, u64) {
+        let Some(group) = self.groups.get_mut(group) else {
+            return (0, 0);
+        };
+        ids.iter()
+            .filter_map(|id| group.pending.remove(id))
+            .fold((0, 0), |(n, freed), d| (n + 1, freed + pending_size(&d.consumer)))
+    }
+
+    /// The pending list of `group`, oldest ID first.
+    pub(crate) fn pending(&self, group: &str, now: Instant) -> Result<Vec<PendingEntry>, BlinkError> {
+        Ok(self
+            .group(group)?
+            .pending
+            .iter()
+            .map(|(id, d)| PendingEntry {
+                id: *id,
+                consumer: d.consumer.clone(),
+                idle: now.saturating_duration_since(d.delivered_at),
+                deliveries: d.count,
+            })
+            .collect())
+    }
+
+    fn group(&self, group: &str) -> Result<&ConsumerGroup, BlinkError> {
+        self.groups
+            .get(group)
+            .ok_or_else(|| BlinkError::NoGroup(group.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &'static str) -> Vec<(String, Bytes)> {
+        vec![("v".to_owned(), Bytes::from_static(value.as_bytes()))]
+    }
+
+    #[test]
+    fn ids_increase_and_parse() {
+        let mut stream = Stream::default();
+        let first = stream.next_id(None).unwrap();
+        stream.add(first, fields("a"));
+        let second = stream.next_id(None).unwrap();
+        assert!(second > first);
+        assert!(matches!(
+            stream.next_id(Some(first)),
+            Err(BlinkError::StreamIdTooSmall(_))
+        ));
+        assert!(Stream::default().next_id(Some(StreamId::MIN)).is_err());
+
+        assert_eq!("5-3".parse::<StreamId>().unwrap(), StreamId { ms: 5, seq: 3 });
+        assert_eq!("5".parse::<StreamId>().unwrap(), StreamId { ms: 5, seq: 0 });
+        assert_eq!(StreamId { ms: 5, seq: 3 }.to_string(), "5-3");
+        assert!("5-x".parse::<StreamId>().is_err());
+    }
+
+    #[test]
+    fn groups_track_pending_entries_and_sizes() {
+        let now = Instant::now();
+        let mut stream = Stream::default();
+        for (ms, value) in [(1, "a"), (2, "b"), (3, "c")] {
+            stream.add(StreamId { ms, seq: 0 }, fields(value));
+        }
+        let id = |ms| StreamId { ms, seq: 0 };
+        stream.create_group("g", StreamId::MIN).unwrap();
+        assert!(stream.create_group("g", StreamId::MIN).is_none());
+        let (handed, added) = stream.deliver("g", "c1", None, 2, now).unwrap();
+        assert_eq!(handed.iter().map(|e| e.id).collect::<Vec<_>>(), [id(1), id(2)]);
+        assert_eq!(added, 2 * (16 + 2));
+        assert_eq!(stream.deliver("g", "c2", None, 10, now).unwrap().0.len(), 1);
+        assert!(!stream.can_deliver("g", "c2", None).unwrap());
+
+        // c1 sees its own pending entries again.
+        let (again, _) = stream.deliver("g", "c1", Some(StreamId::MIN), 10, now).unwrap();
+        assert_eq!(again.len(), 2);
+        let pending = stream.pending("g", now).unwrap();
+        assert_eq!(pending[0].deliveries, 2);
+        assert_eq!(pending[2].consumer, "c2");
+
+        assert_eq!(stream.ack("g", &[id(1), id(9)]).0, 1);
+        assert_eq!(stream.trim(1), (2, 2 * 18 + 16 + 2));
+        assert_eq!(stream.pending("g", now).unwrap().len(), 1);
+        assert_eq!(stream.size(), 18 + 1 + 16 + 18);
+        assert!(matches!(stream.deliver("x", "c1", None, 1, now), Err(BlinkError::NoGroup(_))));
+    }
+}
@@ -0,0 +1,295 @@
+//! Typed cache facade for embedding a `BlinkStorage` in-process.
+//!
+//! `TypedCache<K, V, C>` turns keys into strings with `Display` (plus an
+//! optional prefix) and values into `Bytes` with a `Codec`:
+//!
+//! - `Raw`: `Bytes`, `Vec<u8>` and `String` as-is (always available).
+//! - `Json`: any serde type via `serde_json` (feature `json`, on by default).
+//! - `Bincode`: any serde type via `bincode` (feature `bincode`).
+//!
+//! ```
+//! # #[cfg(feature = "json")]
+//! # fn demo() -> Result<(), blink_store::BlinkError> {
+//! use blink_store::typed::{Json, TypedCache};
+//! use blink_store::MemoryEngine;
+//! use std::sync::Arc;
+//!
+//! let engine = Arc::new(MemoryEngine::new(1 << 20)?);
+//! let users: TypedCache<u64, Vec<String>, _> =
+//!     TypedCache::new(engine, Json).with_prefix("user:");
+//! users.set(&7, &vec!["alice".to_owned()])?;
+//! assert_eq!(users.get(&7)?, Some(vec!["alice".to_owned()]));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::engine::BlinkStorage;
+use crate::error::BlinkError;
+use crate::singleflight::SingleFlight;
+use bytes::Bytes;
+use std::fmt::Display;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Converts values of type `T` to and from stored bytes.
+pub trait Codec<T>: Send + Sync {
+    fn encode(&self, value: &T) -> Result<Bytes, BlinkError>;
+    fn decode(&self, bytes: &Bytes) -> Result<T, BlinkError>;
+}
+
+/// Stores bytes and strings unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Raw;
+
+impl Codec<Bytes> for Raw {
+    fn encode(&self, value: &Bytes) -> Result<Bytes, BlinkError> {
+        Ok(value.clone())
+    }
+
+    fn decode(&self, bytes: &Bytes) -> Result<Bytes, BlinkError> {
+        Ok(bytes.clone())
+    }
+}
+
+impl Codec<Vec<u8>> for Raw {
+    fn encode(&self, value: &Vec<u8>) -> Result<Bytes, BlinkError> {
+        Ok(Bytes::copy_from_slice(value))
+    }
+
+    fn decode(&self, bytes: &Bytes) -> Result<Vec<u8>, BlinkError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Codec<String> for Raw {
+    fn encode(&self, value: &String) -> Result<Bytes, BlinkError> {
+        Ok(Bytes::copy_from_slice(value.as_bytes()))
+    }
+
+    fn decode(&self, bytes: &Bytes) -> Result<String, BlinkError> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| BlinkError::Codec(e.to_string()))
+    }
+}
+
+/// serde values as JSON.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Json {
+    fn encode(&self, value: &T) -> Result<Bytes, BlinkError> {
+        serde_json::to_vec(value)
+            .map(Bytes::from)
+            .map_err(|e| BlinkError::Codec(e.to_string()))
+    }
+
+    fn decode(&self, bytes: &Bytes) -> Result<T, BlinkError> {
+        serde_json::from_slice(bytes).map_err(|e| BlinkError::Codec(e.to_string()))
+    }
+}
+
+/// serde values as bincode (compact, not self-describing).
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Bincode {
+    fn encode(&self, value: &T) -> Result<Bytes, BlinkError> {
+        bincode::serialize(value)
+            .map(Bytes::from)
+            .map_err(|e| BlinkError::Codec(e.to_string()))
+    }
+
+    fn decode(&self, bytes: &Bytes) -> Result<T, BlinkError> {
+        bincode::deserialize(bytes).map_err(|e| BlinkError::Codec(e.to_string()))
+    }
+}
+
+/// Typed view over a `BlinkStorage`. Cheap to share behind an `Arc`.
+pub struct TypedCache<K: ?Sized, V, C> {
+    store: Arc<dyn BlinkStorage>,
+    codec: C,
+    prefix: String,
+    loads: SingleFlight,
+    _types: PhantomData<fn(&K) -> V>,
+}
+
+impl<K, V, C> TypedCache<K, V, C>
+where
+    K: Display + ?Sized,
+    C: Codec<V>,
+{
+    pub fn new(store: Arc<dyn BlinkStorage>, codec: C) -> Self {
+        Self {
+            store,
+            codec,
+            prefix: String::new(),
+            loads: SingleFlight::new(),
+            _types: PhantomData,
+        }
+    }
+
+    /// Prepends `prefix` to every key, so several caches can share a store.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn store(&self) -> &Arc<dyn BlinkStorage> {
+        &self.store
+    }
+
+    fn key(&self, key: &K) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, BlinkError> {
+        match self.store.get(&self.key(key))? {
+            Some(bytes) => self.codec.decode(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, key: &K, value: &V) -> Result<(), BlinkError> {
+        self.store.set(&self.key(key), self.codec.encode(value)?)
+    }
+
+    /// Returns false if the key did not exist.
+    pub fn delete(&self, key: &K) -> Result<bool, BlinkError> {
+        self.store.delete(&self.key(key))
+    }
+
+    /// Returns the cached value, or computes, stores and returns it on a miss.
+    pub fn get_or_insert_with(&self, key: &K, f: impl FnOnce() -> V) -> Result<V, BlinkError> {
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        let value = f();
+        self.set(key, &value)?;
+        Ok(value)
+    }
+
+    /// Returns the cached value, or runs `load` on a miss and caches its result.
+    ///
+    /// Concurrent misses on the same key share one call to `load`: the first
+    /// caller runs it and the rest wait for its result. If the first caller is
+    /// cancelled, a waiting caller runs its own `load` instead. Errors from
+    /// `load` are returned to every waiter and not cached. A value that cannot
+    /// be stored (e.g. `AtCapacity` under `noeviction`) is still returned.
+    pub async fn get_or_load<F, Fut>(&self, key: &K, load: F) -> Result<V, BlinkError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, BlinkError>>,
+    {
+        let key = self.key(key);
+        if let Some(bytes) = self.store.get(&key)? {
+            return self.codec.decode(&bytes);
+        }
+
+        let bytes = self
+            .loads
+            .run(&key, || async {
+                // A load for this key may have finished since our lookup.
+                if let Some(bytes) = self.store.get(&key)? {
+                    return Ok(bytes);
+                }
+                let bytes = self.codec.encode(&load().await?)?;
+                if let Err(e) = self.store.set(&key, bytes.clone()) {
+                    debug!(key = %key, error = %e, action = "typed_cache_store_failed");
+                }
+                Ok(bytes)
+            })
+            .await?;
+        self.codec.decode(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::MemoryEngine;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn engine() -> Arc<dyn BlinkStorage> {
+        Arc::new(MemoryEngine::new(1 << 20).unwrap())
+    }
+
+    #[test]
+    fn raw_codec_and_prefix() {
+        let store = engine();
+        let cache: TypedCache<str, String, _> =
+            TypedCache::new(store.clone(), Raw).with_prefix("name:");
+        cache.set("a", &"alice".to_owned()).unwrap();
+        assert_eq!(cache.get("a").unwrap().as_deref(), Some("alice"));
+        assert_eq!(store.get("name:a").unwrap().as_deref(), Some(&b"alice"[..]));
+
+        store.set("name:bad", Bytes::from_static(&[0xff])).unwrap();
+        assert!(matches!(cache.get("bad"), Err(BlinkError::Codec(_))));
+
+        assert_eq!(
+            cache.get_or_insert_with("b", || "bob".to_owned()).unwrap(),
+            "bob"
+        );
+        assert_eq!(
+            cache.get_or_insert_with("b", || unreachable!()).unwrap(),
+            "bob"
+        );
+        assert!(cache.delete("b").unwrap());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_codec_round_trips() {
+        let cache: TypedCache<u32, (String, Vec<u16>), _> = TypedCache::new(engine(), Json);
+        let value = ("x".to_owned(), vec![1, 2, 3]);
+        cache.set(&1, &value).unwrap();
+        assert_eq!(cache.get(&1).unwrap(), Some(value));
+        assert_eq!(cache.get(&2).unwrap(), None);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_codec_round_trips() {
+        let cache: TypedCache<u32, Option<i64>, _> = TypedCache::new(engine(), Bincode);
+        cache.set(&1, &Some(-5)).unwrap();
+        assert_eq!(cache.get(&1).unwrap(), Some(Some(-5)));
+    }
+
+    #[tokio::test]
+    async fn get_or_load_coalesces_concurrent_misses() {
+        let cache: Arc<TypedCache<str, String, _>> = Arc::new(TypedCache::new(engine(), Raw));
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let loads = loads.clone();
+                tokio::spawn(async move {
+                    cache
+                        .get_or_load("k", || async move {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok("loaded".to_owned())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap(), "loaded");
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.loads.in_flight(), 0);
+
+        let err = cache
+            .get_or_load("other", || async { Err(BlinkError::Internal("db down".into())) })
+            .await;
+        assert!(matches!(err, Err(BlinkError::Internal(_))));
+        assert_eq!(cache.get("other").unwrap(), None);
+    }
+}